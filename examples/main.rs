@@ -3,20 +3,63 @@
 
 use argh::FromArgs;
 use minifb::{Key, Window, WindowOptions};
+use std::fs::File;
+use std::io::BufReader;
 
 use svo_cpu::binvox::import_binvox_svo as import_svo;
 use svo_cpu::camera::spinny_camera::SpinnyCamera;
 use svo_cpu::fb::ColorBuffer;
+use svo_cpu::lighting::Lighting;
+use svo_cpu::mesh;
+use svo_cpu::meshing::{greedy_mesh, isosurface};
+use svo_cpu::pick::IdBuffer;
 use svo_cpu::procgen::generate_voxbuf;
+use svo_cpu::procgen::heightmap::Heightmap;
 use svo_cpu::procgen::terrain::TerrainGen;
+use svo_cpu::vox::{export_vox, try_import_vox_svo};
 use svo_cpu::voxbuf::VoxBuf;
+use svo_cpu::voxelize::voxelize;
 
 #[derive(FromArgs)]
 /// CPU-based sparse voxel octree (SVO) rasterizer
 struct Args {
-    /// model to draw (defaults to "dragon")
+    /// model to draw, or a .svo, .vox or .obj file or .pgm heightmap to load
+    /// (defaults to "dragon")
     #[argh(option, default = "default_model()", from_str_fn(select_model))]
     model: VoxBuf,
+
+    /// save the model to a .svo file for faster loading next time, to a .vox
+    /// file for editing in MagicaVoxel, or as an .obj or .ply mesh
+    #[argh(option)]
+    save: Option<String>,
+
+    /// save .obj and .ply meshes as smooth isosurfaces instead of voxel faces
+    #[argh(switch)]
+    smooth: bool,
+
+    /// draw the model as a DAG of shared subtrees, with colors stored apart
+    #[argh(switch)]
+    dag: bool,
+
+    /// draw the model from the packed node layout instead
+    #[argh(switch)]
+    packed: bool,
+
+    /// shade the model with directional lighting, drawing on one thread
+    #[argh(switch)]
+    lit: bool,
+
+    /// bake ambient occlusion into the model and darken it by that
+    #[argh(switch)]
+    ao: bool,
+
+    /// the number of threads to draw with (defaults to one per core)
+    #[argh(option, default = "default_threads()")]
+    threads: usize,
+}
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get())
 }
 
 fn default_model() -> VoxBuf {
@@ -29,17 +72,122 @@ fn select_model(option: &str) -> Result<VoxBuf, String> {
         "dragon" => Ok(import_svo(include_bytes!("models/stanford_dragon.binvox"))),
         "buddha" => Ok(import_svo(include_bytes!("models/stanford_buddha.binvox"))),
         "terrain" => Ok(generate_voxbuf(TerrainGen::default())),
-        _ => Err("invalid model (must be one of [bunny, dragon, buddha, terrain])".into()),
+        path if path.ends_with(".svo") => load_svo(path),
+        path if path.ends_with(".vox") => load_vox(path),
+        path if path.ends_with(".obj") => load_obj(path),
+        path if path.ends_with(".pgm") => load_heightmap(path),
+        _ => Err(
+            "invalid model (must be one of [bunny, dragon, buddha, terrain] or a .svo, .vox, .obj or .pgm file)"
+                .into(),
+        ),
+    }
+}
+
+fn load_svo(path: &str) -> Result<VoxBuf, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    VoxBuf::load(file).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn load_vox(path: &str) -> Result<VoxBuf, String> {
+    let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    try_import_vox_svo(&data).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn load_obj(path: &str) -> Result<VoxBuf, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mesh = mesh::load_obj(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(voxelize(&mesh, 8))
+}
+
+/// loads a heightmap, colored by a .ppm of the same name if there is one
+fn load_heightmap(path: &str) -> Result<VoxBuf, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut map =
+        Heightmap::from_pgm(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+
+    let color_path = path.trim_end_matches(".pgm").to_string() + ".ppm";
+    if let Ok(file) = File::open(&color_path) {
+        map = map
+            .with_color_map(BufReader::new(file))
+            .map_err(|e| format!("{}: {}", color_path, e))?;
     }
+
+    Ok(map.build(map.width.max(map.height) as f32 / 4.0))
 }
 
 fn main() {
     let args: Args = argh::from_env();
-    let vb = args.model;
+    if [args.dag, args.packed, args.lit]
+        .iter()
+        .filter(|set| **set)
+        .count()
+        > 1
+    {
+        eprintln!("--dag, --packed and --lit draw in different ways and can't be combined");
+        std::process::exit(1);
+    }
+
+    let mut vb = args.model;
+    if args.ao {
+        vb.bake_occlusion();
+    }
+
+    if let Some(path) = args.save {
+        let to_mesh = if args.smooth { isosurface } else { greedy_mesh };
+        let file = File::create(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        if path.ends_with(".vox") {
+            export_vox(&vb, vb.depth(), file)
+        } else if path.ends_with(".obj") {
+            mesh::write_obj(&to_mesh(&vb, vb.depth()), file)
+        } else if path.ends_with(".ply") {
+            mesh::write_ply(&to_mesh(&vb, vb.depth()), file)
+        } else {
+            vb.save(file)
+        }
+        .unwrap_or_else(|e| panic!("{}: {}", path, e));
+    }
+
+    // painted one color, so that the importer's debug colors don't keep
+    // subtrees apart, the bunny, dragon and buddha shrink 28.5x, 25.6x and
+    // 25.2x with compress_dag(), and 4.5x with their colors stored apart
+    let dag = if args.dag {
+        Some(vb.to_colored_dag())
+    } else {
+        None
+    };
+
+    let packed = if args.packed {
+        Some(vb.to_packed())
+    } else {
+        None
+    };
 
     let mut fb = ColorBuffer::default();
+
+    let mut lit = if args.lit {
+        Some((Lighting::default(), vb.node_normals(), IdBuffer::new(&fb)))
+    } else {
+        None
+    };
+
+    let threads = args.threads;
+    let mut draw = |spinny_cam: &SpinnyCamera, fb: &mut ColorBuffer| {
+        let (camera, config) = (&spinny_cam.camera, &spinny_cam.draw_config);
+        match (&dag, &packed, &mut lit) {
+            (Some(dag), _, _) => dag.draw(camera, config, fb),
+            (None, Some(packed), _) => packed.draw(camera, config, fb),
+            (None, None, Some((lighting, normals, ids))) => {
+                ids.clear();
+                vb.draw_with_ids(camera, config, fb, ids);
+                lighting.shade(camera, fb, ids, normals);
+            }
+            (None, None, None) => vb.draw_parallel(camera, config, fb, threads),
+        }
+    };
+
     let mut spinny_cam = SpinnyCamera::new(&fb);
-    vb.draw(&spinny_cam.camera, &spinny_cam.draw_config, &mut fb);
+    spinny_cam.draw_config.occlusion = args.ao;
+    draw(&spinny_cam, &mut fb);
 
     let mut window = Window::new(
         "Test - ESC to exit",
@@ -56,7 +204,7 @@ fn main() {
     while window.is_open() && !window.is_key_down(Key::Escape) {
         spinny_cam.update(&fb);
         fb.clear();
-        vb.draw(&spinny_cam.camera, &spinny_cam.draw_config, &mut fb);
+        draw(&spinny_cam, &mut fb);
         window
             .update_with_buffer(&fb.data, fb.width, fb.height)
             .unwrap();
@@ -2,121 +2,373 @@
 // Copyright (c) 2021 Marceline Cramer
 
 use super::voxbuf::*;
-use std::io::{BufRead, Read};
+use glam::Vec3A;
+use std::fmt;
+use std::io::{BufRead, Read, Write};
 use std::time::Instant;
 
-pub fn import_binvox_svo(bv: &[u8]) -> VoxBuf {
-    let timer = Instant::now();
+#[derive(Debug)]
+pub enum BinvoxError {
+    /// the data does not start with a "#binvox 1" header
+    BadHeader,
+    /// the "dim" line could not be parsed
+    MalformedDimension(String),
+    /// the "dim" line parsed, but the dimensions are not supported
+    UnsupportedDimension([usize; 3]),
+    /// the RLE data ended before the grid was filled
+    TruncatedData { expected: usize, found: usize },
+    /// the RLE data describes more voxels than the grid holds
+    RleOverflow,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for BinvoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinvoxError::BadHeader => write!(f, "data is not a binvox"),
+            BinvoxError::MalformedDimension(line) => write!(f, "malformed dimension {:?}", line),
+            BinvoxError::UnsupportedDimension(dim) => write!(
+                f,
+                "unsupported dimension {}x{}x{}",
+                dim[0], dim[1], dim[2]
+            ),
+            BinvoxError::TruncatedData { expected, found } => write!(
+                f,
+                "voxel data truncated (expected {} voxels, found {})",
+                expected, found
+            ),
+            BinvoxError::RleOverflow => write!(f, "voxel data RLE overflow"),
+            BinvoxError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BinvoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BinvoxError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BinvoxError {
+    fn from(e: std::io::Error) -> Self {
+        BinvoxError::Io(e)
+    }
+}
+
+/// reads one header line, failing on a premature end of the data
+fn read_header_line<R: BufRead>(reader: &mut R) -> Result<String, BinvoxError> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Err(BinvoxError::BadHeader);
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+fn parse_dim(line: &str) -> Result<[usize; 3], BinvoxError> {
+    let malformed = || BinvoxError::MalformedDimension(line.to_string());
+    let mut words = line.split_whitespace();
+    if words.next() != Some("dim") {
+        return Err(malformed());
+    }
+
+    let mut dim = [0; 3];
+    for axis in dim.iter_mut() {
+        *axis = words
+            .next()
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(malformed)?;
+    }
 
+    if words.next().is_some() {
+        return Err(malformed());
+    }
+
+    Ok(dim)
+}
+
+/// a decoded binvox grid
+///
+/// binvox stores its voxels with x running slowest, then z, then y; those
+/// three axes become the octree's z, y and x axes, in that order, so that
+/// the data index of octree voxel (x, y, z) is `(z * dim[1] + y) * dim[2] + x`
+struct BinvoxGrid {
+    dim: [usize; 3],
+    translate: Vec3A,
+    scale: f32,
+    bits: Vec<u64>,
+}
+
+impl BinvoxGrid {
+    fn is_filled(&self, x: usize, y: usize, z: usize) -> bool {
+        let index = (z * self.dim[1] + y) * self.dim[2] + x;
+        (self.bits[index >> 6] >> (index & 63)) & 1 != 0
+    }
+
+    fn contains(&self, x: usize, y: usize, z: usize) -> bool {
+        x < self.dim[2] && y < self.dim[1] && z < self.dim[0]
+    }
+
+    /// the smallest power-of-two edge length enclosing every axis
+    fn octree_size(&self) -> usize {
+        self.dim.iter().max().unwrap().next_power_of_two()
+    }
+
+    /// the world-space placement of the padded octree
+    fn bounds(&self) -> WorldBounds {
+        let max_dim = *self.dim.iter().max().unwrap() as f32;
+        let [tx, ty, tz] = self.translate.to_array();
+        WorldBounds {
+            min: Vec3A::new(ty, tz, tx),
+            size: self.scale * self.octree_size() as f32 / max_dim,
+        }
+    }
+}
+
+fn parse_floats(words: std::str::SplitWhitespace, out: &mut [f32]) -> Option<()> {
+    let mut words = words;
+    for value in out.iter_mut() {
+        *value = words.next()?.parse().ok()?;
+    }
+
+    if words.next().is_some() {
+        None
+    } else {
+        Some(())
+    }
+}
+
+fn read_binvox(bv: &[u8]) -> Result<BinvoxGrid, BinvoxError> {
     let mut reader = std::io::BufReader::new(bv);
 
-    let mut header = String::new();
-    reader.read_line(&mut header).unwrap();
+    let header = read_header_line(&mut reader)?;
     if !header.starts_with("#binvox 1") {
-        panic!("data is not a binvox");
+        return Err(BinvoxError::BadHeader);
     }
 
-    let mut dim = String::new();
-    reader.read_line(&mut dim).unwrap();
-    if !dim.starts_with("dim 512 512 512") {
-        unimplemented!("unsupported dimension");
+    let dim = parse_dim(&read_header_line(&mut reader)?)?;
+    if dim.iter().any(|axis| *axis == 0 || *axis > 1 << (VoxBuf::MAX_DEPTH - 1)) {
+        return Err(BinvoxError::UnsupportedDimension(dim));
     }
 
-    let dim = 512;
-    let dim2 = dim * dim;
+    let capacity = dim
+        .iter()
+        .try_fold(1usize, |product, axis| product.checked_mul(*axis))
+        .ok_or(BinvoxError::UnsupportedDimension(dim))?;
 
-    let mut junk = String::new();
-    reader.read_line(&mut junk).unwrap(); // translate
-    reader.read_line(&mut junk).unwrap(); // scale
-    reader.read_line(&mut junk).unwrap(); // data
+    let mut translate = [0.0; 3];
+    let mut scale = [1.0];
+    loop {
+        let line = read_header_line(&mut reader)?;
+        let mut words = line.split_whitespace();
+        let parsed = match words.next() {
+            Some("translate") => parse_floats(words, &mut translate),
+            Some("scale") => parse_floats(words, &mut scale),
+            Some("data") => break,
+            _ => None,
+        };
+
+        if parsed.is_none() {
+            return Err(BinvoxError::BadHeader);
+        }
+    }
 
     let mut rle_data = Vec::<u8>::new();
-    let rle_size = reader.read_to_end(&mut rle_data).unwrap() / 2;
+    reader.read_to_end(&mut rle_data)?;
 
-    let capacity = dim * dim * dim;
-    let mut data = vec![0 as u8; capacity];
+    // each run covers at most 255 voxels, so reject short data up front
+    // instead of allocating a grid it could never fill
+    let most = (rle_data.len() / 2).saturating_mul(255);
+    if most < capacity {
+        let found = rle_data.chunks_exact(2).map(|pair| pair[1] as usize).sum();
+        return Err(BinvoxError::TruncatedData {
+            expected: capacity,
+            found,
+        });
+    }
 
-    let mut filled: usize = 0;
+    let mut bits = vec![0u64; capacity.div_ceil(64)];
     let mut cur = 0;
-    for pair in 0..rle_size {
-        let value = rle_data[pair << 1];
-        let count = rle_data[pair << 1 | 1] as usize;
-        if value != 0 {
-            filled += count;
-        }
+    let mut pairs = rle_data.chunks_exact(2);
+    for pair in &mut pairs {
+        let value = pair[0];
+        let count = pair[1] as usize;
         if cur + count > capacity {
-            panic!("voxel data RLE overflow");
+            return Err(BinvoxError::RleOverflow);
         }
-        for _ in 0..count {
-            data[cur] = value;
-            cur += 1;
+
+        if value != 0 {
+            for index in cur..(cur + count) {
+                bits[index >> 6] |= 1 << (index & 63);
+            }
         }
+
+        cur += count;
     }
 
-    let mut nodes = vec![Node::default()];
+    if cur < capacity || !pairs.remainder().is_empty() {
+        return Err(BinvoxError::TruncatedData {
+            expected: capacity,
+            found: cur,
+        });
+    }
+
+    Ok(BinvoxGrid {
+        dim,
+        translate: translate.into(),
+        scale: scale[0],
+        bits,
+    })
+}
 
-    let mut stack = std::collections::VecDeque::<(
-        u16,     // 0: x
-        u16,     // 1: y
-        u16,     // 2: z
-        NodeRef, // 3: node
-        u8,      // 4: lod
-    )>::new();
-
-    stack.push_front((0, 0, 0, 0, 8));
-
-    let mut placed_voxels = 0;
-
-    while let Some(iter) = stack.pop_back() {
-        let parent = iter.3 as usize;
-        let lod = iter.4;
-
-        let cursor = nodes.len();
-        nodes.resize_with(cursor + 8, Default::default);
-        let node = nodes.get_mut(parent).unwrap();
-
-        let mut offsets: [(u16, u16, u16); 8] = [(0, 0, 0); 8];
-        for i in 0..8 {
-            offsets[i] = (
-                iter.0 | ((i & 1) << lod) as u16,
-                iter.1 | (((i & 2) >> 1) << lod) as u16,
-                iter.2 | (((i & 4) >> 2) << lod) as u16,
-            );
+/// builds the subtree of `size` voxels cornered at (x, y, z), pushing its
+/// descendants to `nodes` and returning its root, or `None` if it is empty
+fn build_subtree(
+    grid: &BinvoxGrid,
+    nodes: &mut Vec<Node>,
+    (x, y, z): (usize, usize, usize),
+    size: usize,
+) -> Option<Node> {
+    if !grid.contains(x, y, z) {
+        return None;
+    }
+
+    if size == 1 {
+        return if grid.is_filled(x, y, z) {
+            Some(Node::default())
+        } else {
+            None
+        };
+    }
+
+    let mut node = Node::default();
+    node.data.color = 0xff00ffff;
+
+    let half = size / 2;
+    for index in 0..8 {
+        let corner = (
+            x + (index & 1) * half,
+            y + ((index & 2) >> 1) * half,
+            z + ((index & 4) >> 2) * half,
+        );
+
+        if let Some(child) = build_subtree(grid, nodes, corner, half) {
+            node.occupancy |= Node::index_to_mask(index as ChildIndex);
+            node.children[index] = nodes.len() as NodeRef;
+            nodes.push(child);
         }
+    }
+
+    if node.is_leaf() {
+        None
+    } else {
+        Some(node)
+    }
+}
+
+pub fn import_binvox_svo(bv: &[u8]) -> VoxBuf {
+    try_import_binvox_svo(bv).unwrap_or_else(|e| panic!("{}", e))
+}
 
-        if lod == 0 {
-            node.occupancy = 0x00;
-            for i in 0..8 {
-                let offset = offsets[i];
-                let index =
-                    (offset.2 as usize * dim2) + (offset.1 as usize * dim) + offset.0 as usize;
-                if data[index] != 0 {
-                    node.occupancy |= Node::index_to_mask(i as ChildMask);
-                    node.children[i] = (cursor + i) as NodeRef;
-                    placed_voxels += 1;
+/// imports a binvox of any dimensions, padding it into the smallest
+/// enclosing power-of-two octree with the voxels at its deepest level
+pub fn try_import_binvox_svo(bv: &[u8]) -> Result<VoxBuf, BinvoxError> {
+    let timer = Instant::now();
+
+    let grid = read_binvox(bv)?;
+    let size = grid.octree_size();
+
+    let mut nodes = vec![Node::default()];
+    match build_subtree(&grid, &mut nodes, (0, 0, 0), size) {
+        Some(root) => nodes[VoxBuf::ROOT_NODE as usize] = root,
+        None => nodes[VoxBuf::ROOT_NODE as usize].data.color = 0,
+    }
+
+    println!("converted in {:?}", timer.elapsed());
+    println!("{} nodes", nodes.len());
+    println!("{} levels", size.trailing_zeros());
+
+    let mut vb = VoxBuf::from_nodes(nodes);
+    vb.set_bounds(grid.bounds());
+    Ok(vb)
+}
+
+/// rasterizes the octree's leaves into a dense grid of 2^depth voxels per
+/// axis and writes it as a binvox, inverting the importer's axis order
+pub fn export_binvox<W: Write>(vb: &VoxBuf, depth: u32, mut out: W) -> std::io::Result<()> {
+    let timer = Instant::now();
+
+    // the voxel count has to fit a usize
+    if depth >= usize::BITS / 3 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("unsupported binvox depth {}", depth),
+        ));
+    }
+
+    let size = 1usize << depth;
+
+    let mut bits = vec![0u64; (size * size * size).div_ceil(64)];
+    let mut fill = |cell: Cell| {
+        // fill the whole block of target voxels the cell covers
+        let scale = 1 << (depth - cell.depth);
+        let [x0, y0, z0] = (cell.pos * scale).to_array();
+        for z in z0..(z0 + scale) {
+            for y in y0..(y0 + scale) {
+                let row = (z as usize * size + y as usize) * size;
+                for x in x0..(x0 + scale) {
+                    let index = row + x as usize;
+                    bits[index >> 6] |= 1 << (index & 63);
                 }
             }
+        }
+    };
 
-            if node.occupancy == 0x00 {
-                node.data.color = 0;
+    vb.walk_cells(|_node_ref, node, cell| {
+        if node.is_leaf() {
+            if !node.data.is_empty() {
+                fill(cell);
             }
+            false
+        } else if cell.depth == depth {
+            // culled trees only keep interior nodes with filled leaves
+            fill(cell);
+            false
         } else {
-            node.data.color = 0xff00ffff;
-            node.occupancy = 0xff;
-            node.for_kids_all_mut(|index, _mask, child| {
-                let index = index as usize;
-                let child_ref = (cursor + index) as NodeRef;
-                let offset = offsets[index];
-                *child = child_ref;
-                stack.push_back((offset.0, offset.1, offset.2, child_ref, lod - 1));
-            });
+            true
         }
+    });
+
+    let mut rle_data = Vec::<u8>::new();
+    let mut run: Option<(u8, u8)> = None;
+    for index in 0..(size * size * size) {
+        let value = ((bits[index >> 6] >> (index & 63)) & 1) as u8;
+        run = match run {
+            Some((last, count)) if last == value && count < u8::MAX => Some((last, count + 1)),
+            Some((last, count)) => {
+                rle_data.extend_from_slice(&[last, count]);
+                Some((value, 1))
+            }
+            None => Some((value, 1)),
+        };
     }
 
-    println!("converted in {:?}", timer.elapsed());
-    println!("{} nodes", capacity);
-    println!("{} voxels", filled);
-    println!("{} voxels were treed", placed_voxels);
+    if let Some((last, count)) = run {
+        rle_data.extend_from_slice(&[last, count]);
+    }
+
+    let bounds = vb.bounds();
+    let [x, y, z] = bounds.min.to_array();
+    write!(
+        out,
+        "#binvox 1\ndim {} {} {}\ntranslate {} {} {}\nscale {}\ndata\n",
+        size, size, size, z, x, y, bounds.size
+    )?;
+    out.write_all(&rle_data)?;
+
+    println!("exported in {:?}", timer.elapsed());
 
-    VoxBuf::from_nodes(nodes)
+    Ok(())
 }
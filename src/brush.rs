@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! volumetric sculpting brushes, given as signed distance functions
+//!
+//! brushes are applied top-down: a cell whose bounding sphere is entirely
+//! inside of the brush is edited whole, a cell entirely outside of it is
+//! skipped, and only the cells the brush's surface passes through are
+//! subdivided, down to the brush's depth.
+
+use super::edit::VoxelEdit;
+use super::voxbuf::*;
+use glam::Vec3A;
+use std::time::Instant;
+
+/// a shape given by its signed distance in world space, negative inside
+///
+/// the distance may be underestimated but never overestimated, or cells
+/// near the surface may be taken as entirely inside or outside
+pub trait Brush {
+    fn distance(&self, pos: Vec3A) -> f32;
+}
+
+impl<F> Brush for F
+where
+    F: Fn(Vec3A) -> f32,
+{
+    fn distance(&self, pos: Vec3A) -> f32 {
+        self(pos)
+    }
+}
+
+pub struct Sphere {
+    pub center: Vec3A,
+    pub radius: f32,
+}
+
+impl Brush for Sphere {
+    fn distance(&self, pos: Vec3A) -> f32 {
+        (pos - self.center).length() - self.radius
+    }
+}
+
+/// an axis-aligned box
+pub struct AxisBox {
+    pub min: Vec3A,
+    pub max: Vec3A,
+}
+
+impl Brush for AxisBox {
+    fn distance(&self, pos: Vec3A) -> f32 {
+        let center = (self.min + self.max) * 0.5;
+        let half_size = (self.max - self.min) * 0.5;
+        let q = (pos - center).abs() - half_size;
+        q.max(Vec3A::ZERO).length() + q.max_element().min(0.0)
+    }
+}
+
+/// the points within `radius` of the segment from `a` to `b`
+pub struct Capsule {
+    pub a: Vec3A,
+    pub b: Vec3A,
+    pub radius: f32,
+}
+
+impl Brush for Capsule {
+    fn distance(&self, pos: Vec3A) -> f32 {
+        let ab = self.b - self.a;
+        let t = ((pos - self.a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+        (pos - (self.a + ab * t)).length() - self.radius
+    }
+}
+
+/// a flat-capped cylinder around the segment from `a` to `b`
+pub struct Cylinder {
+    pub a: Vec3A,
+    pub b: Vec3A,
+    pub radius: f32,
+}
+
+impl Brush for Cylinder {
+    fn distance(&self, pos: Vec3A) -> f32 {
+        let axis = self.b - self.a;
+        let length = axis.length();
+        let axis = axis / length.max(f32::EPSILON);
+
+        // distances from the side and from the caps' planes
+        let along = (pos - (self.a + self.b) * 0.5).dot(axis);
+        let across = (pos - self.a - axis * (pos - self.a).dot(axis)).length();
+        let side = across - self.radius;
+        let cap = along.abs() - length * 0.5;
+
+        let outside = Vec3A::new(side.max(0.0), cap.max(0.0), 0.0).length();
+        outside + side.max(cap).min(0.0)
+    }
+}
+
+impl VoxBuf {
+    /// applies `edit` to the voxels of the 2^depth grid whose centers are
+    /// inside of the brush
+    ///
+    /// cells entirely inside of the brush are edited whole, so they stay as
+    /// coarse as the brush allows
+    pub fn apply_brush<B: Brush + ?Sized>(&mut self, brush: &B, edit: VoxelEdit, depth: u32) {
+        assert!(depth < Self::MAX_DEPTH, "unsupported depth {}", depth);
+        let timer = Instant::now();
+
+        let edit = match edit {
+            VoxelEdit::Set(data) if data.is_empty() => VoxelEdit::Clear,
+            edit => edit,
+        };
+
+        let mut edited = 0;
+        self.brush_cell(brush, edit, depth, Cell::ROOT, &mut edited);
+
+        println!("brushed {} cells in {:?}", edited, timer.elapsed());
+    }
+
+    fn brush_cell<B: Brush + ?Sized>(
+        &mut self,
+        brush: &B,
+        edit: VoxelEdit,
+        depth: u32,
+        cell: Cell,
+        edited: &mut usize,
+    ) {
+        // the distance from the cell's center to its corners
+        let bounds = self.bounds;
+        let radius = cell.half_size() * bounds.size * 0.5 * 3f32.sqrt();
+        let distance = brush.distance(bounds.to_world(cell.center()));
+        if distance > radius {
+            return;
+        }
+
+        // only adding voxels has anything to do in empty space
+        let adds = matches!(edit, VoxelEdit::Set(_));
+        if !adds && self.is_cell_empty(cell) {
+            return;
+        }
+
+        if distance <= -radius || (cell.depth == depth && distance <= 0.0) {
+            self.edit_voxel(cell, edit);
+            *edited += 1;
+        } else if cell.depth < depth {
+            for index in 0..8 {
+                self.brush_cell(brush, edit, depth, cell.child(index), edited);
+            }
+        }
+    }
+}
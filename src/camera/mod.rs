@@ -1,11 +1,16 @@
 // SPDX-License-Identifier: MIT
 // Copyright (c) 2021 Marceline Cramer
 
-use crate::fb::ColorBuffer;
-use glam::{Mat4, Vec3A, Vec4};
+use crate::fb::Canvas;
+use crate::voxbuf::{Node, Payload};
+use glam::{Mat4, Vec2, Vec3A, Vec4};
 
 pub mod spinny_camera;
 
+/// a drawn voxel's radius relative to its node's half-size, as the drawing
+/// walks pass it (see VoxBufView::fast_walk())
+const RADIUS_SCALE: f32 = 1.73 / 2.0;
+
 pub struct Camera {
     pub eye: Vec3A,
     pub vp: Mat4,
@@ -15,68 +20,166 @@ pub struct DrawConfig {
     pub min_rect: f32,
     pub max_rect: f32,
     pub max_test: usize,
+    /// whether to darken colors by their baked occlusion
+    pub occlusion: bool,
 }
 
 impl Camera {
     pub fn project_voxel(&self, center: &Vec4) -> Vec3A {
+        self.project_with_depth(center).0
+    }
+
+    /// the projected voxel, with the linear eye-space depth of its center
+    ///
+    /// the whole splat is drawn at this depth, although its front is up to
+    /// the voxel's radius nearer
+    pub fn project_with_depth(&self, center: &Vec4) -> (Vec3A, f32) {
         let mut vertex = center.clone();
         vertex.w = 1.0;
         let mut frag = self.vp * vertex;
+        // a perspective projection's w is the depth along the view axis
+        let depth = frag.w.abs();
         frag.z = -center.w;
-        (frag / frag.w).into()
+        ((frag / frag.w).into(), depth)
+    }
+
+    pub fn draw_voxel<C: Canvas>(
+        &self,
+        fb: &mut C,
+        c: &DrawConfig,
+        is_leaf: bool,
+        center: &Vec4,
+        data: &Payload,
+    ) -> bool {
+        self.splat_voxel(fb, c, is_leaf, center, data, |fb, projected| {
+            Self::test_rect(c.max_test, fb, projected)
+        })
+    }
+
+    /// draws like draw_voxel(), but only culls nodes whose descendants can't
+    /// draw anything, so that culling never changes the image and any part
+    /// of it can be drawn on its own (see VoxBufView::draw_parallel())
+    pub fn draw_voxel_exact<C: Canvas>(
+        &self,
+        fb: &mut C,
+        c: &DrawConfig,
+        is_leaf: bool,
+        center: &Vec4,
+        data: &Payload,
+    ) -> bool {
+        self.splat_voxel(fb, c, is_leaf, center, data, |fb, _projected| {
+            match self.reach_bounds(fb, center) {
+                Some(bounds) => Self::test_bounds(c.max_test, fb, bounds),
+                None => false,
+            }
+        })
     }
 
-    pub fn draw_voxel(
+    /// draws a voxel, or for a branch too large to draw, returns whether to
+    /// walk into it as `test` says
+    fn splat_voxel<C: Canvas>(
         &self,
-        fb: &mut ColorBuffer,
+        fb: &mut C,
         c: &DrawConfig,
         is_leaf: bool,
         center: &Vec4,
-        color: u32,
+        data: &Payload,
+        test: impl FnOnce(&mut C, &Vec3A) -> bool,
     ) -> bool {
-        let projected = self.project_voxel(&center);
+        let color = if c.occlusion {
+            data.occluded_color()
+        } else {
+            data.color
+        };
+
+        let (projected, depth) = self.project_with_depth(&center);
         if !is_leaf {
             if projected.z > c.max_rect {
-                Self::test_rect(c.max_test, fb, &projected)
+                test(fb, &projected)
             } else if projected.z > c.min_rect {
-                Self::draw_rect(fb, &projected, color);
+                Self::draw_rect(fb, &projected, color, depth);
                 false
             } else {
-                Self::draw_point(fb, &projected, color);
+                Self::draw_point(fb, &projected, color, depth);
                 false
             }
         } else {
             if projected.z < c.min_rect {
-                Self::draw_point(fb, &projected, color);
+                Self::draw_point(fb, &projected, color, depth);
                 false
             } else {
-                Self::draw_rect(fb, &projected, color);
+                Self::draw_rect(fb, &projected, color, depth);
                 false
             }
         }
     }
 
-    pub fn test_rect(max_test: usize, fb: &mut ColorBuffer, projected: &Vec3A) -> bool {
-        if let Some(bounds) = fb.point_bounds(projected) {
-            let area = (bounds.2 - bounds.0) * (bounds.3 - bounds.1);
-            if area < max_test {
-                fb.test_rect(bounds)
-            } else {
-                true
+    /// the pixels that anything under a node can be drawn to, or None if it
+    /// is all off-screen
+    ///
+    /// the node's descendants are centered within its cube, so they project
+    /// within its corners' projections, as long as the cube is entirely in
+    /// front of the eye. none of them is drawn larger than a child, nor
+    /// nearer than the cube's nearest corner.
+    fn reach_bounds<C: Canvas>(
+        &self,
+        fb: &C,
+        center: &Vec4,
+    ) -> Option<(usize, usize, usize, usize)> {
+        let half_size = center.w / RADIUS_SCALE;
+        let mut min = Vec2::splat(f32::INFINITY);
+        let mut max = Vec2::splat(f32::NEG_INFINITY);
+        let mut nearest = f32::INFINITY;
+        for index in 0..8 {
+            let corner = Vec3A::from(center.truncate()) + Node::index_offset(index, half_size);
+            let frag = self.vp * corner.extend(1.0);
+
+            // a perspective projection's w is negative in front of the eye
+            if frag.w >= 0.0 {
+                let everywhere = Vec2::splat(f32::INFINITY);
+                return fb.box_bounds(-everywhere, everywhere, 0.0);
             }
+
+            let xy = Vec2::new(frag.x, frag.y) / frag.w;
+            min = min.min(xy);
+            max = max.max(xy);
+            nearest = nearest.min(-frag.w);
+        }
+
+        fb.box_bounds(min, max, center.w * 0.5 / nearest)
+    }
+
+    pub fn test_rect<C: Canvas>(max_test: usize, fb: &mut C, projected: &Vec3A) -> bool {
+        if let Some(bounds) = fb.point_bounds(projected) {
+            Self::test_bounds(max_test, fb, bounds)
         } else {
             false
         }
     }
 
-    pub fn draw_rect(fb: &mut ColorBuffer, projected: &Vec3A, color: u32) {
+    /// whether any pixel in the bounds is unfilled, or they are too large to
+    /// be worth testing
+    fn test_bounds<C: Canvas>(
+        max_test: usize,
+        fb: &mut C,
+        bounds: (usize, usize, usize, usize),
+    ) -> bool {
+        let area = (bounds.2 - bounds.0) * (bounds.3 - bounds.1);
+        if area < max_test {
+            fb.test_rect(bounds)
+        } else {
+            true
+        }
+    }
+
+    pub fn draw_rect<C: Canvas>(fb: &mut C, projected: &Vec3A, color: u32, depth: f32) {
         if let Some(bounds) = fb.point_bounds(projected) {
-            fb.draw_rect(bounds, color);
+            fb.draw_rect(bounds, color, depth);
         }
     }
 
-    pub fn draw_point(fb: &mut ColorBuffer, projected: &Vec3A, color: u32) {
+    pub fn draw_point<C: Canvas>(fb: &mut C, projected: &Vec3A, color: u32, depth: f32) {
         let xy = fb.frag_xy(projected);
-        fb.draw_point(xy, color);
+        fb.draw_point(xy, color, depth);
     }
 }
@@ -24,6 +24,7 @@ impl SpinnyCamera {
             min_rect: 0.5 / fb.px,
             max_rect: 6.0 / fb.px,
             max_test: 1024,
+            occlusion: false,
         };
 
         Self {
@@ -0,0 +1,286 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! boolean operations between octrees
+//!
+//! both trees are walked together, cell for cell in their own [-1, 1]
+//! cubes, so they have to share bounds, which the result keeps. a filled
+//! leaf is treated as a solid subtree reaching as deep as the other tree,
+//! and where the result is just one side's subtree, it is copied over
+//! without being walked.
+
+use super::voxbuf::*;
+use std::fmt;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub enum CsgError {
+    /// the trees are placed differently in world space, so their cells
+    /// don't line up
+    BoundsMismatch {
+        left: WorldBounds,
+        right: WorldBounds,
+    },
+}
+
+impl fmt::Display for CsgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsgError::BoundsMismatch { left, right } => {
+                write!(f, "trees have different bounds: {:?} and {:?}", left, right)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CsgError {}
+
+/// how to color cells where both trees are filled
+#[derive(Clone, Copy)]
+pub enum ColorRule {
+    Left,
+    Right,
+    /// the average of both colors and occlusions
+    Blend,
+    Custom(fn(Payload, Payload) -> Payload),
+}
+
+impl ColorRule {
+    fn resolve(&self, left: Payload, right: Payload) -> Payload {
+        match self {
+            ColorRule::Left => left,
+            ColorRule::Right => right,
+            ColorRule::Blend => {
+                let occlusion = (left.occlusion as u32 + right.occlusion as u32).div_ceil(2);
+                let [left, right] = [left.color, right.color].map(u32::to_be_bytes);
+                let mut mixed = [0; 4];
+                for (channel, mixed) in mixed.iter_mut().enumerate() {
+                    *mixed = (left[channel] as u32 + right[channel] as u32).div_ceil(2) as u8;
+                }
+                Payload {
+                    color: u32::from_be_bytes(mixed),
+                    occlusion: occlusion as u8,
+                }
+            }
+            ColorRule::Custom(resolve) => resolve(left, right),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+    Xor,
+}
+
+impl CsgOp {
+    fn contains(&self, left: bool, right: bool) -> bool {
+        match self {
+            CsgOp::Union => left || right,
+            CsgOp::Intersection => left && right,
+            CsgOp::Difference => left && !right,
+            CsgOp::Xor => left != right,
+        }
+    }
+}
+
+/// one tree's contents over a cell
+#[derive(Clone, Copy)]
+enum Side<'a> {
+    Empty,
+    Solid(Payload),
+    Branch(&'a Node),
+}
+
+impl<'a> Side<'a> {
+    fn of(node: &'a Node) -> Self {
+        if !node.is_leaf() {
+            Side::Branch(node)
+        } else if node.data.is_empty() {
+            Side::Empty
+        } else {
+            Side::Solid(node.data)
+        }
+    }
+
+    /// the contents of a child cell, where leaves are solid all the way down
+    fn child(&self, nodes: &'a [Node], index: ChildIndex) -> Self {
+        match self {
+            Side::Branch(node) if node.is_occupied(Node::index_to_mask(index)) => {
+                Side::of(&nodes[node.get_child(index) as usize])
+            }
+            Side::Branch(_) => Side::Empty,
+            side => *side,
+        }
+    }
+}
+
+/// the result over a cell, before it is pushed to the output's nodes
+enum Output {
+    Empty,
+    Leaf(Payload),
+    Branch(Node),
+}
+
+struct Csg<'a> {
+    left: &'a [Node],
+    right: &'a [Node],
+    op: CsgOp,
+    rule: ColorRule,
+    nodes: Vec<Node>,
+}
+
+impl<'a> Csg<'a> {
+    /// copies a subtree to the output, returning its root
+    fn copy(&mut self, source: &'a [Node], node: &Node) -> Node {
+        let mut copy = *node;
+        node.for_kids(|index, child| {
+            let child = self.copy(source, &source[*child as usize]);
+            copy.children[index as usize] = self.nodes.len() as NodeRef;
+            self.nodes.push(child);
+        });
+        copy
+    }
+
+    fn combine(&mut self, left: Side<'a>, right: Side<'a>) -> Output {
+        let filled = |side: &Side| !matches!(side, Side::Empty);
+        let (has_left, has_right) = (filled(&left), filled(&right));
+
+        // cells where one side decides the result on its own
+        match (left, right, self.op) {
+            (Side::Branch(node), Side::Empty, CsgOp::Union)
+            | (Side::Branch(node), Side::Empty, CsgOp::Difference)
+            | (Side::Branch(node), Side::Empty, CsgOp::Xor) => {
+                return Output::Branch(self.copy(self.left, node));
+            }
+            (Side::Empty, Side::Branch(node), CsgOp::Union)
+            | (Side::Empty, Side::Branch(node), CsgOp::Xor) => {
+                return Output::Branch(self.copy(self.right, node));
+            }
+            (Side::Empty, _, CsgOp::Intersection)
+            | (_, Side::Empty, CsgOp::Intersection)
+            | (Side::Empty, _, CsgOp::Difference)
+            | (_, Side::Solid(_), CsgOp::Difference) => return Output::Empty,
+            _ => {}
+        }
+
+        match (left, right) {
+            (Side::Branch(_), _) | (_, Side::Branch(_)) => {}
+            _ if !self.op.contains(has_left, has_right) => return Output::Empty,
+            (Side::Solid(left), Side::Solid(right)) => {
+                return Output::Leaf(self.rule.resolve(left, right))
+            }
+            (Side::Solid(data), _) | (_, Side::Solid(data)) => return Output::Leaf(data),
+            _ => return Output::Empty,
+        }
+
+        let children: Vec<Output> = (0..8)
+            .map(|index| {
+                let left = left.child(self.left, index);
+                let right = right.child(self.right, index);
+                self.combine(left, right)
+            })
+            .collect();
+
+        // collapse children that are all empty or all the same leaf
+        match children.as_slice() {
+            [Output::Leaf(first), rest @ ..]
+                if rest
+                    .iter()
+                    .all(|child| matches!(child, Output::Leaf(data) if data == first)) =>
+            {
+                return Output::Leaf(*first);
+            }
+            _ if children.iter().all(|child| matches!(child, Output::Empty)) => {
+                return Output::Empty;
+            }
+            _ => {}
+        }
+
+        let mut node = Node::default();
+        node.data.color = TreeBuilder::INTERIOR_COLOR;
+        for (index, child) in children.into_iter().enumerate() {
+            let child = match child {
+                Output::Empty => continue,
+                Output::Leaf(data) => Node {
+                    data,
+                    ..Default::default()
+                },
+                Output::Branch(node) => node,
+            };
+
+            node.occupancy |= Node::index_to_mask(index as ChildIndex);
+            node.children[index] = self.nodes.len() as NodeRef;
+            self.nodes.push(child);
+        }
+
+        Output::Branch(node)
+    }
+}
+
+impl VoxBuf {
+    fn csg(&self, other: &VoxBuf, op: CsgOp, rule: ColorRule) -> Result<VoxBuf, CsgError> {
+        if self.bounds != other.bounds {
+            return Err(CsgError::BoundsMismatch {
+                left: self.bounds,
+                right: other.bounds,
+            });
+        }
+
+        let timer = Instant::now();
+
+        let mut csg = Csg {
+            left: &self.nodes,
+            right: &other.nodes,
+            op,
+            rule,
+            nodes: vec![Node::default()],
+        };
+
+        let left = Side::of(&self.nodes[VoxBuf::ROOT_NODE as usize]);
+        let right = Side::of(&other.nodes[VoxBuf::ROOT_NODE as usize]);
+        let root = match csg.combine(left, right) {
+            Output::Empty => {
+                let mut root = Node::default();
+                root.data.color = 0;
+                root
+            }
+            Output::Leaf(data) => Node {
+                data,
+                ..Default::default()
+            },
+            Output::Branch(node) => node,
+        };
+
+        let mut nodes = csg.nodes;
+        nodes[VoxBuf::ROOT_NODE as usize] = root;
+
+        println!("{:?} in {:?}", op, timer.elapsed());
+
+        let mut vb = VoxBuf::from_colored_nodes(nodes);
+        vb.set_bounds(self.bounds);
+        Ok(vb)
+    }
+
+    /// the cells filled in either tree, colored by `rule` where both are
+    pub fn union(&self, other: &VoxBuf, rule: ColorRule) -> Result<VoxBuf, CsgError> {
+        self.csg(other, CsgOp::Union, rule)
+    }
+
+    /// the cells filled in both trees, colored by `rule`
+    pub fn intersection(&self, other: &VoxBuf, rule: ColorRule) -> Result<VoxBuf, CsgError> {
+        self.csg(other, CsgOp::Intersection, rule)
+    }
+
+    /// the cells of this tree that are not filled in `other`
+    pub fn difference(&self, other: &VoxBuf) -> Result<VoxBuf, CsgError> {
+        self.csg(other, CsgOp::Difference, ColorRule::Left)
+    }
+
+    /// the cells filled in exactly one of the trees
+    pub fn xor(&self, other: &VoxBuf) -> Result<VoxBuf, CsgError> {
+        self.csg(other, CsgOp::Xor, ColorRule::Left)
+    }
+}
@@ -0,0 +1,332 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! sparse voxel DAGs, which store each distinct subtree only once
+//!
+//! subtrees are interned bottom-up: once a node's children have been
+//! interned, the node is identified by its occupancy, its interned children
+//! and its color, and identical nodes are merged. compress_dag() keeps colors
+//! as part of a subtree's identity and the usual Node layout, so the walks
+//! work on its result unchanged. to_colored_dag() merges subtrees by shape
+//! alone and stores the colors apart, in the depth-first order of the tree
+//! they came from.
+
+use super::camera::{Camera, DrawConfig};
+use super::fb::ColorBuffer;
+use super::voxbuf::*;
+use glam::{Vec3A, Vec4};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// a node's occupancy, interned children, color and occlusion
+type NodeKey = (ChildMask, [NodeRef; 8], u32, u8);
+
+struct Interner<'a> {
+    source: &'a [Node],
+    /// whether colors are part of a subtree's identity
+    colored: bool,
+    /// the interned node of each source node, once it has been interned
+    interned: Vec<NodeRef>,
+    table: HashMap<NodeKey, NodeRef>,
+    nodes: Vec<Node>,
+}
+
+impl<'a> Interner<'a> {
+    fn new(source: &'a [Node], colored: bool) -> Self {
+        Self {
+            source,
+            colored,
+            interned: vec![INVALID_NODE; source.len()],
+            table: HashMap::new(),
+            // the root's slot, filled in by finish()
+            nodes: vec![Node::default()],
+        }
+    }
+
+    /// a source node with its children interned
+    fn canonical(&mut self, node_ref: NodeRef) -> Node {
+        let mut node = self.source[node_ref as usize];
+        node.for_kids_mut(|_index, child| *child = self.intern(*child));
+
+        // stale refs in unoccupied slots must not tell nodes apart
+        let occupancy = node.occupancy;
+        node.for_kids_all_mut(|_index, mask, child| {
+            if occupancy & mask == 0 {
+                *child = 0;
+            }
+        });
+
+        if !self.colored && !node.data.is_empty() {
+            node.data = Payload::default();
+        }
+
+        node
+    }
+
+    fn intern(&mut self, node_ref: NodeRef) -> NodeRef {
+        let interned = self.interned[node_ref as usize];
+        if interned != INVALID_NODE {
+            return interned;
+        }
+
+        let node = self.canonical(node_ref);
+        let nodes = &mut self.nodes;
+        let interned = *self
+            .table
+            .entry((
+                node.occupancy,
+                node.children,
+                node.data.color,
+                node.data.occlusion,
+            ))
+            .or_insert_with(|| {
+                nodes.push(node);
+                (nodes.len() - 1) as NodeRef
+            });
+
+        self.interned[node_ref as usize] = interned;
+        interned
+    }
+
+    /// the interned nodes, with the root first and every other node after
+    /// its children
+    fn finish(mut self) -> Vec<Node> {
+        let root = self.canonical(VoxBuf::ROOT_NODE);
+        self.nodes[VoxBuf::ROOT_NODE as usize] = root;
+        self.nodes
+    }
+}
+
+impl VoxBuf {
+    /// merges identical subtrees, turning the tree into a DAG
+    ///
+    /// walking and drawing work the same on the result. edits expect a tree,
+    /// so the first one after this expands it back into one with
+    /// depth_sort_nodes()
+    pub fn compress_dag(&mut self) {
+        let timer = Instant::now();
+
+        let reachable = self.view().reachable_nodes();
+        let nodes = Interner::new(&self.nodes, true).finish();
+
+        println!(
+            "compressed {} nodes to {} nodes ({:.2}x) in {:?}",
+            reachable,
+            nodes.len(),
+            reachable as f32 / nodes.len() as f32,
+            timer.elapsed()
+        );
+
+        self.nodes = nodes;
+        self.free.clear();
+        self.shared = true;
+    }
+
+    /// whether subtrees may be shared between parents, as compress_dag()
+    /// leaves them
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// merges subtrees of the same shape, whatever their colors
+    pub fn to_colored_dag(&self) -> ColoredDag {
+        let timer = Instant::now();
+
+        let geometry = VoxBuf {
+            nodes: Interner::new(&self.nodes, false).finish(),
+            bounds: self.bounds,
+            free: Vec::new(),
+            journal: None,
+            shared: true,
+        };
+
+        // children come before their parents, except for the root
+        let mut sizes = vec![1; geometry.nodes.len()];
+        for node_ref in (1..sizes.len()).chain(std::iter::once(0)) {
+            let mut size = 1;
+            geometry.nodes[node_ref].for_kids(|_index, child| size += sizes[*child as usize]);
+            sizes[node_ref] = size;
+        }
+
+        let mut colors = Vec::with_capacity(sizes[VoxBuf::ROOT_NODE as usize] as usize);
+        let mut stack = vec![VoxBuf::ROOT_NODE];
+        while let Some(node_ref) = stack.pop() {
+            let node = &self.nodes[node_ref as usize];
+            colors.push(node.data);
+
+            let mut kids = [0; 8];
+            let mut kid_num = 0;
+            node.for_kids(|_index, child| {
+                kids[kid_num] = *child;
+                kid_num += 1;
+            });
+            stack.extend(kids[..kid_num].iter().rev());
+        }
+
+        let dag = ColoredDag {
+            geometry,
+            sizes,
+            colors,
+        };
+
+        println!(
+            "compressed {} nodes to {} nodes and {} colors ({:.2}x smaller) in {:?}",
+            dag.colors.len(),
+            dag.geometry.nodes.len(),
+            dag.colors.len(),
+            (dag.colors.len() * std::mem::size_of::<Node>()) as f32 / dag.memory_size() as f32,
+            timer.elapsed()
+        );
+
+        dag
+    }
+}
+
+impl<'a> VoxBufView<'a> {
+    /// the number of distinct nodes reachable from the root
+    pub fn reachable_nodes(&self) -> usize {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut reachable = 0;
+        let mut stack = vec![VoxBuf::ROOT_NODE];
+        while let Some(node_ref) = stack.pop() {
+            if std::mem::replace(&mut seen[node_ref as usize], true) {
+                continue;
+            }
+
+            reachable += 1;
+            self.nodes[node_ref as usize].for_kids(|_index, child| stack.push(*child));
+        }
+
+        reachable
+    }
+}
+
+/// a DAG of subtrees shared by shape alone, with the colors of the tree it
+/// was made from stored apart
+///
+/// a node's color is found from its position in the tree's depth-first
+/// order, which counts the tree nodes under the siblings before it
+pub struct ColoredDag {
+    geometry: VoxBuf,
+    /// the number of tree nodes under and including each DAG node
+    sizes: Vec<u32>,
+    /// the tree's colors in depth-first order, with children in the order
+    /// Node::for_kids() visits them
+    colors: Vec<Payload>,
+}
+
+impl ColoredDag {
+    /// the shared shapes, in a single color
+    pub fn geometry(&self) -> &VoxBuf {
+        &self.geometry
+    }
+
+    /// the number of nodes in the tree the DAG was made from
+    pub fn tree_nodes(&self) -> usize {
+        self.colors.len()
+    }
+
+    /// the memory taken by the nodes, sizes and colors, in bytes
+    pub fn memory_size(&self) -> usize {
+        use std::mem::size_of;
+        self.geometry.nodes.len() * size_of::<Node>()
+            + self.sizes.len() * size_of::<u32>()
+            + self.colors.len() * size_of::<Payload>()
+    }
+
+    /// the depth-first positions of a node's children, given its own
+    fn child_positions(&self, node: &Node, position: u32) -> [u32; 8] {
+        let mut positions = [0; 8];
+        let mut next = position + 1;
+        node.for_kids(|index, child| {
+            positions[index as usize] = next;
+            next += self.sizes[*child as usize];
+        });
+        positions
+    }
+
+    /// walks the DAG like VoxBuf::walk(), with each node's own color
+    pub fn walk<F>(&self, eye: &Vec3A, on_node: F)
+    where
+        F: FnMut(bool, &Payload, Vec4) -> bool,
+    {
+        let timer = Instant::now();
+        let walked_num = self.walk_scaled(eye, 1.0, on_node);
+        println!("walked {} nodes in {:?}", walked_num, timer.elapsed());
+    }
+
+    /// walks with each voxel's size scaled by `scale`, returning the number
+    /// of nodes walked
+    fn walk_scaled<F>(&self, eye: &Vec3A, scale: f32, mut on_node: F) -> usize
+    where
+        F: FnMut(bool, &Payload, Vec4) -> bool,
+    {
+        let mut walked_num = 0;
+        let origin = Vec3A::new(0.0, 0.0, 0.0);
+        let mut stack = vec![(VoxBuf::ROOT_NODE, 0, origin, 0)];
+
+        while let Some((node_ref, position, stem, depth)) = stack.pop() {
+            walked_num += 1;
+            let node = &self.geometry.nodes[node_ref as usize];
+            let offset = VoxBuf::depth_to_offset(depth);
+            let voxel = stem.extend(offset * scale);
+
+            let is_leaf = node.is_leaf();
+            let data = &self.colors[position as usize];
+            if on_node(is_leaf, data, voxel) & !is_leaf {
+                let positions = self.child_positions(node, position);
+                let order = Node::sorting_order(eye, &stem);
+                node.for_kids_ordered(order, |index, child| {
+                    let origin = stem + Node::index_offset(index, offset);
+                    stack.push((*child, positions[index as usize], origin, depth + 1));
+                });
+            }
+        }
+
+        walked_num
+    }
+
+    pub fn draw(&self, camera: &Camera, config: &DrawConfig, fb: &mut ColorBuffer) {
+        let timer = Instant::now();
+
+        // the same bounding radius as VoxBufView::fast_walk()
+        self.walk_scaled(&camera.eye, 1.73, |is_leaf, data, voxel| {
+            camera.draw_voxel(fb, config, is_leaf, &voxel, data)
+        });
+
+        println!("done drawing in {:?}", timer.elapsed());
+    }
+
+    /// expands the DAG back into a tree with its colors
+    pub fn to_voxbuf(&self) -> VoxBuf {
+        let mut nodes: Vec<Node> = Vec::with_capacity(self.colors.len());
+        let mut stack = vec![(VoxBuf::ROOT_NODE, 0, INVALID_NODE, 0)];
+        while let Some((node_ref, position, parent_ref, index)) = stack.pop() {
+            let mut node = self.geometry.nodes[node_ref as usize];
+            node.data = self.colors[position as usize];
+
+            let new_ref = nodes.len() as NodeRef;
+            if parent_ref != INVALID_NODE {
+                nodes[parent_ref as usize].children[index as usize] = new_ref;
+            }
+
+            let positions = self.child_positions(&node, position);
+            let mut kids = [(0, 0, 0, 0); 8];
+            let mut kid_num = 0;
+            node.for_kids(|index, child| {
+                kids[kid_num] = (*child, positions[index as usize], new_ref, index);
+                kid_num += 1;
+            });
+            stack.extend(kids[..kid_num].iter().rev());
+            nodes.push(node);
+        }
+
+        VoxBuf {
+            nodes,
+            bounds: self.geometry.bounds,
+            free: Vec::new(),
+            journal: None,
+            shared: false,
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! editing single voxels in place
+//!
+//! an edit splits leaves and creates nodes on its way down to the edited
+//! cell, then collapses the nodes on its way back up whose children have all
+//! become the same leaf. nodes that fall out of the tree go to the
+//! free-list, to be reused by later edits; depth_sort_nodes() compacts them
+//! away.
+
+use super::voxbuf::*;
+use glam::UVec3;
+
+/// a change to everything inside of a cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoxelEdit {
+    Set(Payload),
+    Clear,
+    /// recolors the filled voxels in the cell, leaving empty ones empty
+    Paint(u32),
+}
+
+/// the index of the child that leads down to `cell` from its ancestor
+/// `level + 1` levels up
+fn path_index(cell: &Cell, level: u32) -> ChildIndex {
+    (((cell.pos.x >> level) & 1)
+        | (((cell.pos.y >> level) & 1) << 1)
+        | (((cell.pos.z >> level) & 1) << 2)) as ChildIndex
+}
+
+/// whether an edit leaves everything inside of a leaf as it was
+fn leaf_unchanged(node: &Node, edit: VoxelEdit) -> bool {
+    match edit {
+        VoxelEdit::Set(data) => node.data == data,
+        VoxelEdit::Clear => node.data.is_empty(),
+        VoxelEdit::Paint(color) => node.data.is_empty() || node.data.color == color,
+    }
+}
+
+impl VoxBuf {
+    /// fills the voxel at `pos` in the 2^depth grid, replacing everything
+    /// inside of it; an empty payload clears it instead
+    pub fn set_voxel(&mut self, pos: UVec3, depth: u32, data: Payload) {
+        let edit = if data.is_empty() {
+            VoxelEdit::Clear
+        } else {
+            VoxelEdit::Set(data)
+        };
+
+        self.edit_voxel(Cell { pos, depth }, edit);
+    }
+
+    /// empties the voxel at `pos` in the 2^depth grid
+    pub fn clear_voxel(&mut self, pos: UVec3, depth: u32) {
+        self.edit_voxel(Cell { pos, depth }, VoxelEdit::Clear);
+    }
+
+    /// recolors whatever is filled inside of the voxel at `pos` in the
+    /// 2^depth grid
+    pub fn paint_voxel(&mut self, pos: UVec3, depth: u32, color: u32) {
+        self.edit_voxel(Cell { pos, depth }, VoxelEdit::Paint(color));
+    }
+
+    /// the unused nodes left behind by edits, which are reused last first
+    pub fn free_nodes(&self) -> &[NodeRef] {
+        &self.free
+    }
+
+    /// expands subtrees shared by compress_dag() back into a tree, so that
+    /// an edit only changes the cell it is made to
+    pub(crate) fn expand_shared(&mut self) {
+        if self.shared {
+            self.depth_sort_nodes();
+        }
+    }
+
+    /// whether the cell holds no filled voxels
+    pub(crate) fn is_cell_empty(&self, cell: Cell) -> bool {
+        let mut node = &self.nodes[Self::ROOT_NODE as usize];
+        for level in (0..cell.depth).rev() {
+            if node.is_leaf() {
+                break;
+            }
+
+            let index = path_index(&cell, level);
+            if !node.is_occupied(Node::index_to_mask(index)) {
+                return true;
+            }
+
+            node = &self.nodes[node.get_child(index) as usize];
+        }
+
+        node.is_leaf() && node.data.is_empty()
+    }
+
+    /// the node to write to, which every write to an existing node goes
+    /// through so that an open history step can record what it was
+    fn node_mut(&mut self, node_ref: NodeRef) -> &mut Node {
+        if let Some(journal) = &mut self.journal {
+            journal.record(node_ref, &self.nodes);
+        }
+
+        &mut self.nodes[node_ref as usize]
+    }
+
+    fn pop_free(&mut self) -> Option<NodeRef> {
+        let node_ref = self.free.pop()?;
+        if let Some(journal) = &mut self.journal {
+            journal.record_pop(node_ref, self.free.len());
+        }
+
+        Some(node_ref)
+    }
+
+    fn alloc_node(&mut self, node: Node) -> NodeRef {
+        match self.pop_free() {
+            Some(node_ref) => {
+                *self.node_mut(node_ref) = node;
+                node_ref
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as NodeRef
+            }
+        }
+    }
+
+    /// moves a node and all of its descendants to the free-list
+    fn free_subtree(&mut self, node_ref: NodeRef) {
+        let mut stack = vec![node_ref];
+        while let Some(node_ref) = stack.pop() {
+            self.nodes[node_ref as usize].for_kids(|_index, child| stack.push(*child));
+            self.free.push(node_ref);
+        }
+    }
+
+    /// frees a node's descendants, leaving it a leaf
+    fn free_children(&mut self, node_ref: NodeRef) {
+        let node = self.nodes[node_ref as usize];
+        self.node_mut(node_ref).occupancy = 0;
+        node.for_kids(|_index, child| self.free_subtree(*child));
+    }
+
+    /// the payload shared by all eight of a node's children, if they are all
+    /// leaves
+    fn uniform_children(&self, node: &Node) -> Option<Payload> {
+        if node.occupancy != 0xff {
+            return None;
+        }
+
+        let first = self.nodes[node.children[0] as usize];
+        let uniform = node.children.iter().all(|child| {
+            let child = &self.nodes[*child as usize];
+            child.is_leaf() && child.data == first.data
+        });
+
+        if uniform {
+            Some(first.data)
+        } else {
+            None
+        }
+    }
+
+    /// collapses the nodes under and including `node_ref` whose children
+    /// are all the same leaf, deepest first
+    fn collapse_subtree(&mut self, node_ref: NodeRef) {
+        let node = self.nodes[node_ref as usize];
+        node.for_kids(|_index, child| self.collapse_subtree(*child));
+
+        let node = self.nodes[node_ref as usize];
+        if let Some(data) = self.uniform_children(&node) {
+            self.free_children(node_ref);
+            self.node_mut(node_ref).data = data;
+        }
+    }
+
+    /// applies an edit to one cell, which every single-voxel edit goes
+    /// through to keep the tree consistent
+    pub(crate) fn edit_voxel(&mut self, cell: Cell, edit: VoxelEdit) {
+        assert!(
+            cell.depth < Self::MAX_DEPTH,
+            "unsupported depth {}",
+            cell.depth
+        );
+        assert!(
+            (cell.pos.max_element() as u64) < 1 << cell.depth,
+            "voxel {:?} outside of depth {}",
+            cell.pos,
+            cell.depth
+        );
+
+        // history steps can't record the nodes moving, which is why
+        // EditHistory expands trees up front
+        debug_assert!(!(self.shared && self.journal.is_some()));
+        self.expand_shared();
+
+        // the nodes above the edited cell, with the index of the child that
+        // leads down to it
+        let mut path = Vec::with_capacity(cell.depth as usize);
+        let mut node_ref = Self::ROOT_NODE;
+
+        // nodes created by this edit are leaves until they get a child
+        let mut created = false;
+
+        for level in (0..cell.depth).rev() {
+            let index = path_index(&cell, level);
+            let mask = Node::index_to_mask(index);
+
+            let mut node = self.nodes[node_ref as usize];
+            if node.is_leaf() && !created {
+                if leaf_unchanged(&node, edit) {
+                    return;
+                }
+
+                // split the leaf into eight copies of itself
+                if !node.data.is_empty() {
+                    let child = Node {
+                        occupancy: 0,
+                        children: [0; 8],
+                        data: node.data,
+                    };
+                    for child_ref in node.children.iter_mut() {
+                        *child_ref = self.alloc_node(child);
+                    }
+                    node.occupancy = 0xff;
+                }
+            }
+
+            if !node.is_occupied(mask) {
+                let data = match edit {
+                    VoxelEdit::Set(data) => data,
+                    _ => return,
+                };
+
+                // paths through empty space take the new voxel's color
+                if node.is_leaf() {
+                    node.data = data;
+                }
+
+                node.occupancy |= mask;
+                node.children[index as usize] = self.alloc_node(Node {
+                    occupancy: 0,
+                    children: [0; 8],
+                    data,
+                });
+                created = true;
+            }
+
+            *self.node_mut(node_ref) = node;
+            path.push((node_ref, index));
+            node_ref = node.children[index as usize];
+        }
+
+        let node = self.nodes[node_ref as usize];
+        if node.is_leaf() && !created && leaf_unchanged(&node, edit) {
+            return;
+        }
+
+        match edit {
+            VoxelEdit::Set(data) => {
+                self.free_children(node_ref);
+                self.node_mut(node_ref).data = data;
+            }
+            VoxelEdit::Clear => match path.last() {
+                Some((parent_ref, index)) => {
+                    self.free_subtree(node_ref);
+                    self.node_mut(*parent_ref).occupancy &= !Node::index_to_mask(*index);
+                }
+                None => {
+                    self.free_children(node_ref);
+                    self.node_mut(node_ref).data.color = 0;
+                }
+            },
+            VoxelEdit::Paint(color) => {
+                let mut stack = vec![node_ref];
+                while let Some(node_ref) = stack.pop() {
+                    let node = self.nodes[node_ref as usize];
+                    if !(node.is_leaf() && node.data.is_empty()) && node.data.color != color {
+                        self.node_mut(node_ref).data.color = color;
+                    }
+                    node.for_kids(|_index, child| stack.push(*child));
+                }
+
+                self.collapse_subtree(node_ref);
+            }
+        }
+
+        // collapse the nodes above that were emptied or made uniform
+        for depth in (0..path.len()).rev() {
+            let (node_ref, _index) = path[depth];
+            let node = self.nodes[node_ref as usize];
+            if node.is_leaf() {
+                if depth > 0 {
+                    let (parent_ref, index) = path[depth - 1];
+                    self.free.push(node_ref);
+                    self.node_mut(parent_ref).occupancy &= !Node::index_to_mask(index);
+                } else {
+                    self.node_mut(node_ref).data.color = 0;
+                }
+            } else if let Some(data) = self.uniform_children(&node) {
+                self.free_children(node_ref);
+                self.node_mut(node_ref).data = data;
+            } else {
+                break;
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use glam::Vec3A;
+use glam::{Vec2, Vec3A};
 
 type Bounds = (usize, usize, usize, usize);
 type Point = (usize, usize);
@@ -6,6 +6,10 @@ type Point = (usize, usize);
 type Pixel = u32;
 pub type ColorBuffer = Framebuffer<Pixel>;
 
+/// linear eye-space depths of the centers of the voxels drawn, infinite
+/// where nothing has been drawn
+pub type DepthBuffer = Framebuffer<f32>;
+
 pub trait Target<P> {
     fn draw(ptr: *mut P, p: P);
     fn test(ptr: *const P) -> bool;
@@ -33,6 +37,21 @@ impl Default for Framebuffer<Pixel> {
     }
 }
 
+impl Default for Framebuffer<f32> {
+    fn default() -> Self {
+        let width = 1280;
+        let height = 720;
+        let px = std::cmp::min(width, height) as f32;
+        let data = vec![f32::INFINITY; width * height];
+        Self {
+            width,
+            height,
+            px,
+            data,
+        }
+    }
+}
+
 fn pixel_to_simd(p: Pixel) -> packed_simd::u32x4 {
     let a = (p >> 24) & 0xff;
     let r = (p >> 16) & 0xff;
@@ -78,6 +97,37 @@ impl Target<Pixel> for Framebuffer<Pixel> {
     }
 }
 
+/// keeps the nearest depth drawn to each pixel
+impl Target<f32> for Framebuffer<f32> {
+    fn draw(ptr: *mut f32, depth: f32) {
+        unsafe {
+            if depth < *ptr {
+                *ptr = depth;
+            }
+        }
+    }
+
+    fn test(ptr: *const f32) -> bool {
+        unsafe { *ptr == f32::INFINITY }
+    }
+}
+
+impl Framebuffer<f32> {
+    /// the depth drawn to a pixel, if anything was
+    pub fn depth_at(&self, x: usize, y: usize) -> Option<f32> {
+        let depth = self.data[self.calc_offset((x, y))?];
+        if depth.is_finite() {
+            Some(depth)
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.data.fill(f32::INFINITY);
+    }
+}
+
 impl Framebuffer<Pixel> {
     pub fn draw_point(&mut self, xy: Point, p: Pixel) {
         if let Some(offset) = self.calc_offset(xy) {
@@ -96,50 +146,11 @@ impl Framebuffer<Pixel> {
     }
 
     pub fn draw_rect(&mut self, b: Bounds, c: Pixel) {
-        unsafe {
-            let (l, t, r, b) = b;
-            let w = r - l;
-            let h = b - t;
-            let space = self.width - w;
-            let start = t * self.width + l;
-            let mut ptr = self.data.as_mut_ptr().add(start);
-            let mut y = 0;
-            while y < h {
-                let mut x = 0;
-                while x < w {
-                    Self::draw(ptr, c);
-                    ptr = ptr.add(1);
-                    x += 1;
-                }
-                ptr = ptr.add(space);
-                y += 1;
-            }
-        }
+        draw_rect_rows(&mut self.data, self.width, b, c);
     }
 
     pub fn test_rect(&self, b: Bounds) -> bool {
-        unsafe {
-            let (l, t, r, b) = b;
-            let w = r - l;
-            let h = b - t;
-            let space = self.width - w;
-            let start = t * self.width + l;
-            let mut ptr = self.data.as_ptr().add(start);
-            let mut y = 0;
-            while y < h {
-                let mut x = 0;
-                while x < w {
-                    if Self::test(ptr) {
-                        return true;
-                    }
-                    ptr = ptr.add(1);
-                    x += 1;
-                }
-                ptr = ptr.add(space);
-                y += 1;
-            }
-            false
-        }
+        test_rect_rows(&self.data, self.width, b)
     }
 
     pub fn clear(&mut self) {
@@ -147,52 +158,354 @@ impl Framebuffer<Pixel> {
     }
 }
 
-impl<P> Framebuffer<P> {
-    pub fn frag_xy(&self, frag: &Vec3A) -> (usize, usize) {
-        let w = self.width as f32;
-        let h = self.height as f32;
-        let screen_pos = glam::Vec2::new(frag.x, frag.y) * 0.5 + 0.5;
-        let screen_scale = glam::Vec2::new(w, h);
-        let screen_pos = screen_pos * screen_scale;
-        let screen_pos = screen_pos.floor();
-        let x = screen_pos.x as usize;
-        let y = screen_pos.y as usize;
-        (x, y)
+/// draws to a rectangle of rows `width` pixels wide
+fn draw_rect_rows(data: &mut [Pixel], width: usize, b: Bounds, c: Pixel) {
+    unsafe {
+        let (l, t, r, b) = b;
+        let w = r - l;
+        let h = b - t;
+        let space = width - w;
+        let start = t * width + l;
+        let mut ptr = data.as_mut_ptr().add(start);
+        let mut y = 0;
+        while y < h {
+            let mut x = 0;
+            while x < w {
+                ColorBuffer::draw(ptr, c);
+                ptr = ptr.add(1);
+                x += 1;
+            }
+            ptr = ptr.add(space);
+            y += 1;
+        }
     }
+}
 
-    pub fn point_bounds(&self, center: &Vec3A) -> Option<(usize, usize, usize, usize)> {
-        if center.x < (-center.z - 1.0) || center.y < (-center.z - 1.0) {
-            return None;
+/// tests a rectangle of rows `width` pixels wide for unfilled pixels
+fn test_rect_rows(data: &[Pixel], width: usize, b: Bounds) -> bool {
+    unsafe {
+        let (l, t, r, b) = b;
+        let w = r - l;
+        let h = b - t;
+        let space = width - w;
+        let start = t * width + l;
+        let mut ptr = data.as_ptr().add(start);
+        let mut y = 0;
+        while y < h {
+            let mut x = 0;
+            while x < w {
+                if ColorBuffer::test(ptr) {
+                    return true;
+                }
+                ptr = ptr.add(1);
+                x += 1;
+            }
+            ptr = ptr.add(space);
+            y += 1;
         }
+        false
+    }
+}
 
-        let w = self.width as f32;
-        let h = self.height as f32;
+/// something voxels are drawn to, addressed in the pixels of a whole
+/// framebuffer
+///
+/// voxels are drawn with their linear eye-space depth, which canvases
+/// without a depth buffer ignore
+pub trait Canvas {
+    fn frag_xy(&self, frag: &Vec3A) -> Point;
+    fn point_bounds(&self, center: &Vec3A) -> Option<Bounds>;
+    /// the pixels that voxels centered within the box from `min` to `max`
+    /// can cover, with radii of up to `radius` (see box_bounds())
+    fn box_bounds(&self, min: Vec2, max: Vec2, radius: f32) -> Option<Bounds>;
+    fn draw_point(&mut self, xy: Point, p: Pixel, depth: f32);
+    fn draw_rect(&mut self, b: Bounds, c: Pixel, depth: f32);
+    fn test_rect(&self, b: Bounds) -> bool;
+}
 
-        if center.x > (1.0 + center.z) || center.y > (1.0 + center.z) {
-            return None;
-        }
+impl Canvas for ColorBuffer {
+    fn frag_xy(&self, frag: &Vec3A) -> Point {
+        Framebuffer::frag_xy(self, frag)
+    }
+
+    fn point_bounds(&self, center: &Vec3A) -> Option<Bounds> {
+        Framebuffer::point_bounds(self, center)
+    }
+
+    fn box_bounds(&self, min: Vec2, max: Vec2, radius: f32) -> Option<Bounds> {
+        box_bounds(self.width, self.height, self.px, min, max, radius)
+    }
+
+    fn draw_point(&mut self, xy: Point, p: Pixel, _depth: f32) {
+        Framebuffer::draw_point(self, xy, p)
+    }
+
+    fn draw_rect(&mut self, b: Bounds, c: Pixel, _depth: f32) {
+        Framebuffer::draw_rect(self, b, c)
+    }
+
+    fn test_rect(&self, b: Bounds) -> bool {
+        Framebuffer::test_rect(self, b)
+    }
+}
+
+/// a run of a framebuffer's rows, which can be drawn to apart from the
+/// others
+///
+/// bounds are clipped to the band's rows, so only the pixels inside of it
+/// are drawn to and tested
+pub struct Band<'a> {
+    /// the size of the whole framebuffer
+    pub width: usize,
+    pub height: usize,
+    pub px: f32,
+    /// the framebuffer row the band starts at
+    pub top: usize,
+    pub data: &'a mut [Pixel],
+}
+
+impl ColorBuffer {
+    /// splits the framebuffer into bands of `rows` rows, from the top down
+    pub fn bands(&mut self, rows: usize) -> impl Iterator<Item = Band<'_>> {
+        let (width, height, px) = (self.width, self.height, self.px);
+        let rows = rows.max(1);
+        self.data
+            .chunks_mut(width * rows)
+            .enumerate()
+            .map(move |(index, data)| Band {
+                width,
+                height,
+                px,
+                top: index * rows,
+                data,
+            })
+    }
+}
 
-        let screen_pos = glam::Vec2::new(center.x, center.y) * 0.5 + 0.5;
-        let screen_scale = Vec3A::new(w, h, self.px);
-        let screen_pos: Vec3A = screen_pos.extend(center.z).into();
-        let screen_pos = screen_pos * screen_scale;
+impl<'a> Band<'a> {
+    /// the framebuffer row after the band's last
+    fn bottom(&self) -> usize {
+        self.top + self.data.len() / self.width
+    }
+
+    /// bounds clipped to the band, relative to its top row
+    fn clip(&self, b: Bounds) -> Option<Bounds> {
+        let (l, t, r, b) = b;
+        let (t, b) = (t.max(self.top), b.min(self.bottom()));
+        if t < b {
+            Some((l, t - self.top, r, b - self.top))
+        } else {
+            None
+        }
+    }
+}
 
-        let [x, y, r] = screen_pos.to_array();
+impl<'a> Canvas for Band<'a> {
+    fn frag_xy(&self, frag: &Vec3A) -> Point {
+        frag_xy(self.width, self.height, frag)
+    }
 
-        const MIN_MARGIN: usize = 1;
-        const MAX_MARGIN: usize = 1;
-        let l = (x - r).max(MIN_MARGIN as f32) as usize - MIN_MARGIN;
-        let t = (y - r).max(MIN_MARGIN as f32) as usize - MIN_MARGIN;
-        let b = ((y + r) as usize + MAX_MARGIN).min(self.height);
-        let r = ((x + r) as usize + MAX_MARGIN).min(self.width);
+    fn point_bounds(&self, center: &Vec3A) -> Option<Bounds> {
+        point_bounds(self.width, self.height, self.px, center)
+    }
 
-        if l < r && t < b {
+    /// only the part within the band's rows, so that subtrees that can't
+    /// reach them are culled instead of walked
+    fn box_bounds(&self, min: Vec2, max: Vec2, radius: f32) -> Option<Bounds> {
+        let (l, t, r, b) = box_bounds(self.width, self.height, self.px, min, max, radius)?;
+        let (t, b) = (t.max(self.top), b.min(self.bottom()));
+        if t < b {
             Some((l, t, r, b))
         } else {
             None
         }
     }
 
+    fn draw_point(&mut self, xy: Point, p: Pixel, _depth: f32) {
+        let (x, y) = xy;
+        if x < self.width && y >= self.top && y < self.bottom() {
+            let offset = (y - self.top) * self.width + x;
+            let ptr = unsafe { self.data.as_mut_ptr().add(offset) };
+            ColorBuffer::draw(ptr, p);
+        }
+    }
+
+    fn draw_rect(&mut self, b: Bounds, c: Pixel, _depth: f32) {
+        if let Some(b) = self.clip(b) {
+            draw_rect_rows(self.data, self.width, b, c);
+        }
+    }
+
+    fn test_rect(&self, b: Bounds) -> bool {
+        match self.clip(b) {
+            Some(b) => test_rect_rows(self.data, self.width, b),
+            None => false,
+        }
+    }
+}
+
+/// a color buffer drawn to together with another buffer of the same size,
+/// such as a depth or id buffer
+///
+/// `record` is called with a pixel's offset and the depth being drawn the
+/// first time that pixel is covered, which is by the nearest voxel when
+/// drawing front-to-back. depths are those of voxel centers, as
+/// project_with_depth() gives them, and not of the fronts of their splats,
+/// which are nearer by up to the voxel's radius.
+pub struct FirstHit<'a, R> {
+    pub color: &'a mut ColorBuffer,
+    record: R,
+}
+
+impl<'a, R: FnMut(usize, f32)> FirstHit<'a, R> {
+    /// `size` is the width and height of the buffer that `record` fills,
+    /// which has to match the color buffer's
+    pub fn new(color: &'a mut ColorBuffer, size: (usize, usize), record: R) -> Self {
+        assert!(
+            (color.width, color.height) == size,
+            "color buffer is {}x{} but recorded buffer is {}x{}",
+            color.width,
+            color.height,
+            size.0,
+            size.1
+        );
+
+        Self { color, record }
+    }
+
+    fn draw_at(&mut self, offset: usize, p: Pixel, depth: f32) {
+        let color = &mut self.color.data[offset];
+        let first = *color == 0;
+        ColorBuffer::draw(color, p);
+        if first && *color != 0 {
+            (self.record)(offset, depth);
+        }
+    }
+}
+
+impl<'a, R: FnMut(usize, f32)> Canvas for FirstHit<'a, R> {
+    fn frag_xy(&self, frag: &Vec3A) -> Point {
+        self.color.frag_xy(frag)
+    }
+
+    fn point_bounds(&self, center: &Vec3A) -> Option<Bounds> {
+        self.color.point_bounds(center)
+    }
+
+    fn box_bounds(&self, min: Vec2, max: Vec2, radius: f32) -> Option<Bounds> {
+        self.color.box_bounds(min, max, radius)
+    }
+
+    fn draw_point(&mut self, xy: Point, p: Pixel, depth: f32) {
+        if let Some(offset) = self.color.calc_offset(xy) {
+            self.draw_at(offset, p, depth);
+        }
+    }
+
+    fn draw_rect(&mut self, b: Bounds, c: Pixel, depth: f32) {
+        let (l, t, r, b) = b;
+        for y in t..b {
+            let row = y * self.color.width;
+            for offset in (row + l)..(row + r) {
+                self.draw_at(offset, c, depth);
+            }
+        }
+    }
+
+    fn test_rect(&self, b: Bounds) -> bool {
+        self.color.test_rect(b)
+    }
+}
+
+fn frag_xy(width: usize, height: usize, frag: &Vec3A) -> Point {
+    let w = width as f32;
+    let h = height as f32;
+    let screen_pos = glam::Vec2::new(frag.x, frag.y) * 0.5 + 0.5;
+    let screen_scale = glam::Vec2::new(w, h);
+    let screen_pos = screen_pos * screen_scale;
+    let screen_pos = screen_pos.floor();
+    let x = screen_pos.x as usize;
+    let y = screen_pos.y as usize;
+    (x, y)
+}
+
+fn point_bounds(width: usize, height: usize, px: f32, center: &Vec3A) -> Option<Bounds> {
+    if center.x < (-center.z - 1.0) || center.y < (-center.z - 1.0) {
+        return None;
+    }
+
+    let w = width as f32;
+    let h = height as f32;
+
+    if center.x > (1.0 + center.z) || center.y > (1.0 + center.z) {
+        return None;
+    }
+
+    let screen_pos = glam::Vec2::new(center.x, center.y) * 0.5 + 0.5;
+    let screen_scale = Vec3A::new(w, h, px);
+    let screen_pos: Vec3A = screen_pos.extend(center.z).into();
+    let screen_pos = screen_pos * screen_scale;
+
+    let [x, y, r] = screen_pos.to_array();
+
+    const MIN_MARGIN: usize = 1;
+    const MAX_MARGIN: usize = 1;
+    let l = (x - r).max(MIN_MARGIN as f32) as usize - MIN_MARGIN;
+    let t = (y - r).max(MIN_MARGIN as f32) as usize - MIN_MARGIN;
+    let b = ((y + r) as usize + MAX_MARGIN).min(height);
+    let r = ((x + r) as usize + MAX_MARGIN).min(width);
+
+    if l < r && t < b {
+        Some((l, t, r, b))
+    } else {
+        None
+    }
+}
+
+/// the pixels that voxels centered within the box from `min` to `max`, in
+/// normalized device coordinates, can cover when drawn with radii of up to
+/// `radius`, as a projected voxel's z
+///
+/// this contains every rect point_bounds() gives for such a voxel and every
+/// pixel frag_xy() gives for its center, with a pixel to spare for rounding
+fn box_bounds(
+    width: usize,
+    height: usize,
+    px: f32,
+    min: Vec2,
+    max: Vec2,
+    radius: f32,
+) -> Option<Bounds> {
+    let screen_scale = Vec2::new(width as f32, height as f32);
+    let min = (min * 0.5 + 0.5) * screen_scale - radius * px;
+    let max = (max * 0.5 + 0.5) * screen_scale + radius * px;
+
+    // point_bounds() widens rects by a pixel on each side
+    const MARGIN: f32 = 2.0;
+    let min = (min.floor() - MARGIN).max(Vec2::ZERO);
+    let max = (max.floor() + MARGIN).min(screen_scale);
+
+    if min.x < max.x && min.y < max.y {
+        Some((
+            min.x as usize,
+            min.y as usize,
+            max.x as usize,
+            max.y as usize,
+        ))
+    } else {
+        None
+    }
+}
+
+impl<P> Framebuffer<P> {
+    pub fn frag_xy(&self, frag: &Vec3A) -> (usize, usize) {
+        frag_xy(self.width, self.height, frag)
+    }
+
+    pub fn point_bounds(&self, center: &Vec3A) -> Option<(usize, usize, usize, usize)> {
+        point_bounds(self.width, self.height, self.px, center)
+    }
+
     fn calc_offset(&self, xy: Point) -> Option<usize> {
         if xy.0 >= self.width || xy.1 >= self.height {
             None
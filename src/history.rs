@@ -0,0 +1,282 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! undo and redo of voxel edits
+//!
+//! while a step is open, the tree's writes record the slots they overwrite
+//! (see VoxBuf::node_mut()), so that each step is stored as a delta of only
+//! the node slots it changed, the nodes it appended and the part of the
+//! free-list it changed. deltas are replayed in either direction to undo and
+//! redo them, and the oldest ones are dropped to keep the history within its
+//! memory budget.
+
+use super::brush::Brush;
+use super::edit::VoxelEdit;
+use super::voxbuf::*;
+use glam::UVec3;
+use std::collections::{HashMap, VecDeque};
+
+/// the changes made to a tree since a step was opened
+#[derive(Clone, Debug)]
+pub(crate) struct Journal {
+    /// the node count at the start, past which nodes are new
+    len: usize,
+    /// the first contents of every older slot written to
+    old: HashMap<NodeRef, Node>,
+    /// the lowest the free-list's length has been since the start
+    free_low: usize,
+    /// the free-list's entries from below its starting length, in the order
+    /// they were popped
+    popped: Vec<NodeRef>,
+}
+
+impl Journal {
+    fn new(vb: &VoxBuf) -> Self {
+        Self {
+            len: vb.nodes.len(),
+            old: HashMap::new(),
+            free_low: vb.free.len(),
+            popped: Vec::new(),
+        }
+    }
+
+    /// notes a slot that is about to be written to
+    pub(crate) fn record(&mut self, node_ref: NodeRef, nodes: &[Node]) {
+        if (node_ref as usize) < self.len {
+            self.old.entry(node_ref).or_insert(nodes[node_ref as usize]);
+        }
+    }
+
+    /// notes an entry popped from the free-list, which is left `len` long
+    pub(crate) fn record_pop(&mut self, node_ref: NodeRef, len: usize) {
+        // the free-list is a stack, so entries below its lowest point are
+        // still the ones it started with
+        if len < self.free_low {
+            self.popped.push(node_ref);
+            self.free_low = len;
+        }
+    }
+}
+
+/// one undoable step
+struct Delta {
+    /// the slots written to, with their contents before and after
+    changed: Vec<(NodeRef, Node, Node)>,
+    /// the node count before the step, and the nodes it appended
+    len: usize,
+    appended: Vec<Node>,
+    /// the free-list's unchanged length, and its entries past that length
+    /// before and after the step
+    free_len: usize,
+    free_before: Vec<NodeRef>,
+    free_after: Vec<NodeRef>,
+}
+
+impl Delta {
+    fn new(journal: Journal, vb: &VoxBuf) -> Self {
+        let mut changed: Vec<_> = journal
+            .old
+            .into_iter()
+            .map(|(node_ref, before)| (node_ref, before, vb.nodes[node_ref as usize]))
+            .filter(|(_node_ref, before, after)| before != after)
+            .collect();
+        changed.sort_unstable_by_key(|(node_ref, _, _)| *node_ref);
+
+        let mut free_before = journal.popped;
+        free_before.reverse();
+
+        Self {
+            changed,
+            len: journal.len,
+            appended: vb.nodes[journal.len..].to_vec(),
+            free_len: journal.free_low,
+            free_before,
+            free_after: vb.free[journal.free_low..].to_vec(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.appended.is_empty() && self.free_before == self.free_after
+    }
+
+    /// the memory the delta takes up, in bytes
+    fn size(&self) -> usize {
+        use std::mem::size_of;
+        size_of::<Self>()
+            + self.changed.len() * size_of::<(NodeRef, Node, Node)>()
+            + self.appended.len() * size_of::<Node>()
+            + (self.free_before.len() + self.free_after.len()) * size_of::<NodeRef>()
+    }
+
+    fn undo(&self, vb: &mut VoxBuf) {
+        vb.nodes.truncate(self.len);
+        for (node_ref, before, _after) in self.changed.iter() {
+            vb.nodes[*node_ref as usize] = *before;
+        }
+
+        vb.free.truncate(self.free_len);
+        vb.free.extend_from_slice(&self.free_before);
+    }
+
+    fn redo(&self, vb: &mut VoxBuf) {
+        vb.nodes.truncate(self.len);
+        vb.nodes.extend_from_slice(&self.appended);
+        for (node_ref, _before, after) in self.changed.iter() {
+            vb.nodes[*node_ref as usize] = *after;
+        }
+
+        vb.free.truncate(self.free_len);
+        vb.free.extend_from_slice(&self.free_after);
+    }
+}
+
+/// a tree whose edits can be undone and redone, keeping at most `budget`
+/// bytes of history
+pub struct EditHistory {
+    vb: VoxBuf,
+    undo: VecDeque<Delta>,
+    redo: Vec<Delta>,
+    /// the number of steps dropped from the bottom of the undo stack
+    dropped: usize,
+    /// named positions in the history, counted in steps from the start
+    checkpoints: Vec<(String, usize)>,
+    budget: usize,
+}
+
+impl EditHistory {
+    /// starts a history at `vb`, first expanding any subtrees that
+    /// compress_dag() shared, which steps couldn't undo
+    pub fn new(mut vb: VoxBuf, budget: usize) -> Self {
+        vb.expand_shared();
+        Self {
+            vb,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            dropped: 0,
+            checkpoints: Vec::new(),
+            budget,
+        }
+    }
+
+    pub fn voxbuf(&self) -> &VoxBuf {
+        &self.vb
+    }
+
+    pub fn into_voxbuf(self) -> VoxBuf {
+        self.vb
+    }
+
+    pub fn set_voxel(&mut self, pos: UVec3, depth: u32, data: Payload) {
+        self.record(|vb| vb.set_voxel(pos, depth, data));
+    }
+
+    pub fn clear_voxel(&mut self, pos: UVec3, depth: u32) {
+        self.record(|vb| vb.clear_voxel(pos, depth));
+    }
+
+    pub fn paint_voxel(&mut self, pos: UVec3, depth: u32, color: u32) {
+        self.record(|vb| vb.paint_voxel(pos, depth, color));
+    }
+
+    pub fn apply_brush<B: Brush + ?Sized>(&mut self, brush: &B, edit: VoxelEdit, depth: u32) {
+        self.record(|vb| vb.apply_brush(brush, edit, depth));
+    }
+
+    /// runs an edit as one step, dropping the steps that could be redone
+    fn record<F: FnOnce(&mut VoxBuf)>(&mut self, edit: F) {
+        self.vb.journal = Some(Journal::new(&self.vb));
+        edit(&mut self.vb);
+        let journal = self.vb.journal.take().unwrap();
+
+        let delta = Delta::new(journal, &self.vb);
+        if delta.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        let position = self.position();
+        self.checkpoints.retain(|(_name, step)| *step <= position);
+        self.undo.push_back(delta);
+
+        while self.memory_used() > self.budget {
+            if self.undo.pop_front().is_none() {
+                break;
+            }
+            self.dropped += 1;
+        }
+
+        let dropped = self.dropped;
+        self.checkpoints.retain(|(_name, step)| *step >= dropped);
+    }
+
+    /// the number of steps from the start to the current state
+    fn position(&self) -> usize {
+        self.dropped + self.undo.len()
+    }
+
+    /// the memory the recorded steps take up, in bytes
+    pub fn memory_used(&self) -> usize {
+        self.undo
+            .iter()
+            .chain(self.redo.iter())
+            .map(Delta::size)
+            .sum()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// reverts the last step, returning false if there is none
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop_back() {
+            Some(delta) => {
+                delta.undo(&mut self.vb);
+                self.redo.push(delta);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// reapplies the last undone step, returning false if there is none
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(delta) => {
+                delta.redo(&mut self.vb);
+                self.undo.push_back(delta);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// names the current state, replacing any checkpoint of the same name
+    pub fn checkpoint(&mut self, name: &str) {
+        let position = self.position();
+        self.checkpoints.retain(|(other, _step)| other != name);
+        self.checkpoints.push((name.to_string(), position));
+    }
+
+    /// the names of the checkpoints that can still be restored
+    pub fn checkpoints(&self) -> impl Iterator<Item = &str> {
+        self.checkpoints.iter().map(|(name, _step)| name.as_str())
+    }
+
+    /// undoes or redoes steps back to a checkpoint, returning false if there
+    /// is no such checkpoint or it fell out of the history
+    pub fn restore(&mut self, name: &str) -> bool {
+        let target = match self.checkpoints.iter().find(|(other, _step)| other == name) {
+            Some((_name, step)) => *step,
+            None => return false,
+        };
+
+        while self.position() > target && self.undo() {}
+        while self.position() < target && self.redo() {}
+        self.position() == target
+    }
+}
@@ -5,7 +5,21 @@
 extern crate lazy_static;
 
 pub mod binvox;
+pub mod brush;
 pub mod camera;
+pub mod csg;
+pub mod dag;
+pub mod edit;
 pub mod fb;
+pub mod history;
+pub mod lighting;
+pub mod mesh;
+pub mod meshing;
+pub mod packed;
+pub mod pick;
+pub mod pointcloud;
 pub mod procgen;
+pub mod svo;
+pub mod vox;
 pub mod voxbuf;
+pub mod voxelize;
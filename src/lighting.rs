@@ -0,0 +1,342 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! directional lighting, shaded after drawing
+//!
+//! every node gets a normal from the occupancy around it: a leaf's normal
+//! is across the surface that the filled voxels near it lie along, and a
+//! branch's is the average of its children's, for when it's drawn in their
+//! place. shade() then lights each pixel by the normal of the node
+//! draw_with_ids() drew there, turned towards the camera.
+//!
+//! ambient occlusion is found from the occupancy around each node as well,
+//! but is baked into the payloads instead, to be drawn at no extra cost.
+
+use super::camera::Camera;
+use super::fb::ColorBuffer;
+use super::pick::IdBuffer;
+use super::voxbuf::*;
+use glam::Vec3A;
+use std::time::Instant;
+
+/// how many cells away from a leaf its normal looks for filled cells
+const NORMAL_RADIUS: i32 = 2;
+
+/// the steps, in cells, that occlusion rays from a leaf look for filled
+/// cells at, in each of the 26 directions around it
+const OCCLUSION_STEPS: [i32; 6] = [1, 2, 3, 4, 6, 8];
+
+pub struct DirectionalLight {
+    /// the direction the light shines from, towards the scene
+    pub direction: Vec3A,
+    pub color: Vec3A,
+}
+
+pub struct Lighting {
+    pub ambient: Vec3A,
+    pub lights: Vec<DirectionalLight>,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            ambient: Vec3A::new(0.3, 0.3, 0.35),
+            lights: vec![DirectionalLight {
+                direction: Vec3A::new(0.5, 1.0, 0.3).normalize(),
+                color: Vec3A::new(0.8, 0.8, 0.75),
+            }],
+        }
+    }
+}
+
+impl Lighting {
+    /// the light reaching a surface facing along `normal`, where a zero
+    /// normal only gets the ambient light
+    pub fn light(&self, normal: Vec3A) -> Vec3A {
+        self.lights.iter().fold(self.ambient, |sum, light| {
+            sum + light.color * normal.dot(light.direction).max(0.0)
+        })
+    }
+
+    /// shades a color buffer drawn with draw_with_ids(), given the normals
+    /// that node_normals() found for the same tree
+    pub fn shade(&self, camera: &Camera, fb: &mut ColorBuffer, ids: &IdBuffer, normals: &[Vec3A]) {
+        let timer = Instant::now();
+
+        for (offset, id) in ids.data.iter().enumerate() {
+            if *id == INVALID_NODE {
+                continue;
+            }
+
+            let to_camera = camera.eye - Vec3A::from(ids.voxels[offset].truncate());
+            let mut normal = normals[*id as usize];
+            if normal.dot(to_camera) < 0.0 {
+                normal = -normal;
+            }
+
+            let pixel = &mut fb.data[offset];
+            *pixel = shade_pixel(*pixel, self.light(normal));
+        }
+
+        println!("shaded in {:?}", timer.elapsed());
+    }
+}
+
+impl VoxBuf {
+    pub fn node_normals(&self) -> Vec<Vec3A> {
+        self.view().node_normals()
+    }
+
+    /// stores the ambient occlusion of every node in its payload, for
+    /// drawing with DrawConfig::occlusion
+    pub fn bake_occlusion(&mut self) {
+        let occlusion = self.view().node_occlusion();
+        for (node, occlusion) in self.nodes.iter_mut().zip(occlusion) {
+            node.data.occlusion = occlusion;
+        }
+    }
+}
+
+impl<'a> VoxBufView<'a> {
+    /// the normal of every node, indexed by NodeRef, or zero for nodes that
+    /// are empty, unreachable or surrounded on all sides
+    pub fn node_normals(&self) -> Vec<Vec3A> {
+        let timer = Instant::now();
+
+        let nodes = self.nodes();
+        let mut normals = vec![Vec3A::ZERO; nodes.len()];
+        let mut walked = Vec::new();
+        self.walk_cells(|node_ref, node, cell| {
+            if node.is_leaf() && !node.data.is_empty() {
+                normals[node_ref as usize] = self.leaf_normal(cell);
+            }
+
+            walked.push(node_ref);
+            true
+        });
+
+        // children are walked after their parents. a shell's normals may
+        // face either way, so they're summed facing the same way
+        for node_ref in walked.into_iter().rev() {
+            let node = &nodes[node_ref as usize];
+            if !node.is_leaf() {
+                let mut sum = Vec3A::ZERO;
+                node.for_kids(|_index, child| {
+                    let normal = normals[*child as usize];
+                    if sum.dot(normal) < 0.0 {
+                        sum -= normal;
+                    } else {
+                        sum += normal;
+                    }
+                });
+                normals[node_ref as usize] = sum.normalize_or_zero();
+            }
+        }
+
+        println!("found node normals in {:?}", timer.elapsed());
+
+        normals
+    }
+
+    /// the occlusion of every node, indexed by NodeRef: a leaf is occluded
+    /// by the filled cells that rays from it run into, and a branch by the
+    /// average of its children, weighted as build_lod_colors() weighs them
+    pub fn node_occlusion(&self) -> Vec<u8> {
+        let timer = Instant::now();
+
+        let nodes = self.nodes();
+        let mut occlusion = vec![0; nodes.len()];
+        let mut coverage = vec![0.0; nodes.len()];
+        let mut walked = Vec::new();
+        self.walk_cells(|node_ref, node, cell| {
+            if node.is_leaf() && !node.data.is_empty() {
+                let leaf = self.leaf_occlusion(cell);
+                occlusion[node_ref as usize] = (leaf * 255.0).round() as u8;
+                coverage[node_ref as usize] = LodAverage::leaf_coverage(node);
+            }
+
+            walked.push(node_ref);
+            true
+        });
+
+        // children are walked after their parents. branches average their
+        // children's rounded occlusion, like build_lod_colors() does, so
+        // that the two agree whichever runs last
+        for node_ref in walked.into_iter().rev() {
+            let node = &nodes[node_ref as usize];
+            if !node.is_leaf() {
+                let mut average = LodAverage::default();
+                node.for_kids(|_index, child| {
+                    let data = Payload {
+                        occlusion: occlusion[*child as usize],
+                        ..nodes[*child as usize].data
+                    };
+                    average.add(coverage[*child as usize], &data);
+                });
+
+                if let Some(data) = average.payload() {
+                    occlusion[node_ref as usize] = data.occlusion;
+                }
+                coverage[node_ref as usize] = average.coverage();
+            }
+        }
+
+        println!("found node occlusion in {:?}", timer.elapsed());
+
+        occlusion
+    }
+
+    /// the occlusion of a leaf's cell, from 0 to 1
+    ///
+    /// rays go out on both sides of the surface, weighted by how squarely
+    /// they leave it, and the more open side is taken to be the outside
+    fn leaf_occlusion(&self, cell: Cell) -> f32 {
+        let normal = self.leaf_normal(cell);
+        let (mut front, mut back, mut total) = (0.0, 0.0, 0.0);
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let direction = Vec3A::new(dx as f32, dy as f32, dz as f32);
+                    let facing = match direction.try_normalize() {
+                        Some(direction) => direction.dot(normal),
+                        None => continue,
+                    };
+
+                    let blocked = self.ray_blocked(cell, [dx, dy, dz]);
+                    total += facing.abs();
+                    if facing > 0.0 {
+                        front += facing * blocked;
+                    } else {
+                        back -= facing * blocked;
+                    }
+                }
+            }
+        }
+
+        // every direction's opposite is there too, so both sides weigh the same
+        let side = total / 2.0;
+        if side > 0.0 {
+            front.min(back) / side
+        } else {
+            0.0
+        }
+    }
+
+    /// how soon a ray of cells from a leaf's cell hits a filled one, from 1
+    /// right next to it down to 0 for none at all
+    fn ray_blocked(&self, cell: Cell, direction: [i32; 3]) -> f32 {
+        let steps = OCCLUSION_STEPS.len();
+        for (hit, step) in OCCLUSION_STEPS.iter().enumerate() {
+            let offset = [
+                direction[0] * step,
+                direction[1] * step,
+                direction[2] * step,
+            ];
+            if self.is_filled(cell, offset) {
+                return 1.0 - hit as f32 / steps as f32;
+            }
+        }
+
+        0.0
+    }
+
+    /// the normal of the surface through a leaf's cell
+    ///
+    /// the filled cells around a leaf spread out least across the surface,
+    /// which finds it in thin shells as well as in solids. the normal then
+    /// points towards the side with more empty cells, though that's only a
+    /// guess in shells, which is why shade() turns normals to the camera.
+    fn leaf_normal(&self, cell: Cell) -> Vec3A {
+        let mut filled = Vec::new();
+        let mut away = Vec3A::ZERO;
+        let r = NORMAL_RADIUS;
+        for dz in -r..=r {
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    let offset = Vec3A::new(dx as f32, dy as f32, dz as f32);
+                    if offset == Vec3A::ZERO || self.is_filled(cell, [dx, dy, dz]) {
+                        filled.push(offset);
+                    } else {
+                        away += offset.normalize();
+                    }
+                }
+            }
+        }
+
+        let normal = least_spread(&filled, away);
+        if normal.dot(away) < 0.0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    /// whether the cell at `offset` from `cell` is filled, with the cells
+    /// outside of the grid empty
+    fn is_filled(&self, cell: Cell, offset: [i32; 3]) -> bool {
+        let size = 1i64 << cell.depth;
+        let mut pos = [0; 3];
+        for axis in 0..3 {
+            let p = cell.pos.to_array()[axis] as i64 + offset[axis] as i64;
+            if p < 0 || p >= size {
+                return false;
+            }
+            pos[axis] = p as u32;
+        }
+
+        let mut node = &self.nodes()[VoxBuf::ROOT_NODE as usize];
+        for depth in 0..cell.depth {
+            if node.is_leaf() {
+                break;
+            }
+
+            let shift = cell.depth - depth - 1;
+            let index = (0..3).fold(0, |index, axis| index | ((pos[axis] >> shift) & 1) << axis)
+                as ChildIndex;
+            if !node.is_occupied(Node::index_to_mask(index)) {
+                return false;
+            }
+
+            node = &self.nodes()[node.get_child(index) as usize];
+        }
+
+        !node.data.is_empty()
+    }
+}
+
+/// the axis along which points spread out the least, found by power
+/// iteration from `guess`
+fn least_spread(points: &[Vec3A], guess: Vec3A) -> Vec3A {
+    let mean = points.iter().fold(Vec3A::ZERO, |sum, p| sum + *p) / points.len() as f32;
+    let mut covariance = [Vec3A::ZERO; 3];
+    for p in points {
+        let d = *p - mean;
+        covariance[0] += d * d.x;
+        covariance[1] += d * d.y;
+        covariance[2] += d * d.z;
+    }
+
+    // the least spread axis of the covariance is the most spread one of
+    // trace - covariance
+    let trace = covariance[0].x + covariance[1].y + covariance[2].z;
+    let mut axis = guess.try_normalize().unwrap_or(Vec3A::Y);
+    for _ in 0..16 {
+        let spread = covariance[0] * axis.x + covariance[1] * axis.y + covariance[2] * axis.z;
+        match (axis * trace - spread).try_normalize() {
+            Some(next) => axis = next,
+            None => break,
+        }
+    }
+
+    axis
+}
+
+/// scales a pixel's color channels by a light, keeping its alpha
+pub fn shade_pixel(pixel: u32, light: Vec3A) -> u32 {
+    let channel = |shift: u32, light: f32| {
+        let c = ((pixel >> shift) & 0xff) as f32 * light;
+        (c.min(255.0) as u32) << shift
+    };
+
+    (pixel & 0xff000000) | channel(16, light.x) | channel(8, light.y) | channel(0, light.z)
+}
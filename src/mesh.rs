@@ -0,0 +1,308 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::{Vec2, Vec3A};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufWriter, Write};
+
+/// an ARGB image, stored row by row from the top
+#[derive(Clone, Debug)]
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Texture {
+    /// the nearest texel to `uv`, with v running up the image and wrapping
+    /// outside of [0, 1]
+    pub fn sample(&self, uv: Vec2) -> u32 {
+        let u = uv.x - uv.x.floor();
+        let v = 1.0 - (uv.y - uv.y.floor());
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// an indexed triangle mesh
+///
+/// `colors` and `uvs` are either empty or hold one entry per position; a
+/// mesh with both `uvs` and a `texture` is colored from the texture
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub positions: Vec<Vec3A>,
+    pub colors: Vec<u32>,
+    pub uvs: Vec<Vec2>,
+    pub texture: Option<Texture>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl Mesh {
+    /// the color at barycentric coordinates `bary` of triangle `index`
+    pub fn color_at(&self, index: usize, bary: Vec3A) -> Option<u32> {
+        let [a, b, c] = self.triangles[index];
+        let [a, b, c] = [a as usize, b as usize, c as usize];
+
+        if let (Some(texture), false) = (&self.texture, self.uvs.is_empty()) {
+            let uv = self.uvs[a] * bary.x + self.uvs[b] * bary.y + self.uvs[c] * bary.z;
+            Some(texture.sample(uv))
+        } else if !self.colors.is_empty() {
+            Some(blend_colors(
+                [self.colors[a], self.colors[b], self.colors[c]],
+                bary,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// the smallest box containing every finite position, as (min, max)
+    pub fn aabb(&self) -> Option<(Vec3A, Vec3A)> {
+        let mut positions = self.positions.iter().filter(|p| p.is_finite());
+        let first = *positions.next()?;
+        Some(positions.fold((first, first), |(min, max), p| (min.min(*p), max.max(*p))))
+    }
+}
+
+/// mixes three ARGB colors by barycentric weights
+fn blend_colors(colors: [u32; 3], bary: Vec3A) -> u32 {
+    [24, 16, 8, 0].iter().fold(0, |blend, shift| {
+        let channel = |color: u32| ((color >> shift) & 0xff) as f32;
+        let mixed =
+            channel(colors[0]) * bary.x + channel(colors[1]) * bary.y + channel(colors[2]) * bary.z;
+        blend | (mixed.round().clamp(0.0, 255.0) as u32) << shift
+    })
+}
+
+#[derive(Debug)]
+pub enum ObjError {
+    /// a statement's values could not be parsed, or are not finite
+    Malformed {
+        line: usize,
+    },
+    /// a face refers to a vertex that is not defined
+    InvalidIndex {
+        line: usize,
+    },
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Malformed { line } => write!(f, "malformed statement on line {}", line),
+            ObjError::InvalidIndex { line } => write!(f, "invalid vertex index on line {}", line),
+            ObjError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ObjError {
+    fn from(e: std::io::Error) -> Self {
+        ObjError::Io(e)
+    }
+}
+
+fn parse_floats(words: std::str::SplitWhitespace, out: &mut Vec<f32>) -> Option<()> {
+    out.clear();
+    for word in words {
+        let value: f32 = word.parse().ok()?;
+        if !value.is_finite() {
+            return None;
+        }
+        out.push(value);
+    }
+    Some(())
+}
+
+/// resolves a 1-based, possibly negative OBJ index into `len` elements
+fn resolve_index(word: &str, len: usize) -> Option<usize> {
+    let index: i64 = word.parse().ok()?;
+    let resolved = if index < 0 {
+        len as i64 + index
+    } else {
+        index - 1
+    };
+
+    if (0..len as i64).contains(&resolved) {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+/// loads the polygons of a Wavefront OBJ as triangles
+///
+/// besides `v`, `vt` and `f`, vertex colors written as `v x y z r g b` are
+/// understood; other statements, like normals and materials, are ignored
+pub fn load_obj<R: BufRead>(input: R) -> Result<Mesh, ObjError> {
+    let mut positions = Vec::new();
+    let mut colors = Vec::new();
+    let mut uvs = Vec::new();
+
+    let mut mesh = Mesh::default();
+    let mut has_colors = false;
+    let mut has_uvs = false;
+
+    // OBJ indexes positions and UVs separately, so each distinct pair
+    // becomes one mesh vertex
+    let mut vertices = HashMap::<(usize, Option<usize>), u32>::new();
+    let mut corners = Vec::new();
+    let mut values = Vec::new();
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        let number = number + 1;
+        let malformed = || ObjError::Malformed { line: number };
+
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                parse_floats(words, &mut values).ok_or_else(malformed)?;
+                let color = match values.len() {
+                    3 | 4 => None,
+                    6 => Some(values[3..6].iter().fold(0xff, |color, c| {
+                        color << 8 | (c.clamp(0.0, 1.0) * 255.0).round() as u32
+                    })),
+                    _ => return Err(malformed()),
+                };
+
+                has_colors |= color.is_some();
+                positions.push(Vec3A::new(values[0], values[1], values[2]));
+                colors.push(color.unwrap_or(0xffffffff));
+            }
+            Some("vt") => {
+                parse_floats(words, &mut values).ok_or_else(malformed)?;
+                if values.is_empty() || values.len() > 3 {
+                    return Err(malformed());
+                }
+
+                uvs.push(Vec2::new(values[0], values.get(1).copied().unwrap_or(0.0)));
+            }
+            Some("f") => {
+                corners.clear();
+                for word in words {
+                    let invalid = || ObjError::InvalidIndex { line: number };
+                    let mut indices = word.split('/');
+                    let position = indices.next().ok_or_else(malformed)?;
+                    let position = resolve_index(position, positions.len()).ok_or_else(invalid)?;
+                    let uv = match indices.next() {
+                        Some("") | None => None,
+                        Some(uv) => Some(resolve_index(uv, uvs.len()).ok_or_else(invalid)?),
+                    };
+
+                    has_uvs |= uv.is_some();
+                    let next = vertices.len() as u32;
+                    let vertex = *vertices.entry((position, uv)).or_insert_with(|| {
+                        mesh.positions.push(positions[position]);
+                        mesh.colors.push(colors[position]);
+                        mesh.uvs.push(uv.map(|uv| uvs[uv]).unwrap_or(Vec2::ZERO));
+                        next
+                    });
+                    corners.push(vertex);
+                }
+
+                if corners.len() < 3 {
+                    return Err(malformed());
+                }
+
+                // triangulate the polygon as a fan
+                for i in 1..(corners.len() - 1) {
+                    mesh.triangles
+                        .push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !has_colors {
+        mesh.colors.clear();
+    }
+
+    if !has_uvs {
+        mesh.uvs.clear();
+    }
+
+    Ok(mesh)
+}
+
+/// an ARGB color's red, green and blue channels
+fn rgb(color: u32) -> [u8; 3] {
+    let [_a, r, g, b] = color.to_be_bytes();
+    [r, g, b]
+}
+
+/// writes a mesh as a Wavefront OBJ, with vertex colors written as
+/// `v x y z r g b`, as load_obj() reads them
+pub fn write_obj<W: Write>(mesh: &Mesh, out: W) -> std::io::Result<()> {
+    let mut out = BufWriter::new(out);
+    for (index, p) in mesh.positions.iter().enumerate() {
+        write!(out, "v {} {} {}", p.x, p.y, p.z)?;
+        if let Some(color) = mesh.colors.get(index) {
+            let [r, g, b] = rgb(*color);
+            let [r, g, b] = [r, g, b].map(|c| c as f32 / 255.0);
+            write!(out, " {} {} {}", r, g, b)?;
+        }
+        writeln!(out)?;
+    }
+
+    for uv in mesh.uvs.iter() {
+        writeln!(out, "vt {} {}", uv.x, uv.y)?;
+    }
+
+    for [a, b, c] in mesh.triangles.iter() {
+        let [a, b, c] = [a + 1, b + 1, c + 1];
+        if mesh.uvs.is_empty() {
+            writeln!(out, "f {} {} {}", a, b, c)?;
+        } else {
+            writeln!(out, "f {}/{} {}/{} {}/{}", a, a, b, b, c, c)?;
+        }
+    }
+
+    out.flush()
+}
+
+/// writes a mesh as an ASCII PLY, with vertex colors if it has them
+pub fn write_ply<W: Write>(mesh: &Mesh, out: W) -> std::io::Result<()> {
+    let mut out = BufWriter::new(out);
+    let has_colors = !mesh.colors.is_empty();
+
+    writeln!(out, "ply\nformat ascii 1.0")?;
+    writeln!(out, "element vertex {}", mesh.positions.len())?;
+    writeln!(out, "property float x\nproperty float y\nproperty float z")?;
+    if has_colors {
+        writeln!(
+            out,
+            "property uchar red\nproperty uchar green\nproperty uchar blue"
+        )?;
+    }
+    writeln!(out, "element face {}", mesh.triangles.len())?;
+    writeln!(out, "property list uchar uint vertex_indices\nend_header")?;
+
+    for (index, p) in mesh.positions.iter().enumerate() {
+        write!(out, "{} {} {}", p.x, p.y, p.z)?;
+        if has_colors {
+            let [r, g, b] = rgb(mesh.colors[index]);
+            write!(out, " {} {} {}", r, g, b)?;
+        }
+        writeln!(out)?;
+    }
+
+    for [a, b, c] in mesh.triangles.iter() {
+        writeln!(out, "3 {} {} {}", a, b, c)?;
+    }
+
+    out.flush()
+}
@@ -0,0 +1,506 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use super::mesh::Mesh;
+use super::voxbuf::*;
+use glam::Vec3A;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+/// a face direction, as an axis and whether it faces up the axis
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct Facing {
+    axis: usize,
+    positive: bool,
+}
+
+impl Facing {
+    const ALL: [Facing; 6] = [
+        Facing::new(0, false),
+        Facing::new(0, true),
+        Facing::new(1, false),
+        Facing::new(1, true),
+        Facing::new(2, false),
+        Facing::new(2, true),
+    ];
+
+    const fn new(axis: usize, positive: bool) -> Self {
+        Self { axis, positive }
+    }
+
+    /// the in-plane axes, ordered so that u x v points up `axis`
+    fn plane_axes(&self) -> (usize, usize) {
+        ((self.axis + 1) % 3, (self.axis + 2) % 3)
+    }
+
+    fn neighbor(&self, pos: [u32; 3]) -> Option<[u32; 3]> {
+        let mut neighbor = pos;
+        if self.positive {
+            neighbor[self.axis] = pos[self.axis].checked_add(1)?;
+        } else {
+            neighbor[self.axis] = pos[self.axis].checked_sub(1)?;
+        }
+        Some(neighbor)
+    }
+}
+
+/// a merged rectangle of faces in a slice, in the slice's (u, v) grid
+struct Quad {
+    facing: Facing,
+    /// the face plane's position along the facing axis
+    slice: u32,
+    min: [u32; 2],
+    max: [u32; 2],
+    color: u32,
+}
+
+/// greedily merges the faces of one slice into rectangles of one color,
+/// growing each from its lowest face first along u and then along v
+fn merge_faces(facing: Facing, slice: u32, faces: &[([u32; 2], u32)], quads: &mut Vec<Quad>) {
+    let colors: HashMap<[u32; 2], u32> = faces.iter().copied().collect();
+    let mut merged = HashSet::new();
+
+    let mut order: Vec<[u32; 2]> = colors.keys().copied().collect();
+    order.sort_unstable_by_key(|[u, v]| (*v, *u));
+
+    let free = |merged: &HashSet<[u32; 2]>, face: [u32; 2], color: u32| {
+        colors.get(&face) == Some(&color) && !merged.contains(&face)
+    };
+
+    for [u0, v0] in order {
+        if merged.contains(&[u0, v0]) {
+            continue;
+        }
+
+        let color = colors[&[u0, v0]];
+        let mut u1 = u0 + 1;
+        while free(&merged, [u1, v0], color) {
+            u1 += 1;
+        }
+
+        let mut v1 = v0 + 1;
+        while (u0..u1).all(|u| free(&merged, [u, v1], color)) {
+            v1 += 1;
+        }
+
+        for v in v0..v1 {
+            for u in u0..u1 {
+                merged.insert([u, v]);
+            }
+        }
+
+        quads.push(Quad {
+            facing,
+            slice,
+            min: [u0, v0],
+            max: [u1, v1],
+            color,
+        });
+    }
+}
+
+/// extracts the exposed faces of the octree's voxels at `depth` (see
+/// VoxBufView::voxels()) as a world-space mesh, with coplanar faces of the
+/// same color merged into rectangles
+///
+/// every rectangle has its own four vertices, so the vertex colors are the
+/// faces' colors
+pub fn greedy_mesh(vb: &VoxBuf, depth: u32) -> Mesh {
+    assert!(depth < VoxBuf::MAX_DEPTH, "unsupported depth {}", depth);
+    let timer = Instant::now();
+
+    let voxels = vb.voxels(depth);
+
+    // the faces between filled and empty voxels, by direction and slice
+    let mut slices = HashMap::<(Facing, u32), Vec<([u32; 2], u32)>>::new();
+    let mut face_num = 0;
+    for (pos, color) in voxels.iter() {
+        for facing in Facing::ALL.iter() {
+            let exposed = match facing.neighbor(*pos) {
+                Some(neighbor) => !voxels.contains_key(&neighbor),
+                None => true,
+            };
+
+            if !exposed {
+                continue;
+            }
+
+            let (u, v) = facing.plane_axes();
+            let slice = pos[facing.axis] + facing.positive as u32;
+            slices
+                .entry((*facing, slice))
+                .or_default()
+                .push(([pos[u], pos[v]], *color));
+            face_num += 1;
+        }
+    }
+
+    let mut slices: Vec<_> = slices.into_iter().collect();
+    slices.sort_unstable_by_key(|(key, _faces)| *key);
+
+    let mut quads = Vec::new();
+    for ((facing, slice), faces) in slices.iter() {
+        merge_faces(*facing, *slice, faces, &mut quads);
+    }
+
+    let bounds = vb.bounds();
+    let scale = 2.0 / (1u64 << depth) as f32;
+    let mut mesh = Mesh::default();
+    for quad in quads.iter() {
+        let (u, v) = quad.facing.plane_axes();
+        let corner = |cu: u32, cv: u32| {
+            let mut pos = [0.0; 3];
+            pos[quad.facing.axis] = quad.slice as f32;
+            pos[u] = cu as f32;
+            pos[v] = cv as f32;
+            bounds.to_world(Vec3A::from(pos) * scale - 1.0)
+        };
+
+        let [u0, v0] = quad.min;
+        let [u1, v1] = quad.max;
+        let base = mesh.positions.len() as u32;
+        mesh.positions.push(corner(u0, v0));
+        mesh.positions.push(corner(u1, v0));
+        mesh.positions.push(corner(u1, v1));
+        mesh.positions.push(corner(u0, v1));
+        mesh.colors.extend_from_slice(&[quad.color; 4]);
+
+        // counter-clockwise when seen from the side the face is facing
+        if quad.facing.positive {
+            mesh.triangles.push([base, base + 1, base + 2]);
+            mesh.triangles.push([base, base + 2, base + 3]);
+        } else {
+            mesh.triangles.push([base, base + 2, base + 1]);
+            mesh.triangles.push([base, base + 3, base + 2]);
+        }
+    }
+
+    println!("meshed in {:?}", timer.elapsed());
+    println!("{} voxels", voxels.len());
+    println!("{} faces merged into {} quads", face_num, quads.len());
+
+    mesh
+}
+
+/// the cells of the octree cut off at `depth`, where nodes at `depth` count
+/// as leaves and absent children as empty leaves
+struct Sampler<'a> {
+    nodes: &'a [Node],
+    depth: u32,
+}
+
+/// a leaf at a corner of a dual cell, with its center in half-voxels
+#[derive(Clone, Copy)]
+struct Sample {
+    center: [i64; 3],
+    color: u32,
+}
+
+impl Sample {
+    fn is_filled(&self) -> bool {
+        self.color != 0
+    }
+}
+
+impl<'a> Sampler<'a> {
+    /// the color of the first leaf under `node`
+    fn leaf_color(&self, mut node: &'a Node) -> u32 {
+        while !node.is_leaf() {
+            let index = node.occupancy.trailing_zeros() as ChildIndex;
+            node = &self.nodes[node.get_child(index) as usize];
+        }
+        node.data.color
+    }
+
+    /// the leaf containing `voxel`, with an empty leaf's color as zero
+    fn locate(&self, voxel: [u32; 3]) -> (Cell, u32) {
+        let mut node = &self.nodes[VoxBuf::ROOT_NODE as usize];
+        let mut cell = Cell::ROOT;
+        loop {
+            if node.is_leaf() {
+                return (cell, node.data.color);
+            } else if cell.depth == self.depth {
+                return (cell, self.leaf_color(node));
+            }
+
+            let shift = self.depth - cell.depth - 1;
+            let index = (0..3).fold(0, |index, axis| {
+                index | ((voxel[axis] >> shift) & 1) << axis
+            });
+            cell = cell.child(index as ChildIndex);
+            if !node.is_occupied(Node::index_to_mask(index as ChildIndex)) {
+                return (cell, 0);
+            }
+
+            node = &self.nodes[node.get_child(index as ChildIndex) as usize];
+        }
+    }
+
+    /// the leaf containing `voxel`, which may be one voxel outside of the
+    /// grid; outside leaves are empty mirror images of the leaves inside, so
+    /// that the surface closes over the grid's boundary
+    fn sample(&self, voxel: [i64; 3]) -> Sample {
+        let size = 1i64 << self.depth;
+        let mut inside = [0; 3];
+        for axis in 0..3 {
+            let v = voxel[axis];
+            inside[axis] = if v < 0 {
+                -1 - v
+            } else if v >= size {
+                2 * size - 1 - v
+            } else {
+                v
+            } as u32;
+        }
+
+        let (cell, color) = self.locate(inside);
+        let scale = 1i64 << (self.depth - cell.depth);
+        let mut center = [0; 3];
+        for axis in 0..3 {
+            let c = (2 * cell.pos.to_array()[axis] as i64 + 1) * scale;
+            center[axis] = if voxel[axis] < 0 {
+                -c
+            } else if voxel[axis] >= size {
+                4 * size - c
+            } else {
+                c
+            };
+        }
+
+        let outside = voxel != [inside[0] as i64, inside[1] as i64, inside[2] as i64];
+        Sample {
+            center,
+            color: if outside { 0 } else { color },
+        }
+    }
+}
+
+/// the six tetrahedra of a cube split along its main diagonal, by corner
+/// index; neighboring cubes split their shared faces the same way
+const CUBE_TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 1, 5, 7],
+    [0, 2, 3, 7],
+    [0, 2, 6, 7],
+    [0, 4, 5, 7],
+    [0, 4, 6, 7],
+];
+
+/// the number of Taubin smoothing passes applied to isosurfaces
+const SMOOTHING_PASSES: usize = 4;
+
+/// builds an isosurface mesh, welding vertices on the same dual edge
+#[derive(Default)]
+struct SurfaceBuilder {
+    vertices: HashMap<([i64; 3], [i64; 3]), u32>,
+    /// vertex positions in voxels
+    positions: Vec<Vec3A>,
+    colors: Vec<u32>,
+    triangles: Vec<[u32; 3]>,
+}
+
+impl SurfaceBuilder {
+    /// the vertex halfway along the edge from filled `a` to empty `b`
+    fn vertex(&mut self, a: &Sample, b: &Sample) -> u32 {
+        let key = if a.center < b.center {
+            (a.center, b.center)
+        } else {
+            (b.center, a.center)
+        };
+
+        let next = self.positions.len() as u32;
+        let positions = &mut self.positions;
+        let colors = &mut self.colors;
+        *self.vertices.entry(key).or_insert_with(|| {
+            let [ax, ay, az] = a.center;
+            let [bx, by, bz] = b.center;
+            positions.push(Vec3A::new((ax + bx) as f32, (ay + by) as f32, (az + bz) as f32) / 4.0);
+            colors.push(a.color);
+            next
+        })
+    }
+
+    /// adds a triangle facing from `inside` to `outside`, unless it has
+    /// collapsed into a line or point
+    fn triangle(&mut self, mut triangle: [u32; 3], inside: Vec3A, outside: Vec3A) {
+        let [a, b, c] = triangle;
+        if a == b || b == c || c == a {
+            return;
+        }
+
+        let [pa, pb, pc] = [a, b, c].map(|index| self.positions[index as usize]);
+        if (pb - pa).cross(pc - pa).dot(outside - inside) < 0.0 {
+            triangle.swap(1, 2);
+        }
+
+        self.triangles.push(triangle);
+    }
+
+    /// marches one tetrahedron of the dual grid
+    fn tetrahedron(&mut self, corners: [&Sample; 4]) {
+        let (filled, empty): (Vec<&Sample>, Vec<&Sample>) =
+            corners.iter().partition(|sample| sample.is_filled());
+
+        let centroid = |samples: &[&Sample]| {
+            samples.iter().fold(Vec3A::ZERO, |sum, sample| {
+                let [x, y, z] = sample.center;
+                sum + Vec3A::new(x as f32, y as f32, z as f32)
+            }) / samples.len() as f32
+        };
+
+        match (filled.as_slice(), empty.as_slice()) {
+            ([a], [b, c, d]) | ([b, c, d], [a]) => {
+                let (inside, outside) = (centroid(&filled), centroid(&empty));
+                let triangle = if a.is_filled() {
+                    [self.vertex(a, b), self.vertex(a, c), self.vertex(a, d)]
+                } else {
+                    [self.vertex(b, a), self.vertex(c, a), self.vertex(d, a)]
+                };
+                self.triangle(triangle, inside, outside);
+            }
+            ([a, b], [c, d]) => {
+                let (inside, outside) = (centroid(&filled), centroid(&empty));
+                let ac = self.vertex(a, c);
+                let ad = self.vertex(a, d);
+                let bd = self.vertex(b, d);
+                let bc = self.vertex(b, c);
+                self.triangle([ac, ad, bd], inside, outside);
+                self.triangle([ac, bd, bc], inside, outside);
+            }
+            _ => {}
+        }
+    }
+
+    /// Taubin smoothing, which relaxes the surface's stairs without
+    /// shrinking it
+    fn smooth(&mut self, passes: usize) {
+        let mut neighbors = vec![Vec::new(); self.positions.len()];
+        for [a, b, c] in self.triangles.iter() {
+            for (from, to) in [(a, b), (b, c), (c, a)].iter() {
+                neighbors[**from as usize].push(**to);
+                neighbors[**to as usize].push(**from);
+            }
+        }
+
+        for list in neighbors.iter_mut() {
+            list.sort_unstable();
+            list.dedup();
+        }
+
+        for pass in 0..(passes * 2) {
+            let factor = if pass % 2 == 0 { 0.5 } else { -0.53 };
+            let positions = &self.positions;
+            let smoothed = neighbors
+                .iter()
+                .zip(positions.iter())
+                .map(|(list, position)| {
+                    if list.is_empty() {
+                        return *position;
+                    }
+
+                    let sum = list
+                        .iter()
+                        .fold(Vec3A::ZERO, |sum, index| sum + positions[*index as usize]);
+                    let average = sum / list.len() as f32;
+                    *position + (average - *position) * factor
+                })
+                .collect();
+            self.positions = smoothed;
+        }
+    }
+}
+
+/// extracts a smooth surface around the octree's filled leaves as a
+/// world-space mesh, with the tree cut off at `depth`
+///
+/// the surface is marched over the octree's dual grid, whose cells join
+/// the centers of the leaves around each leaf corner, so larger leaves give
+/// larger triangles and leaves of different sizes meet without cracks; the
+/// vertices are then smoothed, and colored by their filled leaves
+pub fn isosurface(vb: &VoxBuf, depth: u32) -> Mesh {
+    assert!(depth < VoxBuf::MAX_DEPTH, "unsupported depth {}", depth);
+    let timer = Instant::now();
+
+    let view = vb.view();
+    let sampler = Sampler {
+        nodes: view.nodes(),
+        depth,
+    };
+
+    // the corners of every leaf, including the empty ones
+    let mut corners = HashSet::new();
+    let mut add_corners = |cell: Cell| {
+        let scale = 1 << (depth - cell.depth);
+        let base = cell.pos * scale;
+        for index in 0..8 {
+            let offset = Cell::ROOT.child(index).pos * scale;
+            corners.insert((base + offset).to_array());
+        }
+    };
+
+    vb.walk_cells(|_node_ref, node, cell| {
+        if node.is_leaf() || cell.depth == depth {
+            add_corners(cell);
+            return false;
+        }
+
+        for index in 0..8 {
+            if !node.is_occupied(Node::index_to_mask(index)) {
+                add_corners(cell.child(index));
+            }
+        }
+
+        true
+    });
+
+    let mut corners: Vec<[u32; 3]> = corners.into_iter().collect();
+    corners.sort_unstable();
+
+    let mut builder = SurfaceBuilder::default();
+    for corner in corners.iter() {
+        let samples: Vec<Sample> = (0..8)
+            .map(|index| {
+                let mut voxel = [0; 3];
+                for axis in 0..3 {
+                    let below = (index >> axis) & 1 == 0;
+                    voxel[axis] = corner[axis] as i64 - below as i64;
+                }
+                sampler.sample(voxel)
+            })
+            .collect();
+
+        let filled = samples.iter().filter(|sample| sample.is_filled()).count();
+        if filled == 0 || filled == 8 {
+            continue;
+        }
+
+        for tetrahedron in CUBE_TETRAHEDRA.iter() {
+            builder.tetrahedron(tetrahedron.map(|index| &samples[index]));
+        }
+    }
+
+    builder.smooth(SMOOTHING_PASSES);
+
+    let bounds = vb.bounds();
+    let scale = 2.0 / (1u64 << depth) as f32;
+    let mesh = Mesh {
+        positions: builder
+            .positions
+            .iter()
+            .map(|position| bounds.to_world(*position * scale - 1.0))
+            .collect(),
+        colors: builder.colors,
+        triangles: builder.triangles,
+        ..Default::default()
+    };
+
+    println!("meshed in {:?}", timer.elapsed());
+    println!("{} dual cells", corners.len());
+    println!(
+        "{} vertices, {} triangles",
+        mesh.positions.len(),
+        mesh.triangles.len()
+    );
+
+    mesh
+}
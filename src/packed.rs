@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! a pointer-less node layout, for drawing with less memory traffic
+//!
+//! nodes are laid out breadth-first, so that the children of a node are
+//! stored next to each other in child index order. each node then only needs
+//! the index of its first child, and the others are found by counting the
+//! occupied children before them.
+
+use super::camera::{Camera, DrawConfig};
+use super::fb::ColorBuffer;
+use super::voxbuf::*;
+use glam::{Vec3A, Vec4};
+use std::collections::VecDeque;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct PackedNode {
+    pub occupancy: ChildMask,
+    /// the index of the lowest occupied child, which the rest follow
+    pub first_child: NodeRef,
+    pub data: Payload,
+}
+
+impl PackedNode {
+    pub fn is_leaf(&self) -> bool {
+        self.occupancy == 0
+    }
+
+    /// the index of an occupied child
+    pub fn get_child(&self, index: ChildIndex) -> NodeRef {
+        let before = self.occupancy & (Node::index_to_mask(index) - 1);
+        self.first_child + before.count_ones()
+    }
+}
+
+/// an octree in the packed layout, which only to_packed() creates
+pub struct PackedVoxBuf {
+    nodes: Vec<PackedNode>,
+    bounds: WorldBounds,
+}
+
+impl VoxBuf {
+    /// copies the tree into the packed layout, expanding any shared subtrees
+    pub fn to_packed(&self) -> PackedVoxBuf {
+        let timer = Instant::now();
+
+        let mut nodes = Vec::new();
+        let mut queue = VecDeque::new();
+        queue.push_back((Self::ROOT_NODE, 0));
+        while let Some((node_ref, depth)) = queue.pop_front() {
+            assert!(depth <= Self::MAX_DEPTH, "unsupported depth {}", depth);
+
+            let node = &self.nodes[node_ref as usize];
+            let first_child = (nodes.len() + 1 + queue.len()) as NodeRef;
+            for index in 0..8 {
+                if node.is_occupied(Node::index_to_mask(index)) {
+                    queue.push_back((node.get_child(index), depth + 1));
+                }
+            }
+
+            nodes.push(PackedNode {
+                occupancy: node.occupancy,
+                first_child,
+                data: node.data,
+            });
+        }
+
+        println!(
+            "packed {} nodes from {} to {} bytes in {:?}",
+            nodes.len(),
+            nodes.len() * std::mem::size_of::<Node>(),
+            nodes.len() * std::mem::size_of::<PackedNode>(),
+            timer.elapsed()
+        );
+
+        PackedVoxBuf {
+            nodes,
+            bounds: self.bounds,
+        }
+    }
+}
+
+impl PackedVoxBuf {
+    pub fn nodes(&self) -> &[PackedNode] {
+        &self.nodes
+    }
+
+    pub fn bounds(&self) -> WorldBounds {
+        self.bounds
+    }
+
+    /// copies the tree back into the usual layout, in the same order
+    pub fn to_voxbuf(&self) -> VoxBuf {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|packed| {
+                let mut node = Node {
+                    occupancy: packed.occupancy,
+                    children: [0; 8],
+                    data: packed.data,
+                };
+                node.for_kids_mut(|index, child| *child = packed.get_child(index));
+                node
+            })
+            .collect();
+
+        VoxBuf {
+            nodes,
+            bounds: self.bounds,
+            free: Vec::new(),
+            journal: None,
+            shared: false,
+        }
+    }
+
+    /// walks the tree like VoxBuf::walk(), visiting the nodes in the same
+    /// order
+    pub fn walk<F>(&self, eye: &Vec3A, mut on_node: F)
+    where
+        F: FnMut(bool, &Payload, Vec4) -> bool,
+    {
+        let timer = Instant::now();
+
+        let mut walked_num = 0;
+        let origin = Vec3A::new(0.0, 0.0, 0.0);
+        let mut stack = vec![(VoxBuf::ROOT_NODE, origin, 0 as u32)];
+
+        while let Some((node_ref, stem, depth)) = stack.pop() {
+            walked_num += 1;
+            let node = &self.nodes[node_ref as usize];
+            let offset = VoxBuf::depth_to_offset(depth);
+            let voxel = stem.extend(offset);
+
+            let is_leaf = node.is_leaf();
+            if on_node(is_leaf, &node.data, voxel) & !is_leaf {
+                let order = Node::sorting_order(eye, &stem);
+                Node::for_occupied_ordered(node.occupancy, order, |index| {
+                    let origin = stem + Node::index_offset(index, offset);
+                    stack.push((node.get_child(index), origin, depth + 1));
+                });
+            }
+        }
+
+        println!("walked {} nodes in {:?}", walked_num, timer.elapsed());
+    }
+
+    /// walks the tree like VoxBufView::fast_walk()
+    fn fast_walk<F>(&self, eye: &Vec3A, mut on_node: F)
+    where
+        F: FnMut(bool, &Payload, Vec4) -> bool,
+    {
+        // to_packed() keeps every child in range and the tree no deeper than
+        // VoxBuf::MAX_DEPTH, which the stack is sized for
+        unsafe {
+            let svo_ptr = self.nodes.as_ptr();
+            let origin = Vec3A::new(0.0, 0.0, 0.0);
+            let mut stack = [(VoxBuf::ROOT_NODE, origin, 0 as u32); 256];
+            let stack_base = stack.as_mut_ptr();
+            let mut stack_ptr = stack_base.add(1);
+
+            while stack_ptr > stack_base {
+                stack_ptr = stack_ptr.sub(1);
+                let (node_ref, stem, depth) = *stack_ptr;
+
+                let node = *svo_ptr.add(node_ref as usize);
+                let offset = VoxBuf::depth_to_offset(depth);
+                let voxel = stem.extend(offset * 1.73);
+
+                let is_leaf = node.is_leaf();
+                if on_node(is_leaf, &node.data, voxel) & !is_leaf {
+                    let order = Node::sorting_order(eye, &stem);
+                    Node::for_occupied_ordered(node.occupancy, order, |index| {
+                        let origin = stem + Node::index_offset(index, offset);
+                        *stack_ptr = (node.get_child(index), origin, depth + 1);
+                        stack_ptr = stack_ptr.add(1);
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn draw(&self, camera: &Camera, config: &DrawConfig, fb: &mut ColorBuffer) {
+        let timer = Instant::now();
+
+        self.fast_walk(&camera.eye, |is_leaf, data, voxel| {
+            camera.draw_voxel(fb, config, is_leaf, &voxel, data)
+        });
+
+        println!("done drawing in {:?}", timer.elapsed());
+    }
+}
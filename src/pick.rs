@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! finding the voxel under a pixel, for selecting voxels with the mouse
+//!
+//! draw_with_ids() fills an IdBuffer with the node and voxel drawn to each
+//! pixel, and pick() reads them back, so picking works the same on trees
+//! whose nodes are shared after compress_dag(). ids are only good for the
+//! tree they were drawn from until it is next edited, since edits can move
+//! nodes.
+
+use super::camera::{Camera, DrawConfig};
+use super::fb::{ColorBuffer, FirstHit};
+use super::voxbuf::*;
+use glam::Vec4;
+use std::cell::Cell;
+use std::time::Instant;
+
+/// the node drawn to each pixel, or INVALID_NODE where nothing has been
+pub struct IdBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<NodeRef>,
+    /// the voxel drawn to each pixel, as VoxBuf::walk() gives it, wherever
+    /// `data` holds a node
+    pub voxels: Vec<Vec4>,
+}
+
+impl IdBuffer {
+    /// an empty buffer the size of a color buffer
+    pub fn new(fb: &ColorBuffer) -> Self {
+        Self {
+            width: fb.width,
+            height: fb.height,
+            data: vec![INVALID_NODE; fb.width * fb.height],
+            voxels: vec![Vec4::ZERO; fb.width * fb.height],
+        }
+    }
+
+    /// the node drawn to a pixel, if anything was
+    pub fn id_at(&self, x: usize, y: usize) -> Option<NodeRef> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        match self.data[y * self.width + x] {
+            INVALID_NODE => None,
+            node_ref => Some(node_ref),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.data.fill(INVALID_NODE);
+    }
+}
+
+/// a picked voxel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelHit {
+    pub node: NodeRef,
+    /// the voxel's center and half-size, as VoxBuf::walk() gives them
+    pub voxel: Vec4,
+    pub data: Payload,
+}
+
+impl VoxBuf {
+    pub fn draw_with_ids(
+        &self,
+        camera: &Camera,
+        config: &DrawConfig,
+        fb: &mut ColorBuffer,
+        ids: &mut IdBuffer,
+    ) {
+        self.view().draw_with_ids(camera, config, fb, ids)
+    }
+
+    pub fn pick(&self, ids: &IdBuffer, x: usize, y: usize) -> Option<VoxelHit> {
+        self.view().pick(ids, x, y)
+    }
+}
+
+impl<'a> VoxBufView<'a> {
+    /// draws like draw(), also filling an id buffer of the same size with
+    /// the node drawn to each pixel
+    pub fn draw_with_ids(
+        &self,
+        camera: &Camera,
+        config: &DrawConfig,
+        fb: &mut ColorBuffer,
+        ids: &mut IdBuffer,
+    ) {
+        let timer = Instant::now();
+
+        // the node and voxel being drawn
+        let drawing = Cell::new((INVALID_NODE, Vec4::ZERO));
+        let size = (ids.width, ids.height);
+        let mut canvas = FirstHit::new(fb, size, |offset, _depth| {
+            let (node, voxel) = drawing.get();
+            ids.data[offset] = node;
+            ids.voxels[offset] = voxel;
+        });
+
+        unsafe {
+            self.fast_walk_refs(&camera.eye, |node_ref, is_leaf, data, voxel| {
+                // the drawn radius is a power of two times 1.73, so this
+                // gives back walk()'s half-size exactly
+                drawing.set((node_ref, voxel.truncate().extend(voxel.w / 1.73)));
+                camera.draw_voxel(&mut canvas, config, is_leaf, &voxel, data)
+            });
+        };
+
+        println!("done drawing with ids in {:?}", timer.elapsed());
+    }
+
+    /// the voxel drawn to a pixel by draw_with_ids(), if any, or None if
+    /// the id buffer is from a tree without that node
+    pub fn pick(&self, ids: &IdBuffer, x: usize, y: usize) -> Option<VoxelHit> {
+        let node = ids.id_at(x, y)?;
+        Some(VoxelHit {
+            node,
+            voxel: ids.voxels[y * ids.width + x],
+            data: self.nodes().get(node as usize)?.data,
+        })
+    }
+}
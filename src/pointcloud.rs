@@ -0,0 +1,586 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! point cloud importers for PLY and XYZ files
+//!
+//! points are streamed into leaves of a chosen world-space size, of which
+//! only the occupied ones are kept, so the size of a cloud's grid never
+//! matters, only the number of leaves its points fall into.
+
+use super::voxbuf::*;
+use glam::{UVec3, Vec3A};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub enum PointCloudError {
+    /// the data does not start with a PLY header, or the header is malformed
+    BadHeader,
+    /// the PLY's vertex properties are not supported
+    Unsupported,
+    /// a line's values could not be parsed
+    Malformed {
+        line: usize,
+    },
+    /// the data ended before every vertex was read
+    Truncated,
+    /// the points span more leaves than an octree can hold, or lie too far
+    /// from the origin to place
+    TooLarge,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for PointCloudError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PointCloudError::BadHeader => write!(f, "invalid PLY header"),
+            PointCloudError::Unsupported => write!(f, "unsupported PLY vertex properties"),
+            PointCloudError::Malformed { line } => write!(f, "malformed point on line {}", line),
+            PointCloudError::Truncated => write!(f, "point data truncated"),
+            PointCloudError::TooLarge => write!(f, "point cloud too large"),
+            PointCloudError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PointCloudError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PointCloudError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PointCloudError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            PointCloudError::Truncated
+        } else {
+            PointCloudError::Io(e)
+        }
+    }
+}
+
+/// the running color sums of one leaf's points
+#[derive(Default)]
+struct LeafColor {
+    sum: [u64; 3],
+    colored: u64,
+}
+
+/// leaves this many leaves or more from the origin are out of range, which
+/// keeps the distances between leaves within an i64
+const MAX_LEAF: f32 = (1u64 << 62) as f32;
+
+/// collects points into leaves of `leaf_size` world units, averaging the
+/// colors of the points in each leaf
+pub struct PointGrid {
+    leaf_size: f32,
+    leaves: HashMap<[i64; 3], LeafColor>,
+    points: u64,
+    /// whether a point fell outside of MAX_LEAF
+    out_of_range: bool,
+}
+
+impl PointGrid {
+    pub fn new(leaf_size: f32) -> Self {
+        assert!(
+            leaf_size.is_finite() && leaf_size > 0.0,
+            "invalid leaf size {}",
+            leaf_size
+        );
+
+        Self {
+            leaf_size,
+            leaves: HashMap::new(),
+            points: 0,
+            out_of_range: false,
+        }
+    }
+
+    /// adds a point with an optional ARGB color; points that are not finite
+    /// are skipped, and ones too far out to place make build() fail
+    pub fn insert(&mut self, point: Vec3A, color: Option<u32>) {
+        if !point.is_finite() {
+            return;
+        }
+
+        let leaf = (point / self.leaf_size).floor();
+        if leaf.abs().max_element() >= MAX_LEAF {
+            self.out_of_range = true;
+            return;
+        }
+
+        let leaf = [leaf.x as i64, leaf.y as i64, leaf.z as i64];
+        let sums = self.leaves.entry(leaf).or_default();
+        if let Some(color) = color {
+            let [_a, r, g, b] = color.to_be_bytes();
+            sums.sum[0] += r as u64;
+            sums.sum[1] += g as u64;
+            sums.sum[2] += b as u64;
+            sums.colored += 1;
+        }
+
+        self.points += 1;
+    }
+
+    /// builds an octree of the occupied leaves, with bounds that put them
+    /// back at the points' world-space positions
+    ///
+    /// leaves without colored points take the default payload color
+    pub fn build(self) -> Result<VoxBuf, PointCloudError> {
+        if self.out_of_range {
+            return Err(PointCloudError::TooLarge);
+        }
+
+        let mut builder = TreeBuilder::default();
+        let mut bounds = WorldBounds::default();
+
+        if let Some(first) = self.leaves.keys().next() {
+            let (min, max) = self
+                .leaves
+                .keys()
+                .fold((*first, *first), |(min, max), leaf| {
+                    let mut bounds = (min, max);
+                    for axis in 0..3 {
+                        bounds.0[axis] = min[axis].min(leaf[axis]);
+                        bounds.1[axis] = max[axis].max(leaf[axis]);
+                    }
+                    bounds
+                });
+
+            let extent = (0..3).map(|axis| max[axis] - min[axis]).max().unwrap() + 1;
+            if extent > 1 << (VoxBuf::MAX_DEPTH - 1) {
+                return Err(PointCloudError::TooLarge);
+            }
+
+            let size = (extent as u32).next_power_of_two();
+            let depth = size.trailing_zeros();
+            for (leaf, sums) in self.leaves.iter() {
+                let color = match sums.colored {
+                    0 => Payload::default().color,
+                    n => sums
+                        .sum
+                        .iter()
+                        .fold(0xff, |color, sum| color << 8 | ((sum + n / 2) / n) as u32),
+                };
+
+                let cell = Cell {
+                    pos: UVec3::new(
+                        (leaf[0] - min[0]) as u32,
+                        (leaf[1] - min[1]) as u32,
+                        (leaf[2] - min[2]) as u32,
+                    ),
+                    depth,
+                };
+                builder.insert(
+                    cell,
+                    Payload {
+                        color,
+                        occlusion: 0,
+                    },
+                );
+            }
+
+            let min = Vec3A::new(min[0] as f32, min[1] as f32, min[2] as f32);
+            bounds = WorldBounds {
+                min: min * self.leaf_size,
+                size: size as f32 * self.leaf_size,
+            };
+        }
+
+        println!("{} points", self.points);
+        println!("{} leaves", self.leaves.len());
+
+        let mut vb = builder.build();
+        vb.set_bounds(bounds);
+        Ok(vb)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, ScalarType::F32 | ScalarType::F64)
+    }
+
+    /// reads one binary value, in the byte order of `format`
+    fn read<R: BufRead>(&self, format: PlyFormat, input: &mut R) -> std::io::Result<f64> {
+        let mut bytes = [0; 8];
+        input.read_exact(&mut bytes[..self.size()])?;
+        if format == PlyFormat::BinaryBigEndian {
+            bytes[..self.size()].reverse();
+        }
+
+        let mut four = [0; 4];
+        four.copy_from_slice(&bytes[..4]);
+        Ok(match self {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes(four) as f64,
+            ScalarType::U32 => u32::from_le_bytes(four) as f64,
+            ScalarType::F32 => f32::from_le_bytes(four) as f64,
+            ScalarType::F64 => f64::from_le_bytes(bytes),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Debug)]
+struct PlyProperty {
+    name: String,
+    kind: PropertyType,
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: u64,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyElement {
+    /// skips over the element's items without reading their values
+    fn skip<R: BufRead>(&self, format: PlyFormat, input: &mut R) -> std::io::Result<()> {
+        let mut line = String::new();
+        for _ in 0..self.count {
+            if format == PlyFormat::Ascii {
+                line.clear();
+                if input.read_line(&mut line)? == 0 {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                continue;
+            }
+
+            for property in self.properties.iter() {
+                match property.kind {
+                    PropertyType::Scalar(kind) => {
+                        kind.read(format, input)?;
+                    }
+                    PropertyType::List { count, item } => {
+                        for _ in 0..(count.read(format, input)? as u64) {
+                            item.read(format, input)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// reads a PLY header up to and including its "end_header" line, returning
+/// the format, the elements and the number of lines read
+fn read_ply_header<R: BufRead>(
+    input: &mut R,
+) -> Result<(PlyFormat, Vec<PlyElement>, usize), PointCloudError> {
+    let mut format = None;
+    let mut elements = Vec::<PlyElement>::new();
+    let mut line = String::new();
+    let mut number = 0;
+
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(PointCloudError::BadHeader);
+        }
+        number += 1;
+
+        let mut words = line.split_whitespace();
+        let keyword = words.next();
+        if number == 1 {
+            if keyword != Some("ply") {
+                return Err(PointCloudError::BadHeader);
+            }
+            continue;
+        }
+
+        match keyword {
+            Some("format") => {
+                format = match words.next() {
+                    Some("ascii") => Some(PlyFormat::Ascii),
+                    Some("binary_little_endian") => Some(PlyFormat::BinaryLittleEndian),
+                    Some("binary_big_endian") => Some(PlyFormat::BinaryBigEndian),
+                    _ => return Err(PointCloudError::BadHeader),
+                };
+            }
+            Some("element") => {
+                let name = words.next().ok_or(PointCloudError::BadHeader)?;
+                let count = words
+                    .next()
+                    .and_then(|count| count.parse().ok())
+                    .ok_or(PointCloudError::BadHeader)?;
+                elements.push(PlyElement {
+                    name: name.into(),
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            Some("property") => {
+                let element = elements.last_mut().ok_or(PointCloudError::BadHeader)?;
+                let words: Vec<&str> = words.collect();
+                let scalar =
+                    |name: &str| ScalarType::from_name(name).ok_or(PointCloudError::BadHeader);
+                let (kind, name) = match words.as_slice() {
+                    ["list", count, item, name] => (
+                        PropertyType::List {
+                            count: scalar(count)?,
+                            item: scalar(item)?,
+                        },
+                        name,
+                    ),
+                    [kind, name] => (PropertyType::Scalar(scalar(kind)?), name),
+                    _ => return Err(PointCloudError::BadHeader),
+                };
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind,
+                });
+            }
+            Some("end_header") => break,
+            Some("comment") | Some("obj_info") | None => {}
+            _ => return Err(PointCloudError::BadHeader),
+        }
+    }
+
+    let format = format.ok_or(PointCloudError::BadHeader)?;
+    Ok((format, elements, number))
+}
+
+/// where a vertex's position and color are among its properties
+struct VertexLayout {
+    position: [usize; 3],
+    /// the color properties, and whether they are floats in [0, 1]
+    color: Option<([usize; 3], bool)>,
+}
+
+impl VertexLayout {
+    fn new(element: &PlyElement) -> Result<Self, PointCloudError> {
+        let find = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|property| names.contains(&property.name.as_str()))
+        };
+
+        let mut position = [0; 3];
+        for (index, name) in ["x", "y", "z"].iter().enumerate() {
+            position[index] = find(&[name]).ok_or(PointCloudError::Unsupported)?;
+        }
+
+        let channels = [
+            find(&["red", "r", "diffuse_red"]),
+            find(&["green", "g", "diffuse_green"]),
+            find(&["blue", "b", "diffuse_blue"]),
+        ];
+
+        let color = match channels {
+            [Some(r), Some(g), Some(b)] => {
+                let is_float = match element.properties[r].kind {
+                    PropertyType::Scalar(kind) => kind.is_float(),
+                    PropertyType::List { .. } => return Err(PointCloudError::Unsupported),
+                };
+                Some(([r, g, b], is_float))
+            }
+            _ => None,
+        };
+
+        Ok(Self { position, color })
+    }
+
+    fn insert(&self, values: &[f64], grid: &mut PointGrid) {
+        let [x, y, z] = self.position.map(|index| values[index] as f32);
+        let color = self.color.map(|(channels, is_float)| {
+            channels.iter().fold(0xff, |color, index| {
+                let scale = if is_float { 255.0 } else { 1.0 };
+                color << 8 | (values[*index] * scale).round().clamp(0.0, 255.0) as u32
+            })
+        });
+        grid.insert(Vec3A::new(x, y, z), color);
+    }
+}
+
+/// imports the vertices of an ASCII or binary PLY as points
+/// of an octree with leaves of `leaf_size` world units
+///
+/// vertices need "x", "y" and "z" properties and may be colored by "red",
+/// "green" and "blue" ones, which are 0-255 integers or floats in [0, 1]
+pub fn import_ply_points<R: BufRead>(
+    mut input: R,
+    leaf_size: f32,
+) -> Result<VoxBuf, PointCloudError> {
+    let timer = Instant::now();
+
+    let (format, elements, mut number) = read_ply_header(&mut input)?;
+    let mut grid = PointGrid::new(leaf_size);
+
+    for element in elements.iter() {
+        if element.name != "vertex" {
+            element.skip(format, &mut input)?;
+            number += element.count as usize;
+            continue;
+        }
+
+        let layout = VertexLayout::new(element)?;
+        let mut values = vec![0.0; element.properties.len()];
+        let mut line = String::new();
+        for _ in 0..element.count {
+            if format == PlyFormat::Ascii {
+                line.clear();
+                if input.read_line(&mut line)? == 0 {
+                    return Err(PointCloudError::Truncated);
+                }
+                number += 1;
+
+                let malformed = || PointCloudError::Malformed { line: number };
+                let mut words = line.split_whitespace().map(|word| word.parse::<f64>());
+                for (value, property) in values.iter_mut().zip(element.properties.iter()) {
+                    let mut next = || match words.next() {
+                        Some(Ok(value)) => Some(value),
+                        _ => None,
+                    };
+
+                    match property.kind {
+                        PropertyType::Scalar(_) => {
+                            *value = next().ok_or_else(malformed)?;
+                        }
+                        PropertyType::List { .. } => {
+                            let count = next().ok_or_else(malformed)?;
+                            for _ in 0..(count as u64) {
+                                next().ok_or_else(malformed)?;
+                            }
+                        }
+                    }
+                }
+            } else {
+                for (value, property) in values.iter_mut().zip(element.properties.iter()) {
+                    match property.kind {
+                        PropertyType::Scalar(kind) => *value = kind.read(format, &mut input)?,
+                        PropertyType::List { count, item } => {
+                            for _ in 0..(count.read(format, &mut input)? as u64) {
+                                item.read(format, &mut input)?;
+                            }
+                        }
+                    }
+                }
+            }
+
+            layout.insert(&values, &mut grid);
+        }
+
+        // the vertices are all we need
+        break;
+    }
+
+    println!("loaded points in {:?}", timer.elapsed());
+    grid.build()
+}
+
+/// parses an XYZ color channel: 0-255 as an integer, or [0, 1] as a float
+fn parse_channel(word: &str) -> Option<u32> {
+    if word.contains(&['.', 'e', 'E'][..]) {
+        let value: f32 = word.parse().ok()?;
+        Some((value * 255.0).round().clamp(0.0, 255.0) as u32)
+    } else {
+        let value: i64 = word.parse().ok()?;
+        Some(value.clamp(0, 255) as u32)
+    }
+}
+
+/// imports an XYZ text file of one point per line as an octree with leaves
+/// of `leaf_size` world units
+///
+/// lines are "x y z", optionally followed by an intensity, by "r g b", or by
+/// an intensity and "r g b", separated by spaces or commas; empty lines and
+/// lines starting with '#' or '//' are skipped
+pub fn import_xyz_points<R: BufRead>(input: R, leaf_size: f32) -> Result<VoxBuf, PointCloudError> {
+    let timer = Instant::now();
+    let mut grid = PointGrid::new(leaf_size);
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("//") {
+            continue;
+        }
+
+        let malformed = || PointCloudError::Malformed { line: number + 1 };
+        let words: Vec<&str> = trimmed
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        let color = match words.len() {
+            3 | 4 => None,
+            6 | 7 => {
+                let channels = &words[(words.len() - 3)..];
+                let color = channels
+                    .iter()
+                    .try_fold(0xff, |color, word| Some(color << 8 | parse_channel(word)?));
+                Some(color.ok_or_else(malformed)?)
+            }
+            _ => return Err(malformed()),
+        };
+
+        let mut position = [0.0; 3];
+        for (axis, word) in words.iter().take(3).enumerate() {
+            position[axis] = word.parse().map_err(|_| malformed())?;
+        }
+
+        grid.insert(Vec3A::from(position), color);
+    }
+
+    println!("loaded points in {:?}", timer.elapsed());
+    grid.build()
+}
@@ -0,0 +1,430 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! terrain from 16-bit heightmaps, read from raw files or PGM images, with
+//! optional PGM or PPM color maps
+//!
+//! each pixel becomes a column of voxels along y, with the map's rows along
+//! z, and the columns are filled solid down to the bottom of the grid.
+
+use super::*;
+use crate::voxbuf::{Cell, Payload, TreeBuilder, WorldBounds};
+use std::fmt;
+use std::io::{BufRead, Read};
+use std::time::Instant;
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    /// the data does not start with a supported PGM or PPM header
+    BadHeader,
+    /// the data ended before every pixel was read
+    Truncated,
+    /// the color map's size differs from the heightmap's
+    SizeMismatch,
+    /// the image has more than MAX_PIXELS pixels
+    TooLarge,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeightmapError::BadHeader => write!(f, "invalid PGM/PPM header"),
+            HeightmapError::Truncated => write!(f, "image data truncated"),
+            HeightmapError::SizeMismatch => write!(f, "color map size does not match heightmap"),
+            HeightmapError::TooLarge => write!(f, "image too large"),
+            HeightmapError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HeightmapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HeightmapError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for HeightmapError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            HeightmapError::Truncated
+        } else {
+            HeightmapError::Io(e)
+        }
+    }
+}
+
+/// the most pixels a heightmap or color map may have, to bound the memory a
+/// header can ask for
+pub const MAX_PIXELS: usize = 1 << 28;
+
+/// the number of samples in an image of the given size, if it is in range
+fn sample_count(width: usize, height: usize, channels: usize) -> Result<usize, HeightmapError> {
+    width
+        .checked_mul(height)
+        .filter(|pixels| *pixels <= MAX_PIXELS)
+        .map(|pixels| pixels * channels)
+        .ok_or(HeightmapError::TooLarge)
+}
+
+/// reads exactly `len` bytes, growing the buffer as they arrive so that a
+/// truncated input never allocates more than it holds
+fn read_bytes<R: Read>(input: R, len: usize) -> Result<Vec<u8>, HeightmapError> {
+    let mut bytes = Vec::new();
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(HeightmapError::Truncated);
+    }
+    Ok(bytes)
+}
+
+/// a decoded PGM or PPM image
+struct Pnm {
+    width: usize,
+    height: usize,
+    /// 1 for grayscale, 3 for RGB
+    channels: usize,
+    maxval: u32,
+    samples: Vec<u16>,
+}
+
+/// reads the next whitespace-separated header token, skipping comments
+fn next_token<R: BufRead>(input: &mut R) -> Result<String, HeightmapError> {
+    let mut token = String::new();
+    let mut comment = false;
+    let mut byte = [0];
+    loop {
+        if input.read(&mut byte)? == 0 {
+            return if token.is_empty() {
+                Err(HeightmapError::Truncated)
+            } else {
+                Ok(token)
+            };
+        }
+
+        let c = byte[0] as char;
+        if comment {
+            comment = c != '\n';
+        } else if c == '#' {
+            comment = true;
+        } else if c.is_ascii_whitespace() {
+            // a single whitespace byte ends the header before binary data
+            if !token.is_empty() {
+                return Ok(token);
+            }
+        } else {
+            token.push(c);
+        }
+    }
+}
+
+fn read_pnm<R: BufRead>(mut input: R) -> Result<Pnm, HeightmapError> {
+    let magic = next_token(&mut input)?;
+    let (channels, binary) = match magic.as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => return Err(HeightmapError::BadHeader),
+    };
+
+    let mut number = || -> Result<usize, HeightmapError> {
+        next_token(&mut input)?
+            .parse()
+            .map_err(|_| HeightmapError::BadHeader)
+    };
+
+    let width = number()?;
+    let height = number()?;
+    let maxval = number()?;
+    if width == 0 || height == 0 || maxval == 0 || maxval > u16::MAX as usize {
+        return Err(HeightmapError::BadHeader);
+    }
+
+    let len = sample_count(width, height, channels)?;
+    let mut samples = Vec::new();
+    if binary {
+        // 16-bit samples are big-endian
+        let size = if maxval < 256 { 1 } else { 2 };
+        let bytes = read_bytes(&mut input, len * size)?;
+        samples.extend(bytes.chunks_exact(size).map(|sample| match sample {
+            [byte] => *byte as u16,
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            _ => unreachable!(),
+        }));
+    } else {
+        for _ in 0..len {
+            let sample = next_token(&mut input)?
+                .parse()
+                .map_err(|_| HeightmapError::BadHeader)?;
+            samples.push(sample);
+        }
+    }
+
+    Ok(Pnm {
+        width,
+        height,
+        channels,
+        maxval: maxval as u32,
+        samples,
+    })
+}
+
+/// a grid of 16-bit heights, with a color per height if it has a color map
+pub struct Heightmap {
+    pub width: usize,
+    /// the number of rows, laid out along z
+    pub height: usize,
+    pub samples: Vec<u16>,
+    pub colors: Option<Vec<u32>>,
+}
+
+impl Heightmap {
+    /// reads `width` x `height` little-endian 16-bit heights, row by row
+    pub fn from_raw16<R: Read>(
+        input: R,
+        width: usize,
+        height: usize,
+    ) -> Result<Self, HeightmapError> {
+        let bytes = read_bytes(input, sample_count(width, height, 2)?)?;
+        let samples = bytes
+            .chunks_exact(2)
+            .map(|sample| u16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            samples,
+            colors: None,
+        })
+    }
+
+    /// reads a binary or ASCII PGM, scaling its samples to the full 16 bits
+    pub fn from_pgm<R: BufRead>(input: R) -> Result<Self, HeightmapError> {
+        let pnm = read_pnm(input)?;
+        if pnm.channels != 1 {
+            return Err(HeightmapError::BadHeader);
+        }
+
+        let samples = pnm
+            .samples
+            .iter()
+            .map(|sample| ((*sample as u32).min(pnm.maxval) * 0xffff / pnm.maxval) as u16)
+            .collect();
+
+        Ok(Self {
+            width: pnm.width,
+            height: pnm.height,
+            samples,
+            colors: None,
+        })
+    }
+
+    /// colors the columns from a PPM, or a PGM for grayscale, of the same
+    /// size as the heightmap
+    pub fn with_color_map<R: BufRead>(mut self, input: R) -> Result<Self, HeightmapError> {
+        let pnm = read_pnm(input)?;
+        if pnm.width != self.width || pnm.height != self.height {
+            return Err(HeightmapError::SizeMismatch);
+        }
+
+        let colors = pnm
+            .samples
+            .chunks_exact(pnm.channels)
+            .map(|pixel| {
+                let channel =
+                    |index: usize| (pixel[index] as u32).min(pnm.maxval) * 255 / pnm.maxval;
+                let (r, g, b) = match pixel.len() {
+                    1 => (channel(0), channel(0), channel(0)),
+                    _ => (channel(0), channel(1), channel(2)),
+                };
+                0xff000000 | (r << 16) | (g << 8) | b
+            })
+            .collect();
+
+        self.colors = Some(colors);
+        Ok(self)
+    }
+
+    /// builds a solid terrain of one voxel per pixel, where a full-scale
+    /// height is `vertical_scale` voxels tall and every column is at least
+    /// one voxel tall
+    ///
+    /// the octree is only subdivided where it crosses the surface, so cells
+    /// entirely below it become single solid leaves; without a color map,
+    /// columns are shaded by their height
+    pub fn build(&self, vertical_scale: f32) -> VoxBuf {
+        assert_eq!(self.samples.len(), self.width * self.height);
+        assert!(
+            vertical_scale.is_finite() && vertical_scale >= 0.0,
+            "invalid vertical scale {}",
+            vertical_scale
+        );
+
+        let timer = Instant::now();
+
+        let builder = ColumnBuilder::new(self, vertical_scale);
+        let mut tree = TreeBuilder::default();
+        let mut leaves = 0;
+        if !self.samples.is_empty() {
+            builder.fill(Cell::ROOT, &mut tree, &mut leaves);
+        }
+
+        println!("generated in {:?}", timer.elapsed());
+        println!("{} columns in {} leaves", self.samples.len(), leaves);
+
+        let mut vb = tree.build();
+
+        // one world unit per voxel, with the ground at y = 0
+        vb.set_bounds(WorldBounds {
+            min: Vec3A::ZERO,
+            size: (1u64 << builder.depth) as f32,
+        });
+        vb
+    }
+}
+
+/// the heightmap's lowest and highest columns over blocks of 2^level pixels
+struct HeightLevel {
+    width: usize,
+    height: usize,
+    ranges: Vec<(u16, u16)>,
+}
+
+struct ColumnBuilder<'a> {
+    map: &'a Heightmap,
+    vertical_scale: f32,
+    depth: u32,
+    levels: Vec<HeightLevel>,
+}
+
+impl<'a> ColumnBuilder<'a> {
+    fn new(map: &'a Heightmap, vertical_scale: f32) -> Self {
+        let mut levels = vec![HeightLevel {
+            width: map.width,
+            height: map.height,
+            ranges: map
+                .samples
+                .iter()
+                .map(|sample| (*sample, *sample))
+                .collect(),
+        }];
+
+        while let Some(last) = levels.last().filter(|last| last.width * last.height > 1) {
+            let width = last.width.div_ceil(2);
+            let height = last.height.div_ceil(2);
+            let mut ranges = Vec::with_capacity(width * height);
+            for z in 0..height {
+                for x in 0..width {
+                    let mut range = (u16::MAX, 0);
+                    for (dx, dz) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                        let (x, z) = (x * 2 + dx, z * 2 + dz);
+                        if x < last.width && z < last.height {
+                            let (min, max) = last.ranges[z * last.width + x];
+                            range = (range.0.min(min), range.1.max(max));
+                        }
+                    }
+                    ranges.push(range);
+                }
+            }
+
+            levels.push(HeightLevel {
+                width,
+                height,
+                ranges,
+            });
+        }
+
+        let mut builder = Self {
+            map,
+            vertical_scale,
+            depth: 0,
+            levels,
+        };
+
+        let tallest = builder.levels.last().map_or(0, |level| level.ranges[0].1);
+        let extent = map
+            .width
+            .max(map.height)
+            .max(builder.column_height(tallest) as usize);
+        let size = extent.next_power_of_two();
+        builder.depth = size.trailing_zeros();
+        assert!(
+            builder.depth < VoxBuf::MAX_DEPTH,
+            "heightmap too large: {} voxels across",
+            extent
+        );
+
+        builder
+    }
+
+    /// the number of voxels filled in a column of the given height
+    fn column_height(&self, sample: u16) -> u32 {
+        1 + (sample as f32 / u16::MAX as f32 * self.vertical_scale) as u32
+    }
+
+    fn column_color(&self, x: usize, z: usize) -> u32 {
+        let index = z * self.map.width + x;
+        match &self.map.colors {
+            Some(colors) => colors[index],
+            None => {
+                let gray = (self.map.samples[index] >> 8) as u32;
+                0xff000000 | (gray << 16) | (gray << 8) | gray
+            }
+        }
+    }
+
+    /// the heights of the lowest and highest columns under the cell, with
+    /// pixels outside of the map as empty columns
+    fn column_range(&self, cell: &Cell) -> (u32, u32) {
+        let scale = 1usize << (self.depth - cell.depth);
+        let (x0, z0) = (cell.pos.x as usize * scale, cell.pos.z as usize * scale);
+
+        let level = (self.depth - cell.depth) as usize;
+        let (min, max) = if level < self.levels.len() {
+            let level = &self.levels[level];
+            level.ranges[(z0 / scale) * level.width + x0 / scale]
+        } else {
+            self.levels.last().unwrap().ranges[0]
+        };
+
+        let inside = x0 + scale <= self.map.width && z0 + scale <= self.map.height;
+        let min = if inside { self.column_height(min) } else { 0 };
+        (min, self.column_height(max))
+    }
+
+    fn fill(&self, cell: Cell, tree: &mut TreeBuilder, leaves: &mut usize) {
+        let scale = 1u32 << (self.depth - cell.depth);
+        let [x, y, z] = (cell.pos * scale).to_array();
+        if x as usize >= self.map.width || z as usize >= self.map.height {
+            return;
+        }
+
+        let (lowest, highest) = self.column_range(&cell);
+        if y >= highest {
+            return;
+        }
+
+        if y + scale <= lowest {
+            let color = self.column_color(x as usize, z as usize);
+            tree.insert(
+                cell,
+                Payload {
+                    color,
+                    occlusion: 0,
+                },
+            );
+            *leaves += 1;
+            return;
+        }
+
+        // a single voxel is always either below or above its column
+        for index in 0..8 {
+            self.fill(cell.child(index), tree, leaves);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+pub mod heightmap;
 pub mod terrain;
 
 pub use crate::voxbuf::{ChildIndex, Node, NodeRef, VoxBuf};
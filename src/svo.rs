@@ -0,0 +1,456 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! the native .svo container
+//!
+//! every field is little-endian. the file starts with a 48-byte header:
+//!
+//! | offset | size | field                                        |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 8    | magic, `b"svo-cpu\0"`                        |
+//! | 8      | 4    | format version (u32, currently 2)            |
+//! | 12     | 4    | size of one node record in bytes (u32, 44)   |
+//! | 16     | 8    | node count (u64)                             |
+//! | 24     | 4    | tree depth, the depth of the deepest leaf    |
+//! | 28     | 12   | world bounds minimum corner (3 x f32)        |
+//! | 40     | 4    | world bounds edge length (f32)               |
+//! | 44     | 4    | CRC-32 of bytes 0..44 and the whole payload  |
+//!
+//! the payload follows at offset 48 as `node count` records of 44 bytes,
+//! laid out the same as `Node`:
+//!
+//! | offset | size | field                                        |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 1    | occupancy mask                               |
+//! | 1      | 3    | zero padding                                 |
+//! | 4      | 32   | children (8 x u32, zero where unoccupied)    |
+//! | 36     | 4    | payload color (ARGB u32)                     |
+//! | 40     | 1    | payload occlusion (u8)                       |
+//! | 41     | 3    | zero padding                                 |
+//!
+//! the records are in the order depth_sort_nodes() leaves them in, with the
+//! root first and every child stored after its parent.
+
+use super::voxbuf::*;
+use glam::Vec3A;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+pub const MAGIC: [u8; 8] = *b"svo-cpu\0";
+pub const VERSION: u32 = 2;
+pub const HEADER_SIZE: usize = 48;
+pub const NODE_SIZE: usize = 44;
+
+const _: () = assert!(std::mem::size_of::<Node>() == NODE_SIZE);
+
+/// the checksummed span of the header, everything before the checksum
+const CHECKED_HEADER_SIZE: usize = 44;
+
+#[derive(Debug)]
+pub enum SvoError {
+    /// the data does not start with the .svo magic number
+    BadMagic,
+    /// the file was written by an older or newer format version
+    UnsupportedVersion(u32),
+    /// the node records are not the size this version expects
+    UnsupportedNodeSize(u32),
+    /// the node count is zero or too large to address
+    InvalidNodeCount(u64),
+    /// the data ended before the header or all of the nodes were read
+    Truncated,
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// the node has a child that is out of range or not after it
+    InvalidNode(NodeRef),
+    /// the stored depth is too deep or does not match the tree
+    InvalidDepth(u32),
+    /// the payload can't be viewed in place, because it isn't aligned for
+    /// `Node` or the host is big-endian
+    NotMappable,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for SvoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SvoError::BadMagic => write!(f, "data is not an svo"),
+            SvoError::UnsupportedVersion(version) => {
+                write!(f, "unsupported svo version {}", version)
+            }
+            SvoError::UnsupportedNodeSize(size) => write!(f, "unsupported node size {}", size),
+            SvoError::InvalidNodeCount(count) => write!(f, "invalid node count {}", count),
+            SvoError::Truncated => write!(f, "svo data truncated"),
+            SvoError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch (expected {:08x}, found {:08x})",
+                expected, found
+            ),
+            SvoError::InvalidNode(node_ref) => write!(f, "invalid children in node {}", node_ref),
+            SvoError::InvalidDepth(depth) => write!(f, "invalid tree depth {}", depth),
+            SvoError::NotMappable => write!(f, "svo payload can't be viewed in place"),
+            SvoError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SvoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SvoError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SvoError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => SvoError::Truncated,
+            _ => SvoError::Io(e),
+        }
+    }
+}
+
+lazy_static! {
+    static ref CRC_TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        for (index, entry) in table.iter_mut().enumerate() {
+            let mut crc = index as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    0xedb88320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+            }
+            *entry = crc;
+        }
+        table
+    };
+}
+
+/// CRC-32 (IEEE 802.3), as used by zip and png
+struct Crc32(u32);
+
+impl Crc32 {
+    fn new() -> Self {
+        Self(!0)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = CRC_TABLE[((self.0 ^ *byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+struct Header {
+    node_count: u64,
+    depth: u32,
+    bounds: WorldBounds,
+    checksum: u32,
+}
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        let [x, y, z] = self.bounds.min.to_array();
+        bytes[0..8].copy_from_slice(&MAGIC);
+        bytes[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[12..16].copy_from_slice(&(NODE_SIZE as u32).to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.node_count.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.depth.to_le_bytes());
+        bytes[28..32].copy_from_slice(&x.to_le_bytes());
+        bytes[32..36].copy_from_slice(&y.to_le_bytes());
+        bytes[36..40].copy_from_slice(&z.to_le_bytes());
+        bytes[40..44].copy_from_slice(&self.bounds.size.to_le_bytes());
+        bytes[44..48].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    /// parses a header, rejecting any the current version can't load
+    fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Result<Self, SvoError> {
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let f32_at = |offset: usize| f32::from_bits(u32_at(offset));
+
+        if bytes[0..8] != MAGIC {
+            return Err(SvoError::BadMagic);
+        }
+
+        let version = u32_at(8);
+        if version != VERSION {
+            return Err(SvoError::UnsupportedVersion(version));
+        }
+
+        let node_size = u32_at(12);
+        if node_size as usize != NODE_SIZE {
+            return Err(SvoError::UnsupportedNodeSize(node_size));
+        }
+
+        let node_count = u64::from_le_bytes(bytes[16..24].try_into().unwrap());
+        if node_count == 0 || node_count > INVALID_NODE as u64 {
+            return Err(SvoError::InvalidNodeCount(node_count));
+        }
+
+        let depth = u32_at(24);
+        if depth > VoxBuf::MAX_DEPTH {
+            return Err(SvoError::InvalidDepth(depth));
+        }
+
+        Ok(Self {
+            node_count,
+            depth,
+            bounds: WorldBounds {
+                min: Vec3A::new(f32_at(28), f32_at(32), f32_at(36)),
+                size: f32_at(40),
+            },
+            checksum: u32_at(44),
+        })
+    }
+}
+
+fn encode_node(node: &Node) -> [u8; NODE_SIZE] {
+    let mut bytes = [0; NODE_SIZE];
+    bytes[0] = node.occupancy;
+    node.for_kids(|index, child| {
+        let offset = 4 + index as usize * 4;
+        bytes[offset..offset + 4].copy_from_slice(&child.to_le_bytes());
+    });
+    bytes[36..40].copy_from_slice(&node.data.color.to_le_bytes());
+    bytes[40] = node.data.occlusion;
+    bytes
+}
+
+fn decode_node(bytes: &[u8; NODE_SIZE]) -> Node {
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let mut node = Node {
+        occupancy: bytes[0],
+        ..Default::default()
+    };
+    node.for_kids_all_mut(|index, _mask, child| {
+        *child = u32_at(4 + index as usize * 4);
+    });
+    node.data.color = u32_at(36);
+    node.data.occlusion = bytes[40];
+    node
+}
+
+/// checks that every child comes after its parent and that the deepest
+/// leaf is `depth` levels down, so that walking the nodes always ends
+fn validate_nodes(nodes: &[Node], depth: u32) -> Result<(), SvoError> {
+    // children come after their parents, so a reverse scan sees them first;
+    // heights are capped by MAX_DEPTH, so a byte per node is enough
+    let mut heights = vec![0u8; nodes.len()];
+    for (node_ref, node) in nodes.iter().enumerate().rev() {
+        let mut valid = true;
+        let mut height = 0;
+        node.for_kids(|_index, child| {
+            let child = *child as usize;
+            if child <= node_ref || child >= nodes.len() {
+                valid = false;
+            } else {
+                height = height.max(heights[child] + 1);
+            }
+        });
+
+        if !valid {
+            return Err(SvoError::InvalidNode(node_ref as NodeRef));
+        }
+
+        if height as u32 > VoxBuf::MAX_DEPTH {
+            return Err(SvoError::InvalidDepth(depth));
+        }
+
+        heights[node_ref] = height;
+    }
+
+    if heights[VoxBuf::ROOT_NODE as usize] as u32 != depth {
+        return Err(SvoError::InvalidDepth(depth));
+    }
+
+    Ok(())
+}
+
+impl VoxBuf {
+    /// the nodes in the order depth_sort_nodes() would leave them in
+    fn depth_sorted_order(&self) -> Vec<NodeRef> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![Self::ROOT_NODE];
+        while let Some(node_ref) = stack.pop() {
+            order.push(node_ref);
+            let mut kids = [0; 8];
+            let mut kid_num = 0;
+            self.nodes[node_ref as usize].for_kids(|_index, child| {
+                kids[kid_num] = *child;
+                kid_num += 1;
+            });
+            stack.extend(kids[..kid_num].iter().rev());
+        }
+        order
+    }
+
+    /// writes the tree as a .svo file
+    pub fn save<W: Write>(&self, out: W) -> std::io::Result<()> {
+        let timer = Instant::now();
+
+        let order = self.depth_sorted_order();
+        let mut remap = vec![INVALID_NODE; self.nodes.len()];
+        for (new_ref, old_ref) in order.iter().enumerate() {
+            remap[*old_ref as usize] = new_ref as NodeRef;
+        }
+
+        let records = order.iter().map(|old_ref| {
+            let mut node = self.nodes[*old_ref as usize];
+            node.for_kids_mut(|_index, child| *child = remap[*child as usize]);
+            encode_node(&node)
+        });
+
+        let mut header = Header {
+            node_count: order.len() as u64,
+            depth: self.depth(),
+            bounds: self.bounds,
+            checksum: 0,
+        };
+
+        let mut crc = Crc32::new();
+        crc.update(&header.to_bytes()[..CHECKED_HEADER_SIZE]);
+        for record in records.clone() {
+            crc.update(&record);
+        }
+        header.checksum = crc.finish();
+
+        let mut out = std::io::BufWriter::new(out);
+        out.write_all(&header.to_bytes())?;
+        for record in records {
+            out.write_all(&record)?;
+        }
+        out.flush()?;
+
+        println!("saved {} nodes in {:?}", order.len(), timer.elapsed());
+
+        Ok(())
+    }
+
+    /// reads a .svo file, checking its checksum and structure
+    pub fn load<R: Read>(input: R) -> Result<VoxBuf, SvoError> {
+        let timer = Instant::now();
+
+        let mut input = std::io::BufReader::new(input);
+        let mut header_bytes = [0; HEADER_SIZE];
+        input.read_exact(&mut header_bytes)?;
+        let header = Header::from_bytes(&header_bytes)?;
+
+        let mut crc = Crc32::new();
+        crc.update(&header_bytes[..CHECKED_HEADER_SIZE]);
+
+        // don't trust the node count with an allocation up front
+        let mut nodes = Vec::new();
+        let mut record = [0; NODE_SIZE];
+        for _ in 0..header.node_count {
+            input.read_exact(&mut record)?;
+            crc.update(&record);
+            nodes.push(decode_node(&record));
+        }
+
+        let found = crc.finish();
+        if found != header.checksum {
+            return Err(SvoError::ChecksumMismatch {
+                expected: header.checksum,
+                found,
+            });
+        }
+
+        validate_nodes(&nodes, header.depth)?;
+
+        println!("loaded {} nodes in {:?}", nodes.len(), timer.elapsed());
+
+        Ok(VoxBuf {
+            nodes,
+            bounds: header.bounds,
+            free: Vec::new(),
+            journal: None,
+            shared: false,
+        })
+    }
+}
+
+impl<'a> VoxBufView<'a> {
+    /// views the nodes of a whole .svo file in place, checking its checksum
+    /// and structure without copying any nodes
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, SvoError> {
+        let timer = Instant::now();
+
+        let (header, view) = Self::map(bytes)?;
+        let payload_size = view.nodes.len() * NODE_SIZE;
+
+        let mut crc = Crc32::new();
+        crc.update(&bytes[..CHECKED_HEADER_SIZE]);
+        crc.update(&bytes[HEADER_SIZE..(HEADER_SIZE + payload_size)]);
+        let found = crc.finish();
+        if found != header.checksum {
+            return Err(SvoError::ChecksumMismatch {
+                expected: header.checksum,
+                found,
+            });
+        }
+
+        validate_nodes(view.nodes, header.depth)?;
+
+        println!("viewed {} nodes in {:?}", view.nodes.len(), timer.elapsed());
+
+        Ok(view)
+    }
+
+    /// views the nodes of a whole .svo file in place, checking only its
+    /// header, for files too large to scan before every use
+    ///
+    /// # Safety
+    ///
+    /// the payload must be one that from_bytes() accepts, such as one
+    /// written by VoxBuf::save() and unchanged since; traversing a corrupt
+    /// payload reads out of bounds
+    pub unsafe fn from_bytes_unchecked(bytes: &'a [u8]) -> Result<Self, SvoError> {
+        Self::map(bytes).map(|(_header, view)| view)
+    }
+
+    fn map(bytes: &'a [u8]) -> Result<(Header, Self), SvoError> {
+        let header_bytes = bytes.get(..HEADER_SIZE).ok_or(SvoError::Truncated)?;
+        let header = Header::from_bytes(header_bytes.try_into().unwrap())?;
+
+        // a payload too large to address can't fit in `bytes` either
+        let node_count = usize::try_from(header.node_count).map_err(|_| SvoError::Truncated)?;
+        let payload_end = node_count
+            .checked_mul(NODE_SIZE)
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+            .ok_or(SvoError::Truncated)?;
+        let payload = bytes
+            .get(HEADER_SIZE..payload_end)
+            .ok_or(SvoError::Truncated)?;
+
+        let aligned = payload.as_ptr().align_offset(std::mem::align_of::<Node>()) == 0;
+        if !aligned || cfg!(target_endian = "big") {
+            return Err(SvoError::NotMappable);
+        }
+
+        // Node is repr(C) with the record layout, and every bit pattern of
+        // its integer fields is valid
+        let nodes =
+            unsafe { std::slice::from_raw_parts(payload.as_ptr() as *const Node, node_count) };
+
+        let view = VoxBufView {
+            nodes,
+            bounds: header.bounds,
+        };
+
+        Ok((header, view))
+    }
+}
@@ -0,0 +1,794 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! MagicaVoxel .vox files
+//!
+//! see https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+//! and MagicaVoxel-file-format-vox-extension.txt in the same repository.
+//!
+//! MagicaVoxel is z-up, so its (x, y, z) becomes the octree's (x, z, -y).
+//! Exported scenes split the grid into 256^3 models, the largest MagicaVoxel
+//! can open, and place them with transform nodes.
+
+use super::voxbuf::*;
+use glam::{IVec3, Vec3A};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::Write;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub enum VoxError {
+    /// the data does not start with a "VOX " header and MAIN chunk
+    BadHeader,
+    /// the data ended in the middle of a chunk
+    Truncated,
+    /// a chunk's contents could not be parsed
+    MalformedChunk([u8; 4]),
+    /// an XYZI chunk came without a SIZE chunk before it
+    MissingSize,
+    /// the scene graph refers to missing nodes or models, has a cycle, or
+    /// places models more times than MAX_SCENE_NODES allows
+    InvalidSceneGraph,
+    /// the placed voxels span more than an octree can hold, or their
+    /// coordinates overflow
+    TooLarge,
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VoxError::BadHeader => write!(f, "data is not a vox"),
+            VoxError::Truncated => write!(f, "vox data truncated"),
+            VoxError::MalformedChunk(id) => {
+                write!(f, "malformed {} chunk", String::from_utf8_lossy(id))
+            }
+            VoxError::MissingSize => write!(f, "XYZI chunk without a SIZE chunk"),
+            VoxError::InvalidSceneGraph => write!(f, "invalid scene graph"),
+            VoxError::TooLarge => write!(f, "vox scene too large"),
+        }
+    }
+}
+
+impl std::error::Error for VoxError {}
+
+/// a palette of ARGB colors, indexed by the voxels' color indices
+pub type Palette = [u32; 256];
+
+const DEFAULT_RAMP: [u32; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+lazy_static! {
+    /// the palette of files without an RGBA chunk: a 6x6x6 color cube
+    /// followed by red, green, blue and gray ramps
+    pub static ref DEFAULT_PALETTE: Palette = {
+        let mut palette = [0; 256];
+        let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+        let mut index = 1;
+        for r in steps.iter() {
+            for g in steps.iter() {
+                for b in steps.iter() {
+                    if index < 216 {
+                        palette[index] = 0xff000000 | (r << 16) | (g << 8) | b;
+                        index += 1;
+                    }
+                }
+            }
+        }
+
+        for (shift, ramp) in [16, 8, 0].iter().zip([216, 226, 236].iter()) {
+            for (i, step) in DEFAULT_RAMP.iter().enumerate() {
+                palette[ramp + i] = 0xff000000 | (step << shift);
+            }
+        }
+
+        for (i, step) in DEFAULT_RAMP.iter().enumerate() {
+            palette[246 + i] = 0xff000000 | (step << 16) | (step << 8) | step;
+        }
+
+        palette
+    };
+}
+
+/// a little-endian reader over a chunk's bytes
+struct Reader<'a> {
+    data: &'a [u8],
+    id: [u8; 4],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], VoxError> {
+        if len > self.data.len() {
+            return Err(VoxError::MalformedChunk(self.id));
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, VoxError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).to_string())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, VoxError> {
+        let num = self.u32()?;
+        let mut dict = HashMap::new();
+        for _ in 0..num {
+            let key = self.string()?;
+            let value = self.string()?;
+            dict.insert(key, value);
+        }
+        Ok(dict)
+    }
+}
+
+struct Chunk<'a> {
+    id: [u8; 4],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    fn reader(&self) -> Reader<'a> {
+        Reader {
+            data: self.content,
+            id: self.id,
+        }
+    }
+}
+
+/// splits the next chunk off the front of `data`
+fn next_chunk<'a>(data: &mut &'a [u8]) -> Result<Chunk<'a>, VoxError> {
+    if data.len() < 12 {
+        return Err(VoxError::Truncated);
+    }
+
+    let id: [u8; 4] = data[0..4].try_into().unwrap();
+    let content_size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+    let children_size = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+    let rest = &data[12..];
+    if content_size > rest.len() || children_size > rest.len() - content_size {
+        return Err(VoxError::Truncated);
+    }
+
+    let (content, rest) = rest.split_at(content_size);
+    let (children, rest) = rest.split_at(children_size);
+    *data = rest;
+
+    Ok(Chunk {
+        id,
+        content,
+        children,
+    })
+}
+
+/// a signed permutation matrix and translation, as stored in nTRN frames
+#[derive(Clone, Copy)]
+struct Transform {
+    rows: [IVec3; 3],
+    translation: IVec3,
+}
+
+impl Transform {
+    const IDENTITY: Transform = Transform {
+        rows: [IVec3::X, IVec3::Y, IVec3::Z],
+        translation: IVec3::ZERO,
+    };
+
+    fn from_frame(frame: &HashMap<String, String>, id: [u8; 4]) -> Result<Self, VoxError> {
+        let malformed = || VoxError::MalformedChunk(id);
+        let mut transform = Self::IDENTITY;
+
+        if let Some(rotation) = frame.get("_r") {
+            let r: u8 = rotation.parse().map_err(|_| malformed())?;
+            let first = (r & 0b11) as usize;
+            let second = ((r >> 2) & 0b11) as usize;
+            if first > 2 || second > 2 || first == second {
+                return Err(malformed());
+            }
+
+            let third = 3 - first - second;
+            for (row, (column, sign_bit)) in
+                [(first, 4), (second, 5), (third, 6)].iter().enumerate()
+            {
+                let mut axis = [0; 3];
+                axis[*column] = if r & (1 << sign_bit) != 0 { -1 } else { 1 };
+                transform.rows[row] = IVec3::from(axis);
+            }
+        }
+
+        if let Some(translation) = frame.get("_t") {
+            let mut t = [0; 3];
+            let mut words = translation.split_whitespace();
+            for axis in t.iter_mut() {
+                *axis = words
+                    .next()
+                    .and_then(|word| word.parse().ok())
+                    .ok_or_else(malformed)?;
+            }
+            transform.translation = IVec3::from(t);
+        }
+
+        Ok(transform)
+    }
+
+    /// transforms a point, failing if its coordinates overflow
+    fn apply(&self, p: IVec3) -> Result<IVec3, VoxError> {
+        let [r0, r1, r2] = self.rows;
+        let dot = |r: IVec3, t: i32| {
+            let wide = |a: i32, b: i32| a as i64 * b as i64;
+            let sum = wide(r.x, p.x) + wide(r.y, p.y) + wide(r.z, p.z) + t as i64;
+            i32::try_from(sum).map_err(|_| VoxError::TooLarge)
+        };
+        let t = self.translation;
+        Ok(IVec3::new(dot(r0, t.x)?, dot(r1, t.y)?, dot(r2, t.z)?))
+    }
+
+    /// the transform applying `child` first and then `self`
+    fn then(&self, child: &Transform) -> Result<Transform, VoxError> {
+        let column = |c: IVec3| {
+            let [r0, r1, r2] = self.rows;
+            IVec3::new(r0.dot(c), r1.dot(c), r2.dot(c))
+        };
+        let [c0, c1, c2] = [
+            column(IVec3::new(
+                child.rows[0].x,
+                child.rows[1].x,
+                child.rows[2].x,
+            )),
+            column(IVec3::new(
+                child.rows[0].y,
+                child.rows[1].y,
+                child.rows[2].y,
+            )),
+            column(IVec3::new(
+                child.rows[0].z,
+                child.rows[1].z,
+                child.rows[2].z,
+            )),
+        ];
+        Ok(Transform {
+            rows: [
+                IVec3::new(c0.x, c1.x, c2.x),
+                IVec3::new(c0.y, c1.y, c2.y),
+                IVec3::new(c0.z, c1.z, c2.z),
+            ],
+            translation: self.apply(child.translation)?,
+        })
+    }
+}
+
+enum SceneNode {
+    Transform {
+        child: i32,
+        layer: i32,
+        hidden: bool,
+        transform: Transform,
+    },
+    Group {
+        children: Vec<i32>,
+    },
+    Shape {
+        models: Vec<i32>,
+    },
+}
+
+struct Model {
+    size: IVec3,
+    /// x, y, z and color index of each voxel
+    voxels: Vec<[u8; 4]>,
+}
+
+/// the contents of a .vox file that matter to the octree
+struct VoxScene {
+    models: Vec<Model>,
+    palette: Palette,
+    nodes: HashMap<i32, SceneNode>,
+    hidden_layers: Vec<i32>,
+}
+
+fn read_vox(data: &[u8]) -> Result<VoxScene, VoxError> {
+    if data.len() < 8 || &data[0..4] != b"VOX " {
+        return Err(VoxError::BadHeader);
+    }
+
+    let mut rest = &data[8..];
+    let main = next_chunk(&mut rest)?;
+    if &main.id != b"MAIN" {
+        return Err(VoxError::BadHeader);
+    }
+
+    let mut scene = VoxScene {
+        models: Vec::new(),
+        palette: *DEFAULT_PALETTE,
+        nodes: HashMap::new(),
+        hidden_layers: Vec::new(),
+    };
+
+    let mut size = None;
+    let mut children = main.children;
+    while !children.is_empty() {
+        let chunk = next_chunk(&mut children)?;
+        let mut reader = chunk.reader();
+        match &chunk.id {
+            b"SIZE" => {
+                let x = reader.i32()?;
+                let y = reader.i32()?;
+                let z = reader.i32()?;
+                size = Some(IVec3::new(x, y, z));
+            }
+            b"XYZI" => {
+                let size = size.take().ok_or(VoxError::MissingSize)?;
+                let num = reader.u32()? as usize;
+                let bytes = reader.bytes(num.checked_mul(4).ok_or(VoxError::Truncated)?)?;
+                let voxels = bytes
+                    .chunks_exact(4)
+                    .map(|v| [v[0], v[1], v[2], v[3]])
+                    .collect();
+                scene.models.push(Model { size, voxels });
+            }
+            b"RGBA" => {
+                let bytes = reader.bytes(256 * 4)?;
+                // palette index i + 1 is stored at entry i
+                for (index, rgba) in bytes.chunks_exact(4).take(255).enumerate() {
+                    let [r, g, b] = [rgba[0] as u32, rgba[1] as u32, rgba[2] as u32];
+                    scene.palette[index + 1] = 0xff000000 | (r << 16) | (g << 8) | b;
+                }
+            }
+            b"nTRN" => {
+                let id = reader.i32()?;
+                let attributes = reader.dict()?;
+                let child = reader.i32()?;
+                let _reserved = reader.i32()?;
+                let layer = reader.i32()?;
+                let frame_num = reader.u32()?;
+                let transform = if frame_num > 0 {
+                    Transform::from_frame(&reader.dict()?, chunk.id)?
+                } else {
+                    Transform::IDENTITY
+                };
+
+                let hidden = attributes.get("_hidden").map(String::as_str) == Some("1");
+                let node = SceneNode::Transform {
+                    child,
+                    layer,
+                    hidden,
+                    transform,
+                };
+                scene.nodes.insert(id, node);
+            }
+            b"nGRP" => {
+                let id = reader.i32()?;
+                let _attributes = reader.dict()?;
+                let num = reader.u32()?;
+                let children = (0..num).map(|_| reader.i32()).collect::<Result<_, _>>()?;
+                scene.nodes.insert(id, SceneNode::Group { children });
+            }
+            b"nSHP" => {
+                let id = reader.i32()?;
+                let _attributes = reader.dict()?;
+                let num = reader.u32()?;
+                let mut models = Vec::new();
+                for _ in 0..num {
+                    models.push(reader.i32()?);
+                    let _attributes = reader.dict()?;
+                }
+                scene.nodes.insert(id, SceneNode::Shape { models });
+            }
+            b"LAYR" => {
+                let id = reader.i32()?;
+                let attributes = reader.dict()?;
+                if attributes.get("_hidden").map(String::as_str) == Some("1") {
+                    scene.hidden_layers.push(id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(scene)
+}
+
+/// the deepest nesting of scene graph nodes followed before assuming a cycle
+const MAX_SCENE_DEPTH: usize = 256;
+
+/// the most scene graph nodes visited while placing models, since groups
+/// that share children can place them exponentially many times
+pub const MAX_SCENE_NODES: usize = 1 << 20;
+
+impl VoxScene {
+    /// calls `f` with each placed model and its world transform
+    fn place_models<F>(&self, mut f: F) -> Result<(), VoxError>
+    where
+        F: FnMut(&Model, &Transform) -> Result<(), VoxError>,
+    {
+        // files from before the scene graph place every model at the origin
+        if self.nodes.is_empty() {
+            for model in self.models.iter() {
+                let transform = Transform {
+                    translation: model.size / 2,
+                    ..Transform::IDENTITY
+                };
+                f(model, &transform)?;
+            }
+            return Ok(());
+        }
+
+        let mut stack = vec![(0, Transform::IDENTITY, 0)];
+        let mut visited = 0;
+        while let Some((id, transform, depth)) = stack.pop() {
+            visited += 1;
+            if depth > MAX_SCENE_DEPTH || visited > MAX_SCENE_NODES {
+                return Err(VoxError::InvalidSceneGraph);
+            }
+
+            match self.nodes.get(&id).ok_or(VoxError::InvalidSceneGraph)? {
+                SceneNode::Transform {
+                    child,
+                    layer,
+                    hidden,
+                    transform: local,
+                } => {
+                    if !*hidden && !self.hidden_layers.contains(layer) {
+                        stack.push((*child, transform.then(local)?, depth + 1));
+                    }
+                }
+                SceneNode::Group { children } => {
+                    for child in children.iter() {
+                        stack.push((*child, transform, depth + 1));
+                    }
+                }
+                SceneNode::Shape { models } => {
+                    for model in models.iter() {
+                        let model = usize::try_from(*model)
+                            .ok()
+                            .and_then(|index| self.models.get(index))
+                            .ok_or(VoxError::InvalidSceneGraph)?;
+                        f(model, &transform)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn import_vox_svo(vox: &[u8]) -> VoxBuf {
+    try_import_vox_svo(vox).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// imports every visible model of a .vox scene into one octree, with each
+/// voxel's color taken from the palette and one leaf per voxel
+pub fn try_import_vox_svo(vox: &[u8]) -> Result<VoxBuf, VoxError> {
+    let timer = Instant::now();
+
+    let scene = read_vox(vox)?;
+
+    // models are centered on their transform's translation
+    let mut voxels = Vec::new();
+    scene.place_models(|model, transform| {
+        for [x, y, z, color] in model.voxels.iter() {
+            let local = IVec3::new(*x as i32, *y as i32, *z as i32) - model.size / 2;
+            let world = transform.apply(local)?;
+            let y = world.y.checked_neg().ok_or(VoxError::TooLarge)?;
+            let color = scene.palette[*color as usize];
+            voxels.push((IVec3::new(world.x, world.z, y), color));
+        }
+        Ok(())
+    })?;
+
+    let mut builder = TreeBuilder::default();
+    let mut bounds = WorldBounds::default();
+
+    if let Some((first, _)) = voxels.first() {
+        let (min, max) = voxels.iter().fold((*first, *first), |(min, max), (p, _)| {
+            (min.min(*p), max.max(*p))
+        });
+        let span = |axis: usize| max[axis] as i64 - min[axis] as i64;
+        let extent = span(0).max(span(1)).max(span(2)) + 1;
+        if extent > 1 << (VoxBuf::MAX_DEPTH - 1) {
+            return Err(VoxError::TooLarge);
+        }
+
+        let extent = extent as u32;
+        let size = extent.next_power_of_two();
+        let depth = size.trailing_zeros();
+        for (p, color) in voxels.iter() {
+            let cell = Cell {
+                pos: (*p - min).as_uvec3(),
+                depth,
+            };
+            builder.insert(
+                cell,
+                Payload {
+                    color: *color,
+                    occlusion: 0,
+                },
+            );
+        }
+
+        // one world unit per voxel
+        bounds = WorldBounds {
+            min: Vec3A::from(min.as_vec3()),
+            size: size as f32,
+        };
+    }
+
+    println!("converted in {:?}", timer.elapsed());
+    println!("{} voxels", voxels.len());
+
+    let mut vb = builder.build();
+    vb.set_bounds(bounds);
+    Ok(vb)
+}
+
+/// the largest model edge length MagicaVoxel supports
+const MAX_MODEL_SIZE: u32 = 256;
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(children.len() as u32).to_le_bytes());
+    out.extend_from_slice(content);
+    out.extend_from_slice(children);
+}
+
+fn write_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_dict(out: &mut Vec<u8>, dict: &[(&str, &str)]) {
+    write_i32(out, dict.len() as i32);
+    for (key, value) in dict.iter() {
+        for string in [key, value].iter() {
+            write_i32(out, string.len() as i32);
+            out.extend_from_slice(string.as_bytes());
+        }
+    }
+}
+
+fn color_distance(a: u32, b: u32) -> u32 {
+    [16, 8, 0]
+        .iter()
+        .map(|shift| {
+            let a = channel(a, *shift) as i32;
+            let b = channel(b, *shift) as i32;
+            ((a - b) * (a - b)) as u32
+        })
+        .sum()
+}
+
+fn channel(color: u32, shift: u32) -> u32 {
+    (color >> shift) & 0xff
+}
+
+/// the shift and extent of the widest RGB channel among the colors
+fn widest_channel(colors: &[(u32, usize)]) -> (u32, u32) {
+    [16, 8, 0]
+        .iter()
+        .map(|shift| {
+            let values = colors.iter().map(|(color, _)| channel(*color, *shift));
+            let extent = values.clone().max().unwrap() - values.min().unwrap();
+            (extent, *shift)
+        })
+        .max()
+        .unwrap()
+}
+
+/// the count-weighted average of the colors
+fn average_color(colors: &[(u32, usize)]) -> u32 {
+    let total: usize = colors.iter().map(|(_, count)| count).sum();
+    [16, 8, 0].iter().fold(0xff000000, |average, shift| {
+        let sum: usize = colors
+            .iter()
+            .map(|(color, count)| channel(*color, *shift) as usize * count)
+            .sum();
+        average | (((sum + total / 2) / total) as u32) << shift
+    })
+}
+
+/// quantizes colors into a palette of up to 255 entries by median cut
+///
+/// the colors, weighted by their counts, are split along their widest channel
+/// until there are enough boxes, and each color then falls back to its
+/// nearest entry; returns the palette and each color's index
+fn quantize(counts: &HashMap<u32, usize>) -> (Vec<u32>, HashMap<u32, u8>) {
+    let mut colors: Vec<(u32, usize)> = counts.iter().map(|(c, n)| (*c, *n)).collect();
+    colors.sort_unstable();
+
+    let mut boxes = Vec::new();
+    if !colors.is_empty() {
+        boxes.push(colors);
+    }
+
+    while boxes.len() < 255 {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(index, colors)| (widest_channel(colors), index))
+            .max();
+
+        let ((_extent, shift), index) = match widest {
+            Some(widest) => widest,
+            None => break,
+        };
+
+        let mut lower = boxes.swap_remove(index);
+        lower.sort_by_key(|(color, _)| channel(*color, shift));
+
+        let total: usize = lower.iter().map(|(_, count)| count).sum();
+        let mut below = 0;
+        let median = lower
+            .iter()
+            .position(|(_, count)| {
+                below += count;
+                below * 2 >= total
+            })
+            .unwrap();
+
+        let upper = lower.split_off((median + 1).min(lower.len() - 1));
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    let palette: Vec<u32> = boxes.iter().map(|colors| average_color(colors)).collect();
+    let indices = counts
+        .keys()
+        .map(|color| {
+            let nearest = palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| color_distance(*color, **entry))
+                .unwrap()
+                .0;
+            (*color, nearest as u8 + 1)
+        })
+        .collect();
+
+    (palette, indices)
+}
+
+/// rasterizes the octree's leaves into a grid of 2^depth voxels per axis (see
+/// VoxBufView::voxels()) and writes them as a .vox scene, with one model per
+/// 256^3 tile of the grid
+pub fn export_vox<W: Write>(vb: &VoxBuf, depth: u32, mut out: W) -> std::io::Result<()> {
+    let timer = Instant::now();
+
+    // voxel coordinates have to fit the scene graph's i32 translations
+    if depth >= i32::BITS - 1 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("unsupported vox depth {}", depth),
+        ));
+    }
+
+    let size = 1u32 << depth;
+
+    let voxels = vb.voxels(depth);
+
+    let mut counts = HashMap::new();
+    for color in voxels.values() {
+        *counts.entry(*color).or_insert(0) += 1;
+    }
+
+    let (palette, indices) = quantize(&counts);
+
+    // octree (x, y, z) is MagicaVoxel (x, -z, y), flipped back into the grid
+    let model_size = size.min(MAX_MODEL_SIZE);
+    let mut tiles = HashMap::<[u32; 3], Vec<[u8; 4]>>::new();
+    for ([x, y, z], color) in voxels.iter() {
+        let pos = [*x, size - 1 - *z, *y];
+        let tile = [
+            pos[0] / model_size,
+            pos[1] / model_size,
+            pos[2] / model_size,
+        ];
+        let voxel = [
+            (pos[0] % model_size) as u8,
+            (pos[1] % model_size) as u8,
+            (pos[2] % model_size) as u8,
+            indices[color],
+        ];
+        tiles.entry(tile).or_default().push(voxel);
+    }
+
+    let mut tiles: Vec<_> = tiles.into_iter().collect();
+    tiles.sort_unstable();
+
+    let mut children = Vec::new();
+    for (_tile, voxels) in tiles.iter() {
+        let mut content = Vec::new();
+        for _ in 0..3 {
+            write_i32(&mut content, model_size as i32);
+        }
+        write_chunk(&mut children, b"SIZE", &content, &[]);
+
+        let mut content = Vec::new();
+        write_i32(&mut content, voxels.len() as i32);
+        for voxel in voxels.iter() {
+            content.extend_from_slice(voxel);
+        }
+        write_chunk(&mut children, b"XYZI", &content, &[]);
+    }
+
+    // a root transform holding a group of one transform and shape per model
+    let mut content = Vec::new();
+    write_i32(&mut content, 0);
+    write_dict(&mut content, &[]);
+    write_i32(&mut content, 1);
+    write_i32(&mut content, -1);
+    write_i32(&mut content, -1);
+    write_i32(&mut content, 1);
+    write_dict(&mut content, &[]);
+    write_chunk(&mut children, b"nTRN", &content, &[]);
+
+    let mut content = Vec::new();
+    write_i32(&mut content, 1);
+    write_dict(&mut content, &[]);
+    write_i32(&mut content, tiles.len() as i32);
+    for index in 0..tiles.len() {
+        write_i32(&mut content, 2 + 2 * index as i32);
+    }
+    write_chunk(&mut children, b"nGRP", &content, &[]);
+
+    for (index, (tile, _voxels)) in tiles.iter().enumerate() {
+        // models are centered on their translation
+        let [x, y, z] = tile.map(|axis| (axis * model_size + model_size / 2) as i32);
+        let translation = format!("{} {} {}", x, y, z);
+
+        let id = 2 + 2 * index as i32;
+        let mut content = Vec::new();
+        write_i32(&mut content, id);
+        write_dict(&mut content, &[]);
+        write_i32(&mut content, id + 1);
+        write_i32(&mut content, -1);
+        write_i32(&mut content, 0);
+        write_i32(&mut content, 1);
+        write_dict(&mut content, &[("_t", &translation)]);
+        write_chunk(&mut children, b"nTRN", &content, &[]);
+
+        let mut content = Vec::new();
+        write_i32(&mut content, id + 1);
+        write_dict(&mut content, &[]);
+        write_i32(&mut content, 1);
+        write_i32(&mut content, index as i32);
+        write_dict(&mut content, &[]);
+        write_chunk(&mut children, b"nSHP", &content, &[]);
+    }
+
+    // palette index i + 1 is stored at entry i
+    let mut content = Vec::new();
+    for entry in 0..256 {
+        let color = palette.get(entry).copied().unwrap_or(0);
+        let [_a, r, g, b] = color.to_be_bytes();
+        content.extend_from_slice(&[r, g, b, 0xff]);
+    }
+    write_chunk(&mut children, b"RGBA", &content, &[]);
+
+    let mut vox = b"VOX ".to_vec();
+    write_i32(&mut vox, 150);
+    write_chunk(&mut vox, b"MAIN", &[], &children);
+    out.write_all(&vox)?;
+
+    println!("exported in {:?}", timer.elapsed());
+    println!("{} voxels in {} models", voxels.len(), tiles.len());
+    println!(
+        "{} colors in {} palette entries",
+        counts.len(),
+        palette.len()
+    );
+
+    Ok(())
+}
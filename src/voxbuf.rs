@@ -2,9 +2,11 @@
 // Copyright (c) 2021 Marceline Cramer
 
 use super::camera::{Camera, DrawConfig};
-use super::fb::ColorBuffer;
-use glam::{Vec3A, Vec4};
+use super::fb::{ColorBuffer, DepthBuffer, FirstHit, Target};
+use super::history::Journal;
+use glam::{UVec3, Vec3A, Vec4};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::time::Instant;
 
@@ -15,22 +17,108 @@ pub type NodeRef = u32;
 
 pub const INVALID_NODE: NodeRef = NodeRef::MAX;
 
+/// places the octree's [-1, 1] cube in world space
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WorldBounds {
+    /// world-space position of the cube's minimum corner
+    pub min: Vec3A,
+    /// world-space edge length of the cube
+    pub size: f32,
+}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self {
+            min: Vec3A::new(-1.0, -1.0, -1.0),
+            size: 2.0,
+        }
+    }
+}
+
+impl WorldBounds {
+    pub fn to_world(&self, local: Vec3A) -> Vec3A {
+        self.min + (local + 1.0) * (self.size * 0.5)
+    }
+
+    pub fn to_local(&self, world: Vec3A) -> Vec3A {
+        (world - self.min) * (2.0 / self.size) - 1.0
+    }
+}
+
+/// a node's integer position in the 2^depth grid of its level
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Cell {
+    pub pos: UVec3,
+    pub depth: u32,
+}
+
+impl Cell {
+    pub const ROOT: Cell = Cell {
+        pos: UVec3::ZERO,
+        depth: 0,
+    };
+
+    pub fn child(&self, index: ChildIndex) -> Cell {
+        let index = index as u32;
+        let offset = UVec3::new(index & 1, (index >> 1) & 1, (index >> 2) & 1);
+        Cell {
+            pos: self.pos * 2 + offset,
+            depth: self.depth + 1,
+        }
+    }
+
+    /// half of the cell's edge length in the local [-1, 1] cube
+    pub fn half_size(&self) -> f32 {
+        VoxBuf::depth_to_offset(self.depth) * 2.0
+    }
+
+    /// the cell's center in the local [-1, 1] cube
+    pub fn center(&self) -> Vec3A {
+        let half_size = self.half_size();
+        Vec3A::from(self.pos.as_vec3() * 2.0 + 1.0) * half_size - 1.0
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct VoxBuf {
-    nodes: Vec<Node>,
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) bounds: WorldBounds,
+    /// unused nodes left behind by edits, for later edits to reuse
+    #[serde(skip)]
+    pub(crate) free: Vec<NodeRef>,
+    /// the changes of the open EditHistory step, if any
+    #[serde(skip)]
+    pub(crate) journal: Option<Journal>,
+    /// whether nodes may have more than one parent, as compress_dag() leaves
+    /// them, so that edits have to expand the tree first
+    #[serde(default)]
+    pub(crate) shared: bool,
 }
 
 impl VoxBuf {
     pub const ROOT_NODE: NodeRef = 0;
 
+    /// the deepest level fast_walk()'s fixed-size stack can hold
+    pub const MAX_DEPTH: u32 = 32;
+
     pub fn new() -> Self {
         Self {
             nodes: vec![Node::default()],
+            bounds: WorldBounds::default(),
+            free: Vec::new(),
+            journal: None,
+            shared: false,
         }
     }
 
     pub fn from_nodes(nodes: Vec<Node>) -> Self {
-        let mut vb = Self { nodes };
+        let mut vb = Self {
+            nodes,
+            bounds: WorldBounds::default(),
+            free: Vec::new(),
+            journal: None,
+            shared: false,
+        };
         let dummy_eye = Vec3A::new(3.0, 2.0, 1.0);
 
         print!("unprocessed:\n  ");
@@ -48,6 +136,27 @@ impl VoxBuf {
         vb.depth_sort_nodes();
         vb.walk_all(&dummy_eye);
 
+        print!("after building lod colors:\n  ");
+        vb.build_lod_colors();
+        vb.walk_all(&dummy_eye);
+
+        vb
+    }
+
+    /// like from_nodes(), but keeps the leaves' colors instead of replacing
+    /// them with breadth_sort_nodes()'s debug colors
+    pub fn from_colored_nodes(nodes: Vec<Node>) -> Self {
+        let mut vb = Self {
+            nodes,
+            bounds: WorldBounds::default(),
+            free: Vec::new(),
+            journal: None,
+            shared: false,
+        };
+
+        vb.cull_unfilled();
+        vb.depth_sort_nodes();
+        vb.build_lod_colors();
         vb
     }
 
@@ -62,9 +171,26 @@ impl VoxBuf {
 
         Self {
             nodes: vec![root_node, leaf_node],
+            bounds: WorldBounds::default(),
+            free: Vec::new(),
+            journal: None,
+            shared: false,
         }
     }
 
+    pub fn bounds(&self) -> WorldBounds {
+        self.bounds
+    }
+
+    pub fn set_bounds(&mut self, bounds: WorldBounds) {
+        self.bounds = bounds;
+    }
+
+    /// the depth of the deepest leaf
+    pub fn depth(&self) -> u32 {
+        self.view().depth()
+    }
+
     pub fn cull_unfilled(&mut self) {
         let timer = Instant::now();
         self.cull_unfilled_children(Self::ROOT_NODE);
@@ -101,7 +227,7 @@ impl VoxBuf {
     }
 
     /// depth-sorts nodes
-    /// also removes unused nodes
+    /// also removes unused nodes, including those on the free-list
     pub fn depth_sort_nodes(&mut self) {
         let timer = Instant::now();
 
@@ -117,6 +243,8 @@ impl VoxBuf {
         );
 
         self.nodes = nodes;
+        self.free.clear();
+        self.shared = false;
     }
 
     fn depth_sort_sub(&mut self, nodes: &mut Vec<Node>, old_ref: NodeRef) -> NodeRef {
@@ -131,7 +259,7 @@ impl VoxBuf {
     }
 
     /// breadth-sorts nodes
-    /// also removes unused nodes
+    /// also removes unused nodes, including those on the free-list
     pub fn breadth_sort_nodes(&mut self) {
         let timer = Instant::now();
 
@@ -171,6 +299,50 @@ impl VoxBuf {
         );
 
         self.nodes = nodes;
+        self.free.clear();
+        self.shared = false;
+    }
+
+    /// sets every branch's payload to the average of its children's,
+    /// weighted by how much of each child's cell is filled, so that branches
+    /// drawn as a level of detail take the colors of the voxels below them
+    ///
+    /// edits don't keep these up to date, so this needs to be run again after
+    /// them for the branches to match
+    pub fn build_lod_colors(&mut self) {
+        let timer = Instant::now();
+        let mut coverage = vec![None; self.nodes.len()];
+        self.build_lod_colors_sub(Self::ROOT_NODE, &mut coverage);
+        println!("built lod colors in {:?}", timer.elapsed());
+    }
+
+    /// returns the fraction of the node's cell that is filled, which is
+    /// found once per node, so that the subtrees that compress_dag() shares
+    /// aren't averaged again for every parent
+    fn build_lod_colors_sub(&mut self, node_ref: NodeRef, coverage: &mut [Option<f32>]) -> f32 {
+        if let Some(coverage) = coverage[node_ref as usize] {
+            return coverage;
+        }
+
+        let node = self.nodes[node_ref as usize];
+        let filled = if node.is_leaf() {
+            LodAverage::leaf_coverage(&node)
+        } else {
+            let mut average = LodAverage::default();
+            node.for_kids(|_index, child| {
+                let filled = self.build_lod_colors_sub(*child, coverage);
+                average.add(filled, &self.nodes[*child as usize].data);
+            });
+
+            if let Some(data) = average.payload() {
+                self.nodes[node_ref as usize].data = data;
+            }
+
+            average.coverage()
+        };
+
+        coverage[node_ref as usize] = Some(filled);
+        filled
     }
 
     pub fn depth_to_offset(depth: u32) -> f32 {
@@ -181,6 +353,98 @@ impl VoxBuf {
         }
     }
 
+    /// borrows the nodes as a view, which holds the traversal code
+    pub fn view(&self) -> VoxBufView<'_> {
+        VoxBufView {
+            nodes: &self.nodes,
+            bounds: self.bounds,
+        }
+    }
+
+    pub fn walk<F>(&self, eye: &Vec3A, on_node: F)
+    where
+        F: FnMut(bool, &Payload, Vec4) -> bool,
+    {
+        self.view().walk(eye, on_node)
+    }
+
+    /// # Safety
+    ///
+    /// see VoxBufView::fast_walk()
+    pub unsafe fn fast_walk<F>(&self, eye: &Vec3A, on_node: F)
+    where
+        F: FnMut(bool, &Payload, Vec4) -> bool,
+    {
+        self.view().fast_walk(eye, on_node)
+    }
+
+    pub fn walk_cells<F>(&self, on_node: F)
+    where
+        F: FnMut(NodeRef, &Node, Cell) -> bool,
+    {
+        self.view().walk_cells(on_node)
+    }
+
+    pub fn voxels(&self, depth: u32) -> HashMap<[u32; 3], u32> {
+        self.view().voxels(depth)
+    }
+
+    pub fn walk_all(&self, eye: &Vec3A) -> Vec<(Payload, Vec4)> {
+        self.view().walk_all(eye)
+    }
+
+    pub fn draw(&self, camera: &Camera, config: &DrawConfig, fb: &mut ColorBuffer) {
+        self.view().draw(camera, config, fb)
+    }
+
+    pub fn draw_with_depth(
+        &self,
+        camera: &Camera,
+        config: &DrawConfig,
+        fb: &mut ColorBuffer,
+        depth: &mut DepthBuffer,
+    ) {
+        self.view().draw_with_depth(camera, config, fb, depth)
+    }
+
+    pub fn draw_parallel(
+        &self,
+        camera: &Camera,
+        config: &DrawConfig,
+        fb: &mut ColorBuffer,
+        threads: usize,
+    ) {
+        self.view().draw_parallel(camera, config, fb, threads)
+    }
+}
+
+/// a read-only octree over borrowed nodes, such as those of a memory-mapped
+/// .svo file (see VoxBufView::from_bytes())
+#[derive(Clone, Copy)]
+pub struct VoxBufView<'a> {
+    pub(crate) nodes: &'a [Node],
+    pub(crate) bounds: WorldBounds,
+}
+
+impl<'a> VoxBufView<'a> {
+    pub fn nodes(&self) -> &'a [Node] {
+        self.nodes
+    }
+
+    pub fn bounds(&self) -> WorldBounds {
+        self.bounds
+    }
+
+    /// the depth of the deepest leaf
+    pub fn depth(&self) -> u32 {
+        let mut depth = 0;
+        self.walk_cells(|_node_ref, _node, cell| {
+            depth = depth.max(cell.depth);
+            true
+        });
+        depth
+    }
+
     pub fn walk<F>(&self, eye: &Vec3A, mut on_node: F)
     where
         F: FnMut(bool, &Payload, Vec4) -> bool,
@@ -190,12 +454,12 @@ impl VoxBuf {
         let mut walked_num = 0;
         let mut leaf_num = 0;
         let origin = Vec3A::new(0.0, 0.0, 0.0);
-        let mut stack = vec![(Self::ROOT_NODE, origin, 0 as u32)];
+        let mut stack = vec![(VoxBuf::ROOT_NODE, origin, 0 as u32)];
 
         while let Some((node_ref, stem, depth)) = stack.pop() {
             walked_num += 1;
             let node = self.nodes.get(node_ref as usize).unwrap();
-            let offset = Self::depth_to_offset(depth);
+            let offset = VoxBuf::depth_to_offset(depth);
             let voxel = stem.extend(offset);
 
             let is_leaf = node.is_leaf();
@@ -220,13 +484,31 @@ impl VoxBuf {
         );
     }
 
+    /// # Safety
+    ///
+    /// every occupied child must be in range and the tree no deeper than
+    /// VoxBuf::MAX_DEPTH, as checked when loading or viewing a .svo file
     pub unsafe fn fast_walk<F>(&self, eye: &Vec3A, mut on_node: F)
     where
         F: FnMut(bool, &Payload, Vec4) -> bool,
+    {
+        self.fast_walk_refs(eye, |_node_ref, is_leaf, data, voxel| {
+            on_node(is_leaf, data, voxel)
+        })
+    }
+
+    /// walks like fast_walk(), also passing each node's ref to `on_node`
+    ///
+    /// # Safety
+    ///
+    /// the same as fast_walk()
+    pub unsafe fn fast_walk_refs<F>(&self, eye: &Vec3A, mut on_node: F)
+    where
+        F: FnMut(NodeRef, bool, &Payload, Vec4) -> bool,
     {
         let svo_ptr = self.nodes.as_ptr();
         let origin = Vec3A::new(0.0, 0.0, 0.0);
-        let mut stack = [(svo_ptr.add(Self::ROOT_NODE as usize), origin, 0 as u32); 256];
+        let mut stack = [(svo_ptr.add(VoxBuf::ROOT_NODE as usize), origin, 0 as u32); 256];
         let stack_base = stack.as_mut_ptr();
         let mut stack_ptr = stack_base.add(1);
 
@@ -235,12 +517,13 @@ impl VoxBuf {
             let (node_ptr, stem, depth) = *stack_ptr;
 
             let node = *node_ptr;
-            let offset = Self::depth_to_offset(depth);
+            let offset = VoxBuf::depth_to_offset(depth);
             // TODO bit magic 2.0 ^ -depth * sqrt(3)
             let voxel = stem.extend(offset * 1.73);
 
+            let node_ref = node_ptr.offset_from(svo_ptr) as NodeRef;
             let is_leaf = node.is_leaf();
-            if on_node(is_leaf, &node.data, voxel) & !is_leaf {
+            if on_node(node_ref, is_leaf, &node.data, voxel) & !is_leaf {
                 let order = Node::sorting_order(&eye, &stem);
                 let next_level = depth + 1;
                 node.for_kids_ordered(order, |index, child| {
@@ -253,6 +536,61 @@ impl VoxBuf {
         }
     }
 
+    /// walks every node depth-first with its grid cell, descending into a
+    /// node's children only when `on_node` returns true
+    pub fn walk_cells<F>(&self, mut on_node: F)
+    where
+        F: FnMut(NodeRef, &Node, Cell) -> bool,
+    {
+        let mut stack = vec![(VoxBuf::ROOT_NODE, Cell::ROOT)];
+        while let Some((node_ref, cell)) = stack.pop() {
+            let node = &self.nodes[node_ref as usize];
+            if on_node(node_ref, node, cell) {
+                node.for_kids(|index, child| {
+                    stack.push((*child, cell.child(index)));
+                });
+            }
+        }
+    }
+
+    /// the filled voxels of a grid of 2^depth voxels per axis, with their
+    /// colors
+    ///
+    /// a leaf above `depth` fills its whole block of voxels, and a leaf
+    /// below it is merged into its ancestor at `depth`, which takes the color
+    /// of the first such leaf found
+    pub fn voxels(&self, depth: u32) -> HashMap<[u32; 3], u32> {
+        let mut voxels = HashMap::new();
+        self.walk_cells(|_node_ref, node, cell| {
+            if !node.is_leaf() {
+                return true;
+            }
+
+            if node.data.is_empty() {
+                return false;
+            }
+
+            if cell.depth > depth {
+                let pos = cell.pos / (1 << (cell.depth - depth));
+                voxels.entry(pos.to_array()).or_insert(node.data.color);
+                return false;
+            }
+
+            let scale = 1 << (depth - cell.depth);
+            let [x0, y0, z0] = (cell.pos * scale).to_array();
+            for z in z0..(z0 + scale) {
+                for y in y0..(y0 + scale) {
+                    for x in x0..(x0 + scale) {
+                        voxels.insert([x, y, z], node.data.color);
+                    }
+                }
+            }
+
+            false
+        });
+        voxels
+    }
+
     pub fn walk_all(&self, eye: &Vec3A) -> Vec<(Payload, Vec4)> {
         let mut nodes = Vec::<(Payload, Vec4)>::new();
         self.walk(eye, |is_leaf, data, voxel| {
@@ -269,26 +607,220 @@ impl VoxBuf {
 
         unsafe {
             self.fast_walk(&camera.eye, |is_leaf, data, voxel| {
-                camera.draw_voxel(fb, config, is_leaf, &voxel, data.color)
+                camera.draw_voxel(fb, config, is_leaf, &voxel, data)
             });
         };
 
         println!("done drawing in {:?}", timer.elapsed());
     }
+
+    /// draws like draw(), also filling a depth buffer of the same size with
+    /// the linear eye-space depth of the center of the voxel drawn to each
+    /// pixel
+    pub fn draw_with_depth(
+        &self,
+        camera: &Camera,
+        config: &DrawConfig,
+        fb: &mut ColorBuffer,
+        depth: &mut DepthBuffer,
+    ) {
+        let timer = Instant::now();
+
+        let size = (depth.width, depth.height);
+        let mut canvas = FirstHit::new(fb, size, |offset, drawn| {
+            DepthBuffer::draw(&mut depth.data[offset], drawn)
+        });
+        unsafe {
+            self.fast_walk(&camera.eye, |is_leaf, data, voxel| {
+                camera.draw_voxel(&mut canvas, config, is_leaf, &voxel, data)
+            });
+        };
+
+        println!("done drawing with depth in {:?}", timer.elapsed());
+    }
+
+    /// draws like draw(), with the framebuffer split into a band of rows
+    /// for each of `threads` threads
+    ///
+    /// each thread walks only the nodes that can reach its own band, and
+    /// culls them by where their descendants can be drawn instead of by
+    /// their own splats, as draw() does, so that no band culls anything
+    /// another band would draw. the image is the same on any number of
+    /// threads, but can differ from draw()'s in the few pixels that its
+    /// culling gets wrong.
+    pub fn draw_parallel(
+        &self,
+        camera: &Camera,
+        config: &DrawConfig,
+        fb: &mut ColorBuffer,
+        threads: usize,
+    ) {
+        let timer = Instant::now();
+
+        let threads = threads.max(1);
+        let rows = fb.height.div_ceil(threads);
+        std::thread::scope(|scope| {
+            for mut band in fb.bands(rows) {
+                scope.spawn(move || unsafe {
+                    self.fast_walk(&camera.eye, |is_leaf, data, voxel| {
+                        camera.draw_voxel_exact(&mut band, config, is_leaf, &voxel, data)
+                    });
+                });
+            }
+        });
+
+        println!(
+            "done drawing on {} threads in {:?}",
+            threads,
+            timer.elapsed()
+        );
+    }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+/// builds a sparse octree one leaf at a time, creating only the interior
+/// nodes on the paths down to the inserted leaves
+pub struct TreeBuilder {
+    nodes: Vec<Node>,
+}
+
+impl Default for TreeBuilder {
+    fn default() -> Self {
+        let mut root = Node::default();
+        root.data.color = 0;
+        Self { nodes: vec![root] }
+    }
+}
+
+impl TreeBuilder {
+    /// the placeholder color of interior nodes, until build() replaces it
+    /// with their lod colors
+    pub const INTERIOR_COLOR: u32 = 0xff00ffff;
+
+    /// sets the leaf at `cell` to `data`, returning its node
+    pub fn insert(&mut self, cell: Cell, data: Payload) -> NodeRef {
+        let mut node_ref = VoxBuf::ROOT_NODE;
+        for level in (0..cell.depth).rev() {
+            let index = (((cell.pos.x >> level) & 1)
+                | (((cell.pos.y >> level) & 1) << 1)
+                | (((cell.pos.z >> level) & 1) << 2)) as ChildIndex;
+            let mask = Node::index_to_mask(index);
+
+            let next_ref = self.nodes.len() as NodeRef;
+            let node = &mut self.nodes[node_ref as usize];
+            node.data.color = Self::INTERIOR_COLOR;
+            if node.is_occupied(mask) {
+                node_ref = node.children[index as usize];
+            } else {
+                node.occupancy |= mask;
+                node.children[index as usize] = next_ref;
+                self.nodes.push(Node::default());
+                node_ref = next_ref;
+            }
+        }
+
+        self.nodes[node_ref as usize].data = data;
+        node_ref
+    }
+
+    pub fn payload_mut(&mut self, node_ref: NodeRef) -> &mut Payload {
+        &mut self.nodes[node_ref as usize].data
+    }
+
+    pub fn build(self) -> VoxBuf {
+        VoxBuf::from_colored_nodes(self.nodes)
+    }
+}
+
+/// the average of a branch's children's payloads, weighted by how much of
+/// each child's cell is filled, which is how a branch stands in for the
+/// voxels below it when drawn as a level of detail
+#[derive(Default)]
+pub(crate) struct LodAverage {
+    filled: f32,
+    channels: [f32; 4],
+    occlusion: f32,
+}
+
+impl LodAverage {
+    /// the fraction of a leaf's cell that is filled
+    pub(crate) fn leaf_coverage(leaf: &Node) -> f32 {
+        if leaf.data.is_empty() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    /// adds a child, `coverage` of whose cell is filled
+    pub(crate) fn add(&mut self, coverage: f32, data: &Payload) {
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            *channel += ((data.color >> (index * 8)) & 0xff) as f32 * coverage;
+        }
+        self.occlusion += data.occlusion as f32 * coverage;
+        self.filled += coverage;
+    }
+
+    /// the fraction of the branch's cell that is filled
+    pub(crate) fn coverage(&self) -> f32 {
+        self.filled / 8.0
+    }
+
+    /// the averaged payload, or None if nothing below the branch is filled
+    pub(crate) fn payload(&self) -> Option<Payload> {
+        if self.filled <= 0.0 {
+            return None;
+        }
+
+        let average = |sum: f32| (sum / self.filled).round() as u32;
+        let color = self
+            .channels
+            .iter()
+            .enumerate()
+            .fold(0, |color, (index, sum)| {
+                color | average(*sum) << (index * 8)
+            });
+        Some(Payload {
+            color,
+            occlusion: average(self.occlusion) as u8,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[repr(C)]
 pub struct Payload {
     pub color: u32,
+    /// how much ambient light the voxel's surroundings block, from none at
+    /// 0 to all of it at 255 (see bake_occlusion())
+    pub occlusion: u8,
+}
+
+impl Payload {
+    /// unfilled leaves are marked by a zero color (see cull_unfilled())
+    pub fn is_empty(&self) -> bool {
+        self.color == 0
+    }
+
+    /// the color darkened by the occlusion, keeping its alpha
+    pub fn occluded_color(&self) -> u32 {
+        let open = 255 - self.occlusion as u32;
+        let channel = |shift: u32| (((self.color >> shift) & 0xff) * open / 255) << shift;
+        (self.color & 0xff000000) | channel(16) | channel(8) | channel(0)
+    }
 }
 
 impl Default for Payload {
     fn default() -> Self {
-        Self { color: 0xff0000ff }
+        Self {
+            color: 0xff0000ff,
+            occlusion: 0,
+        }
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+/// the layout is fixed so that .svo payloads can be used in place
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[repr(C)]
 pub struct Node {
     pub occupancy: ChildMask,
     pub children: [NodeRef; 8],
@@ -387,12 +919,23 @@ impl Node {
     where
         F: FnMut(ChildIndex, &NodeRef),
     {
-        if !self.is_leaf() {
-            let occupancy = self.occupancy.clone();
+        let children = &self.children;
+        Self::for_occupied_ordered(self.occupancy, order, |index| {
+            f(index, &children[index as usize])
+        });
+    }
+
+    /// visits the children set in an occupancy mask in the same order as
+    /// for_kids_ordered(), for layouts without a children array
+    pub fn for_occupied_ordered<F>(occupancy: ChildMask, order: ChildOrder, mut f: F)
+    where
+        F: FnMut(ChildIndex),
+    {
+        if occupancy != 0 {
             let indices = SORTED_ORDER_INDICES[order as usize].clone();
             for (index, mask) in indices.iter() {
                 if (occupancy & *mask) != 0 {
-                    f(*index, &self.children[*index as usize]);
+                    f(*index);
                 }
             }
         }
@@ -0,0 +1,336 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use super::mesh::Mesh;
+use super::voxbuf::*;
+use glam::Vec3A;
+use std::time::Instant;
+
+type Triangle = [Vec3A; 3];
+
+/// whether a triangle touches the cube of half-size `half` around `center`,
+/// by the separating axis test of Akenine-Möller's "Fast 3D Triangle-Box
+/// Overlap Testing"
+fn triangle_overlaps_cube(triangle: &Triangle, center: Vec3A, half: f32) -> bool {
+    let v = [
+        triangle[0] - center,
+        triangle[1] - center,
+        triangle[2] - center,
+    ];
+
+    // separated along a projection onto `axis` from the box's [-r, r]
+    let separated = |axis: Vec3A| {
+        let p = [axis.dot(v[0]), axis.dot(v[1]), axis.dot(v[2])];
+        let r = half * axis.abs().dot(Vec3A::ONE);
+        p[0].min(p[1]).min(p[2]) > r || p[0].max(p[1]).max(p[2]) < -r
+    };
+
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+    let axes = [Vec3A::X, Vec3A::Y, Vec3A::Z];
+
+    if axes.iter().any(|axis| separated(*axis)) {
+        return false;
+    }
+
+    if separated(edges[0].cross(edges[1])) {
+        return false;
+    }
+
+    !edges
+        .iter()
+        .any(|edge| axes.iter().any(|axis| separated(axis.cross(*edge))))
+}
+
+/// the barycentric coordinates of the point on a triangle closest to `p`,
+/// from Ericson's "Real-Time Collision Detection"
+fn closest_barycentric(triangle: &Triangle, p: Vec3A) -> Vec3A {
+    let [a, b, c] = *triangle;
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return Vec3A::X;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return Vec3A::Y;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 && d1 > d3 {
+        let v = d1 / (d1 - d3);
+        return Vec3A::new(1.0 - v, v, 0.0);
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return Vec3A::Z;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 && d2 > d6 {
+        let w = d2 / (d2 - d6);
+        return Vec3A::new(1.0 - w, 0.0, w);
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 && (d4 - d3) + (d5 - d6) > 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return Vec3A::new(0.0, 1.0 - w, w);
+    }
+
+    // the edge regions skip zero-length edges, leaving degenerate
+    // triangles to fall back to their first vertex
+    let denom = va + vb + vc;
+    if denom == 0.0 {
+        return Vec3A::X;
+    }
+
+    let v = vb / denom;
+    let w = vc / denom;
+    Vec3A::new(1.0 - v - w, v, w)
+}
+
+/// the signed area of the parallelogram spanned by `a -> b` and `a -> p`, in
+/// the yz plane
+///
+/// the edge is always evaluated in the same direction, so triangles sharing
+/// it see exactly opposite signs; the returned flag is whether the point is
+/// owned by the edge when it lies exactly on it, true for just one direction
+fn edge_function(a: Vec3A, b: Vec3A, p: Vec3A) -> (f32, bool) {
+    let forward = (a.y, a.z) < (b.y, b.z);
+    let (a, b) = if forward { (a, b) } else { (b, a) };
+    let w = (b.y - a.y) * (p.z - a.z) - (b.z - a.z) * (p.y - a.y);
+    if forward {
+        (w, true)
+    } else {
+        (-w, false)
+    }
+}
+
+/// the triangles binned by their extent in the yz plane, for casting rays
+/// along the x axis
+struct RayGrid {
+    size: usize,
+    bins: Vec<Vec<u32>>,
+}
+
+impl RayGrid {
+    fn new(triangles: &[Triangle], depth: u32) -> Self {
+        let size = 1 << depth.min(6);
+        let mut grid = RayGrid {
+            size,
+            bins: vec![Vec::new(); size * size],
+        };
+
+        for (index, triangle) in triangles.iter().enumerate() {
+            let min = triangle[0].min(triangle[1]).min(triangle[2]);
+            let max = triangle[0].max(triangle[1]).max(triangle[2]);
+            let (y0, z0) = grid.bin(min);
+            let (y1, z1) = grid.bin(max);
+            for z in z0..=z1 {
+                for y in y0..=y1 {
+                    grid.bins[z * size + y].push(index as u32);
+                }
+            }
+        }
+
+        grid
+    }
+
+    fn bin(&self, p: Vec3A) -> (usize, usize) {
+        let to_bin = |x: f32| (((x + 1.0) * 0.5 * self.size as f32) as usize).min(self.size - 1);
+        (to_bin(p.y), to_bin(p.z))
+    }
+
+    /// whether `p` is inside a closed mesh, by the parity of the surfaces
+    /// crossed by a ray from `p` along +x
+    ///
+    /// a ray through an edge or vertex is counted once by the fill rule of
+    /// edge_function(), so `p` may lie anywhere off the surface
+    fn is_inside(&self, triangles: &[Triangle], p: Vec3A) -> bool {
+        let (y, z) = self.bin(p);
+        let mut inside = false;
+        for index in self.bins[z * self.size + y].iter() {
+            let [a, b, c] = triangles[*index as usize];
+            let edges = [
+                edge_function(b, c, p),
+                edge_function(c, a, p),
+                edge_function(a, b, p),
+            ];
+            let area = edges[0].0 + edges[1].0 + edges[2].0;
+            if area == 0.0 {
+                continue;
+            }
+
+            // orient every triangle counter-clockwise in the yz plane
+            let covers = edges.iter().all(|(w, owned)| {
+                let w = w * area.signum();
+                w > 0.0 || (w == 0.0 && *owned == (area > 0.0))
+            });
+
+            if covers {
+                let x = (a.x * edges[0].0 + b.x * edges[1].0 + c.x * edges[2].0) / area;
+                if x > p.x {
+                    inside = !inside;
+                }
+            }
+        }
+
+        inside
+    }
+}
+
+struct Voxelizer<'a> {
+    mesh: &'a Mesh,
+    /// the mesh's triangles in the local [-1, 1] cube
+    triangles: Vec<Triangle>,
+    depth: u32,
+    /// for filling the mesh's interior, if it is solid
+    rays: Option<RayGrid>,
+}
+
+impl<'a> Voxelizer<'a> {
+    /// the color of the surface closest to a voxel's center
+    fn leaf_color(&self, touching: &[u32], center: Vec3A) -> u32 {
+        touching
+            .iter()
+            .map(|index| {
+                let triangle = &self.triangles[*index as usize];
+                let bary = closest_barycentric(triangle, center);
+                let closest = triangle[0] * bary.x + triangle[1] * bary.y + triangle[2] * bary.z;
+                (*index, bary, closest.distance_squared(center))
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .and_then(|(index, bary, _)| self.mesh.color_at(index as usize, bary))
+            .unwrap_or_else(|| Payload::default().color)
+    }
+
+    /// builds the subtree of `cell` from the triangles in `candidates` that
+    /// touch it, pushing its descendants to `nodes` and returning its root,
+    /// or `None` if the cell is empty
+    ///
+    /// when filling, a cell no triangle touches is wholly inside or outside
+    /// the mesh, so an inside cell becomes a solid leaf at any level, colored
+    /// like its nearest surface
+    fn build_subtree(&self, nodes: &mut Vec<Node>, cell: Cell, candidates: &[u32]) -> Option<Node> {
+        let center = cell.center();
+        let touching: Vec<u32> = candidates
+            .iter()
+            .copied()
+            .filter(|index| {
+                let triangle = &self.triangles[*index as usize];
+                triangle_overlaps_cube(triangle, center, cell.half_size())
+            })
+            .collect();
+
+        let mut node = Node::default();
+        if touching.is_empty() {
+            return match &self.rays {
+                Some(rays) if rays.is_inside(&self.triangles, center) => {
+                    node.data.color = self.leaf_color(candidates, center);
+                    Some(node)
+                }
+                _ => None,
+            };
+        }
+
+        if cell.depth == self.depth {
+            node.data.color = self.leaf_color(&touching, center);
+            return Some(node);
+        }
+
+        node.data.color = TreeBuilder::INTERIOR_COLOR;
+        for index in 0..8 {
+            let child = cell.child(index);
+            if let Some(child) = self.build_subtree(nodes, child, &touching) {
+                node.occupancy |= Node::index_to_mask(index);
+                node.children[index as usize] = nodes.len() as NodeRef;
+                nodes.push(child);
+            }
+        }
+
+        Some(node)
+    }
+}
+
+/// the world-space placement of a cube enclosing the mesh
+fn mesh_bounds(mesh: &Mesh) -> WorldBounds {
+    match mesh.aabb() {
+        Some((min, max)) => {
+            let size = (max - min).max_element();
+            WorldBounds {
+                min,
+                size: if size > 0.0 { size } else { 1.0 },
+            }
+        }
+        None => WorldBounds::default(),
+    }
+}
+
+/// voxelizes the surface of a mesh into an octree of the given depth,
+/// placed over the mesh's bounding cube
+///
+/// only the nodes the surface passes through are subdivided, so memory use
+/// follows the surface area rather than the grid's volume
+pub fn voxelize(mesh: &Mesh, depth: u32) -> VoxBuf {
+    build_voxbuf(mesh, depth, false)
+}
+
+/// like voxelize(), but also fills the interior of a closed mesh, with the
+/// nodes wholly inside it kept as solid leaves instead of being subdivided
+pub fn voxelize_solid(mesh: &Mesh, depth: u32) -> VoxBuf {
+    build_voxbuf(mesh, depth, true)
+}
+
+fn build_voxbuf(mesh: &Mesh, depth: u32, solid: bool) -> VoxBuf {
+    assert!(depth < VoxBuf::MAX_DEPTH, "unsupported depth {}", depth);
+    let timer = Instant::now();
+
+    let bounds = mesh_bounds(mesh);
+    let triangles: Vec<Triangle> = mesh
+        .triangles
+        .iter()
+        .map(|triangle| triangle.map(|index| bounds.to_local(mesh.positions[index as usize])))
+        .collect();
+
+    let voxelizer = Voxelizer {
+        mesh,
+        rays: if solid {
+            Some(RayGrid::new(&triangles, depth))
+        } else {
+            None
+        },
+        triangles,
+        depth,
+    };
+
+    // a triangle with a non-finite corner would touch every cell
+    let candidates: Vec<u32> = (0..mesh.triangles.len() as u32)
+        .filter(|index| {
+            let triangle = &voxelizer.triangles[*index as usize];
+            triangle.iter().all(|corner| corner.is_finite())
+        })
+        .collect();
+    let mut nodes = vec![Node::default()];
+    match voxelizer.build_subtree(&mut nodes, Cell::ROOT, &candidates) {
+        Some(root) => nodes[VoxBuf::ROOT_NODE as usize] = root,
+        None => nodes[VoxBuf::ROOT_NODE as usize].data.color = 0,
+    }
+
+    println!("voxelized in {:?}", timer.elapsed());
+    println!("{} triangles", mesh.triangles.len());
+    println!("{} nodes", nodes.len());
+
+    let mut vb = VoxBuf::from_colored_nodes(nodes);
+    vb.set_bounds(bounds);
+    vb
+}
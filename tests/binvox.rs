@@ -0,0 +1,335 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::Vec3A;
+use rand::{Rng, SeedableRng};
+use svo_cpu::binvox::{export_binvox, try_import_binvox_svo, BinvoxError};
+use svo_cpu::voxbuf::VoxBuf;
+
+const MODELS: [&[u8]; 3] = [
+    include_bytes!("../examples/models/stanford_bunny.binvox"),
+    include_bytes!("../examples/models/stanford_dragon.binvox"),
+    include_bytes!("../examples/models/stanford_buddha.binvox"),
+];
+
+/// byte offset of the RLE data following the "data" line
+fn data_offset(bv: &[u8]) -> usize {
+    let mut offset = 0;
+    for _ in 0..5 {
+        offset += bv[offset..].iter().position(|b| *b == b'\n').unwrap() + 1;
+    }
+    offset
+}
+
+/// RLE-encodes a binvox of the given dimensions, filled where `f(x, y, z)`
+/// is true in octree axes
+fn encode<F>(dim: [usize; 3], translate: [f32; 3], scale: f32, f: F) -> Vec<u8>
+where
+    F: Fn(usize, usize, usize) -> bool,
+{
+    let mut bv = format!(
+        "#binvox 1\ndim {} {} {}\ntranslate {} {} {}\nscale {}\ndata\n",
+        dim[0], dim[1], dim[2], translate[0], translate[1], translate[2], scale
+    )
+    .into_bytes();
+
+    let mut run: Option<(u8, u8)> = None;
+    for z in 0..dim[0] {
+        for y in 0..dim[1] {
+            for x in 0..dim[2] {
+                let value = f(x, y, z) as u8;
+                run = match run {
+                    Some((v, count)) if v == value && count < 255 => Some((v, count + 1)),
+                    Some((v, count)) => {
+                        bv.extend_from_slice(&[v, count]);
+                        Some((value, 1))
+                    }
+                    None => Some((value, 1)),
+                };
+            }
+        }
+    }
+
+    if let Some((v, count)) = run {
+        bv.extend_from_slice(&[v, count]);
+    }
+
+    bv
+}
+
+/// the voxel coordinates of every leaf of a tree with voxels of `size`
+fn leaf_voxels(vb: &VoxBuf, size: usize) -> Vec<(usize, usize, usize)> {
+    let leaves = vb.walk_all(&Vec3A::new(3.0, 2.0, 1.0));
+    let mut voxels: Vec<_> = leaves
+        .iter()
+        .map(|(_, voxel)| {
+            assert_eq!(voxel.w, 0.5 / size as f32);
+            let corner = (voxel.truncate() + 1.0) * (size as f32 * 0.5) - 0.5;
+            let [x, y, z] = corner.round().to_array();
+            (x as usize, y as usize, z as usize)
+        })
+        .collect();
+    voxels.sort_unstable();
+    voxels
+}
+
+fn ball(center: Vec3A, radius: f32) -> impl Fn(usize, usize, usize) -> bool {
+    move |x, y, z| {
+        let p = Vec3A::new(x as f32, y as f32, z as f32) + 0.5;
+        (p - center).length() < radius
+    }
+}
+
+#[test]
+fn imports_bundled_models() {
+    let filled = [916285, 684311, 539539];
+    for (bv, filled) in MODELS.iter().zip(filled.iter()) {
+        let vb = try_import_binvox_svo(bv).unwrap();
+        let leaves = vb.walk_all(&Vec3A::new(3.0, 2.0, 1.0));
+        assert_eq!(leaves.len(), *filled);
+        assert!(leaves.iter().all(|(_, voxel)| voxel.w == 0.5 / 512.0));
+    }
+
+    let bounds = try_import_binvox_svo(MODELS[0]).unwrap().bounds();
+    assert_eq!(bounds.min, Vec3A::new(-0.0587997, 0.0329874, -0.0946899));
+    assert_eq!(bounds.size, 0.155699);
+}
+
+#[test]
+fn imports_power_of_two_sizes() {
+    for size in [1, 2, 64, 128, 256].iter() {
+        let size = *size;
+        let center = Vec3A::new(size as f32 * 0.3, size as f32 * 0.5, 0.6);
+        let f = ball(center, size as f32 * 0.4);
+        let bv = encode([size; 3], [0.0; 3], 1.0, &f);
+
+        let mut expected = Vec::new();
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    if f(x, y, z) {
+                        expected.push((x, y, z));
+                    }
+                }
+            }
+        }
+
+        let vb = try_import_binvox_svo(&bv).unwrap();
+        assert_eq!(leaf_voxels(&vb, size), expected);
+    }
+}
+
+#[test]
+fn imports_non_cubic_grids() {
+    // 300 deep (octree z), 80 high (octree y) and 200 wide (octree x)
+    let dim = [300, 80, 200];
+    let f = |x: usize, y: usize, z: usize| (x * 7 + y * 3 + z) % 11 == 0 || x == 199;
+    let bv = encode(dim, [1.0, 2.0, 3.0], 3.0, f);
+
+    let mut expected = Vec::new();
+    for x in 0..dim[2] {
+        for y in 0..dim[1] {
+            for z in 0..dim[0] {
+                if f(x, y, z) {
+                    expected.push((x, y, z));
+                }
+            }
+        }
+    }
+
+    let vb = try_import_binvox_svo(&bv).unwrap();
+    assert_eq!(leaf_voxels(&vb, 512), expected);
+
+    // a 300-voxel span of 3.0 units padded out to 512 voxels
+    let bounds = vb.bounds();
+    assert_eq!(bounds.min, Vec3A::new(2.0, 3.0, 1.0));
+    assert!((bounds.size - 3.0 * 512.0 / 300.0).abs() < 1e-5);
+}
+
+#[test]
+fn imports_empty_grid() {
+    let bv = encode([8, 4, 2], [0.0; 3], 1.0, |_, _, _| false);
+    let vb = try_import_binvox_svo(&bv).unwrap();
+    let leaves = vb.walk_all(&Vec3A::ZERO);
+    assert!(leaves.iter().all(|(data, _)| data.color == 0));
+}
+
+#[test]
+fn rejects_unsupported_dim() {
+    for dim in ["0 1 1", "1 0 1", "99999999999 99999999999 99999999999"].iter() {
+        let bv = format!("#binvox 1\ndim {}\ndata\n", dim);
+        match try_import_binvox_svo(bv.as_bytes()) {
+            Err(BinvoxError::UnsupportedDimension(_)) => {}
+            other => panic!("dim {} gave {:?}", dim, other.err()),
+        }
+    }
+}
+
+#[test]
+fn imports_corrupted_values() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(4);
+    let bv = MODELS[0];
+    let offset = data_offset(bv);
+    let pairs = (bv.len() - offset) / 2;
+
+    for _ in 0..4 {
+        let mut corrupt = bv.to_vec();
+        for _ in 0..1024 {
+            let value = offset + rng.gen_range(0, pairs) * 2;
+            corrupt[value] = rng.gen();
+        }
+
+        assert!(try_import_binvox_svo(&corrupt).is_ok());
+    }
+}
+
+#[test]
+fn rejects_empty_and_garbage() {
+    assert!(matches!(
+        try_import_binvox_svo(&[]),
+        Err(BinvoxError::BadHeader)
+    ));
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    for len in [1, 16, 1024, 65536].iter() {
+        let garbage: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+        assert!(try_import_binvox_svo(&garbage).is_err());
+    }
+}
+
+#[test]
+fn rejects_malformed_dim() {
+    let lines = [
+        "dim",
+        "dim 512 512",
+        "dim 512 512 512 512",
+        "dim a b c",
+        "dim -1 512 512",
+        "dims 512 512 512",
+    ];
+
+    for line in lines.iter() {
+        let bv = format!("#binvox 1\n{}\ntranslate 0 0 0\nscale 1\ndata\n", line);
+        match try_import_binvox_svo(bv.as_bytes()) {
+            Err(BinvoxError::MalformedDimension(_)) => {}
+            other => panic!("{:?} gave {:?}", line, other.err()),
+        }
+    }
+}
+
+#[test]
+fn rejects_truncated_models() {
+    for bv in MODELS.iter() {
+        let offset = data_offset(bv);
+
+        for len in 0..offset {
+            assert!(try_import_binvox_svo(&bv[..len]).is_err());
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for _ in 0..16 {
+            let len = rng.gen_range(offset, bv.len());
+            match try_import_binvox_svo(&bv[..len]) {
+                Err(BinvoxError::TruncatedData { expected, found }) => assert!(found < expected),
+                other => panic!("truncated to {} bytes gave {:?}", len, other.err()),
+            }
+        }
+    }
+}
+
+#[test]
+fn rejects_corrupted_header() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+    for bv in MODELS.iter() {
+        // everything up to and including the "dim" line
+        let dim_end = bv
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .nth(1)
+            .unwrap()
+            .0;
+
+        for _ in 0..256 {
+            let mut corrupt = bv.to_vec();
+            let index = rng.gen_range(0, dim_end);
+            let byte = rng.gen::<u8>();
+            // digits and whitespace could still spell a valid header
+            if corrupt[index] == byte || byte.is_ascii_digit() || (byte as char).is_whitespace() {
+                continue;
+            }
+            corrupt[index] = byte;
+            assert!(try_import_binvox_svo(&corrupt).is_err());
+        }
+    }
+}
+
+#[test]
+fn rejects_corrupted_run_lengths() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+    for bv in MODELS.iter() {
+        let offset = data_offset(bv);
+        let pairs = (bv.len() - offset) / 2;
+
+        for _ in 0..32 {
+            let mut corrupt = bv.to_vec();
+            let count = offset + rng.gen_range(0, pairs) * 2 + 1;
+            let delta = rng.gen_range(1, 255);
+            corrupt[count] = corrupt[count].wrapping_add(delta);
+
+            match try_import_binvox_svo(&corrupt) {
+                Err(BinvoxError::TruncatedData { .. }) | Err(BinvoxError::RleOverflow) => {}
+                other => panic!("corrupt run length gave {:?}", other.err()),
+            }
+        }
+    }
+}
+
+#[test]
+fn round_trips_bundled_models() {
+    for bv in MODELS.iter() {
+        let vb = try_import_binvox_svo(bv).unwrap();
+        let mut exported = Vec::new();
+        export_binvox(&vb, 9, &mut exported).unwrap();
+
+        let round_trip = try_import_binvox_svo(&exported).unwrap();
+        assert_eq!(round_trip.bounds(), vb.bounds());
+        assert_eq!(leaf_voxels(&round_trip, 512), leaf_voxels(&vb, 512));
+
+        let mut again = Vec::new();
+        export_binvox(&round_trip, 9, &mut again).unwrap();
+        assert!(again == exported);
+    }
+}
+
+#[test]
+fn exports_at_coarser_and_finer_depths() {
+    let f = ball(Vec3A::new(10.0, 20.0, 30.0), 9.0);
+    let bv = encode([32, 32, 32], [0.0; 3], 1.0, &f);
+    let vb = try_import_binvox_svo(&bv).unwrap();
+
+    // each coarse voxel is filled if any of its fine voxels are
+    let mut coarse = Vec::new();
+    export_binvox(&vb, 3, &mut coarse).unwrap();
+    let mut expected: Vec<_> = leaf_voxels(&vb, 32)
+        .iter()
+        .map(|(x, y, z)| (x / 4, y / 4, z / 4))
+        .collect();
+    expected.sort_unstable();
+    expected.dedup();
+    let coarse = try_import_binvox_svo(&coarse).unwrap();
+    assert_eq!(leaf_voxels(&coarse, 8), expected);
+
+    // each fine voxel is filled if its coarse voxel is
+    let mut fine = Vec::new();
+    export_binvox(&vb, 6, &mut fine).unwrap();
+    let mut expected = Vec::new();
+    for (x, y, z) in leaf_voxels(&vb, 32) {
+        for i in 0..8 {
+            expected.push((x * 2 + (i & 1), y * 2 + ((i >> 1) & 1), z * 2 + (i >> 2)));
+        }
+    }
+    expected.sort_unstable();
+    let fine = try_import_binvox_svo(&fine).unwrap();
+    assert_eq!(leaf_voxels(&fine, 64), expected);
+}
@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::{UVec3, Vec3A};
+use std::collections::{HashMap, HashSet};
+use svo_cpu::brush::{AxisBox, Brush, Capsule, Cylinder, Sphere};
+use svo_cpu::edit::VoxelEdit;
+use svo_cpu::voxbuf::{Cell, Payload, VoxBuf, WorldBounds};
+
+const DEPTH: u32 = 4;
+
+const RED: Payload = Payload {
+    color: 0xffff0000,
+    occlusion: 0,
+};
+
+/// a 16-unit world, so that voxel (x, y, z) is centered on
+/// (x + 0.5, y + 0.5, z + 0.5)
+const BOUNDS: WorldBounds = WorldBounds {
+    min: Vec3A::ZERO,
+    size: 16.0,
+};
+
+fn brushes() -> Vec<(&'static str, Box<dyn Brush>)> {
+    vec![
+        (
+            "sphere",
+            Box::new(Sphere {
+                center: Vec3A::new(7.3, 8.1, 6.6),
+                radius: 5.2,
+            }),
+        ),
+        (
+            "box",
+            Box::new(AxisBox {
+                min: Vec3A::new(2.5, 1.0, 3.2),
+                max: Vec3A::new(13.0, 9.7, 14.0),
+            }),
+        ),
+        (
+            "capsule",
+            Box::new(Capsule {
+                a: Vec3A::new(3.0, 3.0, 3.0),
+                b: Vec3A::new(12.0, 10.0, 5.0),
+                radius: 2.5,
+            }),
+        ),
+        (
+            "cylinder",
+            Box::new(Cylinder {
+                a: Vec3A::new(8.0, 2.0, 8.0),
+                b: Vec3A::new(8.0, 14.0, 9.0),
+                radius: 4.3,
+            }),
+        ),
+        (
+            "half-space",
+            Box::new(|pos: Vec3A| (pos.y - 0.5 * pos.x - 4.0) / 1.25f32.sqrt()),
+        ),
+    ]
+}
+
+fn grid() -> impl Iterator<Item = UVec3> {
+    let size = 1 << DEPTH;
+    (0..size * size * size)
+        .map(move |index| UVec3::new(index % size, (index / size) % size, index / (size * size)))
+}
+
+/// the voxels whose centers are inside of the brush, one at a time
+fn reference(brush: &dyn Brush) -> HashSet<[u32; 3]> {
+    grid()
+        .filter(|pos| {
+            let cell = Cell {
+                pos: *pos,
+                depth: DEPTH,
+            };
+            brush.distance(BOUNDS.to_world(cell.center())) <= 0.0
+        })
+        .map(|pos| pos.to_array())
+        .collect()
+}
+
+fn empty() -> VoxBuf {
+    let mut vb = full();
+    vb.clear_voxel(UVec3::ZERO, 0);
+    vb
+}
+
+fn full() -> VoxBuf {
+    let mut vb = VoxBuf::new();
+    vb.set_bounds(BOUNDS);
+    vb
+}
+
+fn occupied(vb: &VoxBuf) -> HashSet<[u32; 3]> {
+    vb.voxels(DEPTH).keys().copied().collect()
+}
+
+/// the shallowest leaf in the tree
+fn coarsest_leaf(vb: &VoxBuf) -> u32 {
+    let mut coarsest = u32::MAX;
+    vb.walk_cells(|_node_ref, node, cell| {
+        if node.is_leaf() && !node.data.is_empty() {
+            coarsest = coarsest.min(cell.depth);
+        }
+        true
+    });
+    coarsest
+}
+
+#[test]
+fn brushes_cross_cells() {
+    // every brush has a surface running through the middle of the grid, so
+    // some cells a few levels up are partly inside of it
+    for (name, brush) in brushes().iter() {
+        let inside = reference(brush.as_ref());
+        let straddles = grid().any(|pos| {
+            let block = pos / 4;
+            let filled = (0..64)
+                .map(|index| block * 4 + UVec3::new(index % 4, (index / 4) % 4, index / 16))
+                .filter(|pos| inside.contains(&pos.to_array()))
+                .count();
+            filled > 0 && filled < 64
+        });
+        assert!(straddles, "{}", name);
+    }
+}
+
+#[test]
+fn sets_voxels_inside() {
+    for (name, brush) in brushes().iter() {
+        let mut vb = empty();
+        vb.apply_brush(brush.as_ref(), VoxelEdit::Set(RED), DEPTH);
+        assert_eq!(occupied(&vb), reference(brush.as_ref()), "{}", name);
+        assert!(vb.voxels(DEPTH).values().all(|color| *color == RED.color));
+
+        // cells entirely inside stay whole
+        assert!(coarsest_leaf(&vb) < DEPTH, "{}", name);
+    }
+}
+
+#[test]
+fn clears_voxels_inside() {
+    let everything: HashSet<[u32; 3]> = grid().map(|pos| pos.to_array()).collect();
+    for (name, brush) in brushes().iter() {
+        let mut vb = full();
+        vb.apply_brush(brush.as_ref(), VoxelEdit::Clear, DEPTH);
+        let outside: HashSet<_> = everything
+            .difference(&reference(brush.as_ref()))
+            .copied()
+            .collect();
+        assert_eq!(occupied(&vb), outside, "{}", name);
+
+        // an empty payload clears too
+        let mut vb = full();
+        let empty = Payload {
+            color: 0,
+            occlusion: 0,
+        };
+        vb.apply_brush(brush.as_ref(), VoxelEdit::Set(empty), DEPTH);
+        assert_eq!(occupied(&vb), outside, "{}", name);
+    }
+}
+
+#[test]
+fn paints_filled_voxels_inside() {
+    // a full tree with a slab along x taken out
+    let mut base = full();
+    let solid = base.voxels(0)[&[0, 0, 0]];
+    for pos in grid().filter(|pos| pos.x < 4) {
+        base.clear_voxel(pos, DEPTH);
+    }
+
+    for (name, brush) in brushes().iter() {
+        let mut vb = base.clone();
+        vb.apply_brush(brush.as_ref(), VoxelEdit::Paint(0xff00ff00), DEPTH);
+
+        let inside = reference(brush.as_ref());
+        let expected: HashMap<[u32; 3], u32> = base
+            .voxels(DEPTH)
+            .keys()
+            .map(|pos| {
+                let color = if inside.contains(pos) {
+                    0xff00ff00
+                } else {
+                    solid
+                };
+                (*pos, color)
+            })
+            .collect();
+        assert_eq!(vb.voxels(DEPTH), expected, "{}", name);
+    }
+}
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+//! Fixtures shared between the integration tests. Each test crate builds
+//! its own copy and uses only some of them.
+#![allow(dead_code)]
+
+use glam::UVec3;
+use svo_cpu::voxbuf::{Cell, Payload, TreeBuilder, VoxBuf};
+
+pub const RED: Payload = Payload {
+    color: 0xffff0000,
+    occlusion: 0,
+};
+
+/// what a fixture cell is filled with
+pub trait Fill: Copy {
+    fn payload(self) -> Payload;
+}
+
+/// a color without occlusion
+impl Fill for u32 {
+    fn payload(self) -> Payload {
+        Payload {
+            color: self,
+            occlusion: 0,
+        }
+    }
+}
+
+/// a color and its occlusion
+impl Fill for (u32, u8) {
+    fn payload(self) -> Payload {
+        Payload {
+            color: self.0,
+            occlusion: self.1,
+        }
+    }
+}
+
+impl Fill for Payload {
+    fn payload(self) -> Payload {
+        self
+    }
+}
+
+/// a tree with each (depth, position, fill) cell filled in
+pub fn build<F: Fill>(cells: &[(u32, [u32; 3], F)]) -> VoxBuf {
+    let mut builder = TreeBuilder::default();
+    for (depth, pos, fill) in cells.iter() {
+        let cell = Cell {
+            pos: UVec3::from(*pos),
+            depth: *depth,
+        };
+        builder.insert(cell, fill.payload());
+    }
+    builder.build()
+}
+
+/// a tree whose root is a single empty leaf
+pub fn empty() -> VoxBuf {
+    let mut vb = VoxBuf::new();
+    vb.clear_voxel(UVec3::ZERO, 0);
+    vb
+}
+
+/// the payload of the node at a cell
+pub fn payload_at(vb: &VoxBuf, depth: u32, pos: [u32; 3]) -> Payload {
+    let mut found = None;
+    vb.walk_cells(|_node_ref, node, cell| {
+        if cell.depth == depth && cell.pos == UVec3::from(pos) {
+            found = Some(node.data);
+        }
+        true
+    });
+    found.unwrap()
+}
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+mod common;
+
+use common::build;
+use glam::Vec3A;
+use std::collections::HashMap;
+use svo_cpu::csg::{ColorRule, CsgError};
+use svo_cpu::voxbuf::{Payload, VoxBuf, WorldBounds};
+
+const RED: u32 = 0xffff0000;
+const GREEN: u32 = 0xff00ff00;
+const BLUE: u32 = 0xff0000ff;
+
+/// coarse leaves and finer branches, overlapping every way they can: a
+/// leaf over a leaf, a leaf over a branch and a branch over a branch
+fn operands() -> (VoxBuf, VoxBuf) {
+    let left = build(&[
+        (1, [0, 0, 0], RED),
+        (1, [1, 1, 0], RED),
+        (2, [2, 0, 0], GREEN),
+        (2, [3, 1, 1], GREEN),
+        (2, [0, 3, 3], GREEN),
+        (2, [1, 2, 2], GREEN),
+        (2, [3, 3, 3], GREEN),
+    ]);
+    let right = build(&[
+        (2, [0, 0, 0], BLUE),
+        (2, [1, 1, 1], BLUE),
+        (2, [1, 0, 1], BLUE),
+        (1, [1, 0, 0], BLUE),
+        (1, [0, 1, 1], BLUE),
+        (1, [1, 1, 0], BLUE),
+        (2, [3, 3, 3], BLUE),
+        (2, [2, 3, 3], BLUE),
+    ]);
+    (left, right)
+}
+
+/// the filled voxels of a combination of two trees, one voxel at a time
+fn reference(
+    left: &VoxBuf,
+    right: &VoxBuf,
+    combine: impl Fn(Option<u32>, Option<u32>) -> Option<u32>,
+) -> HashMap<[u32; 3], u32> {
+    let (left, right) = (left.voxels(2), right.voxels(2));
+    let mut positions: Vec<_> = left.keys().chain(right.keys()).copied().collect();
+    positions.sort_unstable();
+    positions.dedup();
+    positions
+        .into_iter()
+        .filter_map(|pos| {
+            let color = combine(left.get(&pos).copied(), right.get(&pos).copied())?;
+            Some((pos, color))
+        })
+        .collect()
+}
+
+fn leaf_count(vb: &VoxBuf) -> usize {
+    let mut count = 0;
+    vb.walk_cells(|_node_ref, node, _cell| {
+        if node.is_leaf() && !node.data.is_empty() {
+            count += 1;
+        }
+        true
+    });
+    count
+}
+
+#[test]
+fn unites_trees() {
+    let (left, right) = operands();
+    let union = left.union(&right, ColorRule::Left).unwrap();
+    let expected = reference(&left, &right, |left, right| left.or(right));
+    assert_eq!(union.voxels(2), expected);
+
+    let union = left.union(&right, ColorRule::Right).unwrap();
+    let expected = reference(&left, &right, |left, right| right.or(left));
+    assert_eq!(union.voxels(2), expected);
+
+    // blended colors average each channel, rounding up
+    let union = left.union(&right, ColorRule::Blend).unwrap();
+    let expected = reference(&left, &right, |left, right| match (left, right) {
+        (Some(RED), Some(BLUE)) => Some(0xff800080),
+        (Some(GREEN), Some(BLUE)) => Some(0xff008080),
+        (Some(_), Some(_)) => unreachable!(),
+        _ => left.or(right),
+    });
+    assert_eq!(union.voxels(2), expected);
+}
+
+#[test]
+fn intersects_trees() {
+    let (left, right) = operands();
+    let intersection = left.intersection(&right, ColorRule::Right).unwrap();
+    let expected = reference(&left, &right, |left, right| left.and(right));
+    assert_eq!(intersection.voxels(2), expected);
+
+    let custom = ColorRule::Custom(|left, right| Payload {
+        color: left.color | right.color,
+        occlusion: 0,
+    });
+    let intersection = left.intersection(&right, custom).unwrap();
+    let expected = reference(&left, &right, |left, right| Some(left? | right?));
+    assert_eq!(intersection.voxels(2), expected);
+}
+
+#[test]
+fn subtracts_trees() {
+    let (left, right) = operands();
+    let difference = left.difference(&right).unwrap();
+    let expected = reference(&left, &right, |left, right| match right {
+        Some(_) => None,
+        None => left,
+    });
+    assert_eq!(difference.voxels(2), expected);
+
+    let difference = right.difference(&left).unwrap();
+    let expected = reference(&right, &left, |right, left| match left {
+        Some(_) => None,
+        None => right,
+    });
+    assert_eq!(difference.voxels(2), expected);
+}
+
+#[test]
+fn xors_trees() {
+    let (left, right) = operands();
+    let xor = left.xor(&right).unwrap();
+    let expected = reference(&left, &right, |left, right| match (left, right) {
+        (Some(_), Some(_)) => None,
+        _ => left.or(right),
+    });
+    assert_eq!(xor.voxels(2), expected);
+}
+
+#[test]
+fn collapses_uniform_results() {
+    // the left half of the cube, with one cell of the right half in finer
+    // voxels, and the rest of the right half
+    let half = |x| (0..4).map(move |index| (1, [x, index & 1, index >> 1], RED));
+    let fine = (0..8).map(|index| (2, [2 + (index & 1), (index >> 1) & 1, index >> 2], RED));
+    let left = build(&half(0).chain(fine).collect::<Vec<_>>());
+    let right = build(&half(1).collect::<Vec<_>>());
+    assert_eq!(leaf_count(&left), 4 + 8);
+
+    // the fine voxels meet a coarse leaf and merge, and then so does the root
+    let union = left.union(&right, ColorRule::Left).unwrap();
+    assert_eq!(leaf_count(&union), 1);
+    assert_eq!(union.voxels(2).len(), 64);
+
+    // splitting the leaf back up merges what is left as far as it goes
+    let everything = union;
+    let difference = everything.difference(&left).unwrap();
+    let expected = reference(&right, &left, |right, left| match left {
+        Some(_) => None,
+        None => right,
+    });
+    assert_eq!(difference.voxels(2), expected);
+    assert_eq!(leaf_count(&difference), 3);
+
+    // a tree xor itself, or intersected with its complement, is empty
+    let xor = everything.xor(&everything).unwrap();
+    assert!(xor.voxels(2).is_empty());
+    assert_eq!(leaf_count(&xor), 0);
+    let intersection = left.intersection(&difference, ColorRule::Left).unwrap();
+    assert!(intersection.voxels(2).is_empty());
+    assert_eq!(leaf_count(&intersection), 0);
+}
+
+#[test]
+fn rejects_different_bounds() {
+    let (left, mut right) = operands();
+    let bounds = WorldBounds {
+        min: Vec3A::ZERO,
+        size: 4.0,
+    };
+    right.set_bounds(bounds);
+    let result = left.union(&right, ColorRule::Left);
+    assert!(matches!(
+        result,
+        Err(CsgError::BoundsMismatch { left, right })
+            if left == WorldBounds::default() && right == bounds
+    ));
+    assert!(right.difference(&left).is_err());
+}
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::{UVec3, Vec3A};
+use svo_cpu::binvox::import_binvox_svo;
+use svo_cpu::history::EditHistory;
+use svo_cpu::voxbuf::{Cell, Payload, TreeBuilder, VoxBuf};
+
+fn bunny() -> VoxBuf {
+    import_binvox_svo(include_bytes!("../examples/models/stanford_bunny.binvox"))
+}
+
+/// the same few voxels in each of the eight octants of a 16^3 grid, colored
+/// by `color(octant, voxel)`
+fn repeated(color: impl Fn(u32, usize) -> u32) -> VoxBuf {
+    let pattern = [
+        [0, 0, 0],
+        [1, 0, 0],
+        [5, 2, 7],
+        [6, 6, 6],
+        [3, 7, 1],
+        [7, 7, 7],
+    ];
+    let mut builder = TreeBuilder::default();
+    for octant in 0..8 {
+        let corner = UVec3::new(octant & 1, (octant >> 1) & 1, octant >> 2) * 8;
+        for (index, pos) in pattern.iter().enumerate() {
+            let cell = Cell {
+                pos: corner + UVec3::from(*pos),
+                depth: 4,
+            };
+            let data = Payload {
+                color: color(octant, index),
+                occlusion: 0,
+            };
+            builder.insert(cell, data);
+        }
+    }
+    builder.build()
+}
+
+/// everything a walk hands to its callback, in order
+fn walk(vb: &VoxBuf, eye: &Vec3A) -> Vec<(bool, Payload, [f32; 4])> {
+    let mut walked = Vec::new();
+    vb.walk(eye, |is_leaf, data, voxel| {
+        walked.push((is_leaf, *data, voxel.to_array()));
+        true
+    });
+    walked
+}
+
+const EYES: [[f32; 3]; 3] = [[3.0, 2.0, 1.0], [-2.0, 0.5, -4.0], [0.1, -3.0, 2.0]];
+
+#[test]
+fn walks_like_the_tree() {
+    for vb in [
+        bunny(),
+        repeated(|_octant, index| 0xff000000 | index as u32),
+    ]
+    .iter()
+    {
+        let mut dag = vb.clone();
+        dag.compress_dag();
+        let expanded = vb.to_colored_dag().to_voxbuf();
+
+        for eye in EYES.iter() {
+            let eye = &Vec3A::from(*eye);
+            let expected = walk(vb, eye);
+            assert_eq!(walk(&dag, eye), expected);
+            assert_eq!(walk(&expanded, eye), expected);
+
+            let mut walked = Vec::new();
+            vb.to_colored_dag().walk(eye, |is_leaf, data, voxel| {
+                walked.push((is_leaf, *data, voxel.to_array()));
+                true
+            });
+            assert_eq!(walked, expected);
+        }
+    }
+}
+
+#[test]
+fn shares_repeated_subtrees() {
+    let vb = repeated(|_octant, index| 0xff000000 | index as u32);
+    let tree = vb.view().reachable_nodes();
+    assert_eq!(tree, vb.view().nodes().len());
+
+    // the octants collapse into one, leaving the root and a single octant
+    let mut dag = vb.clone();
+    dag.compress_dag();
+    let octant = (tree - 1) / 8;
+    assert_eq!(dag.view().reachable_nodes(), 1 + octant);
+    assert!(dag.view().reachable_nodes() < octant * 2);
+    assert_eq!(dag.view().nodes().len(), dag.view().reachable_nodes());
+
+    // with a different color in each octant, only shapes are shared, so
+    // only the colored DAG keeps them together
+    let vb = repeated(|octant, _index| 0xff000000 | octant);
+    let mut dag = vb.clone();
+    dag.compress_dag();
+    let colored = vb.to_colored_dag();
+    assert!(colored.geometry().view().reachable_nodes() < dag.view().reachable_nodes());
+    assert_eq!(colored.tree_nodes(), vb.view().reachable_nodes());
+    assert_eq!(colored.to_voxbuf().voxels(4), vb.voxels(4));
+}
+
+#[test]
+fn compresses_idempotently() {
+    for vb in [
+        bunny(),
+        repeated(|octant, index| 0xff000000 | octant << 8 | index as u32),
+    ]
+    .iter()
+    {
+        let mut once = vb.clone();
+        once.compress_dag();
+        let mut twice = once.clone();
+        twice.compress_dag();
+        assert_eq!(twice.view().nodes(), once.view().nodes());
+    }
+}
+
+#[test]
+fn edits_expand_shared_subtrees() {
+    let mut vb = repeated(|_octant, index| 0xff000000 | index as u32);
+    let mut dag = vb.clone();
+    dag.compress_dag();
+    assert!(dag.is_shared());
+    assert!(!vb.is_shared());
+
+    // the edit lands in one octant only, not in every octant sharing it
+    let data = Payload {
+        color: 0xffff0000,
+        occlusion: 0,
+    };
+    for vb in [&mut vb, &mut dag].iter_mut() {
+        vb.set_voxel(UVec3::new(2, 2, 2), 4, data);
+        vb.paint_voxel(UVec3::new(8, 0, 0), 4, 0xff00ff00);
+    }
+    assert!(!dag.is_shared());
+    assert_eq!(dag.voxels(4), vb.voxels(4));
+    assert_eq!(dag.view().reachable_nodes(), vb.view().reachable_nodes());
+
+    // and histories expand them up front
+    let mut dag = vb.clone();
+    dag.compress_dag();
+    let history = EditHistory::new(dag, usize::MAX);
+    assert!(!history.voxbuf().is_shared());
+    assert_eq!(history.voxbuf().voxels(4), vb.voxels(4));
+}
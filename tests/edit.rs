@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+mod common;
+
+use common::{empty, RED};
+use glam::UVec3;
+use std::collections::HashMap;
+use svo_cpu::voxbuf::{Payload, VoxBuf};
+
+/// the leaves reachable from the root, as (depth, position, payload)
+fn leaves(vb: &VoxBuf) -> Vec<(u32, [u32; 3], Payload)> {
+    let mut leaves = Vec::new();
+    vb.walk_cells(|_node_ref, node, cell| {
+        if node.is_leaf() {
+            leaves.push((cell.depth, cell.pos.to_array(), node.data));
+        }
+        true
+    });
+    leaves.sort_unstable_by_key(|(depth, pos, _data)| (*depth, *pos));
+    leaves
+}
+
+fn reachable(vb: &VoxBuf) -> usize {
+    let mut count = 0;
+    vb.walk_cells(|_node_ref, _node, _cell| {
+        count += 1;
+        true
+    });
+    count
+}
+
+/// every voxel of the 2^depth grid
+fn grid(depth: u32) -> impl Iterator<Item = [u32; 3]> {
+    let size = 1 << depth;
+    (0..size * size * size)
+        .map(move |index| [index % size, (index / size) % size, index / (size * size)])
+}
+
+#[test]
+fn set_then_clear_collapses() {
+    let mut vb = empty();
+    assert!(vb.voxels(0).is_empty());
+    assert_eq!(leaves(&vb), vec![(0, [0; 3], vb.view().nodes()[0].data)]);
+
+    // setting a deep voxel creates a path of one node per level
+    vb.set_voxel(UVec3::new(5, 3, 6), 3, RED);
+    assert_eq!(leaves(&vb), vec![(3, [5, 3, 6], RED)]);
+    assert_eq!(reachable(&vb), 4);
+
+    // and clearing it takes the whole path back down to an empty root
+    vb.clear_voxel(UVec3::new(5, 3, 6), 3);
+    assert!(vb.voxels(3).is_empty());
+    assert_eq!(reachable(&vb), 1);
+    assert!(vb.view().nodes()[0].is_leaf());
+    assert!(vb.view().nodes()[0].data.is_empty());
+
+    // clearing a voxel out of a solid leaf splits it down to the voxel
+    let mut vb = VoxBuf::new();
+    let solid = vb.view().nodes()[0].data;
+    vb.clear_voxel(UVec3::new(1, 2, 3), 2);
+    let voxels = vb.voxels(2);
+    assert_eq!(voxels.len(), 63);
+    assert!(!voxels.contains_key(&[1, 2, 3]));
+    assert!(voxels.values().all(|color| *color == solid.color));
+    // the root's eight children, and the seven left of the split child
+    assert_eq!(reachable(&vb), 1 + 8 + 7);
+
+    // and filling it back in collapses the tree to one leaf again
+    vb.set_voxel(UVec3::new(1, 2, 3), 2, solid);
+    assert_eq!(leaves(&vb), vec![(0, [0; 3], solid)]);
+}
+
+#[test]
+fn merges_full_siblings() {
+    let mut vb = empty();
+    let block = [2, 2, 0];
+    for (index, offset) in grid(1).enumerate() {
+        let pos = UVec3::from(block) + UVec3::from(offset);
+        vb.set_voxel(pos, 2, RED);
+
+        // siblings stay apart until the last one fills their parent
+        let expected = if index < 7 { index + 1 } else { 1 };
+        assert_eq!(leaves(&vb).len(), expected);
+    }
+
+    // the eight voxels become one leaf a level up
+    assert_eq!(leaves(&vb), vec![(1, [1, 1, 0], RED)]);
+    assert_eq!(reachable(&vb), 2);
+
+    // and filling the rest of the grid merges all the way up to the root
+    for pos in grid(2) {
+        vb.set_voxel(UVec3::from(pos), 2, RED);
+    }
+    assert_eq!(leaves(&vb), vec![(0, [0; 3], RED)]);
+
+    // siblings of different colors do not merge
+    let mut vb = empty();
+    for (index, pos) in grid(1).enumerate() {
+        let color = if index == 5 { 0xff00ff00 } else { RED.color };
+        let data = Payload { color, ..RED };
+        vb.set_voxel(UVec3::from(pos), 1, data);
+    }
+    assert_eq!(leaves(&vb).len(), 8);
+}
+
+#[test]
+fn reuses_freed_nodes() {
+    let mut vb = empty();
+    vb.set_voxel(UVec3::new(1, 2, 3), 4, RED);
+    vb.clear_voxel(UVec3::new(1, 2, 3), 4);
+    let len = vb.view().nodes().len();
+    assert_eq!(len, 5);
+
+    // every later edit of the same size fits in the freed nodes
+    let positions = [[15, 0, 7], [0, 0, 0], [8, 9, 10], [3, 14, 3]];
+    for pos in positions.iter() {
+        vb.set_voxel(UVec3::from(*pos), 4, RED);
+        assert_eq!(vb.voxels(4), [(*pos, RED.color)].iter().copied().collect());
+        assert_eq!(vb.view().nodes().len(), len);
+        vb.clear_voxel(UVec3::from(*pos), 4);
+        assert!(vb.voxels(4).is_empty());
+    }
+
+    // including ones split out of a leaf
+    let mut vb = VoxBuf::new();
+    vb.clear_voxel(UVec3::new(3, 3, 3), 2);
+    vb.set_voxel(UVec3::new(3, 3, 3), 2, Payload::default());
+    let len = vb.view().nodes().len();
+    vb.clear_voxel(UVec3::new(0, 1, 2), 2);
+    assert_eq!(vb.view().nodes().len(), len);
+    assert_eq!(vb.voxels(2).len(), 63);
+}
+
+#[test]
+fn paints_part_of_a_leaf() {
+    let mut vb = VoxBuf::new();
+    let solid = vb.view().nodes()[0].data;
+    vb.paint_voxel(UVec3::new(1, 0, 1), 2, 0xff00ff00);
+
+    let mut expected: HashMap<[u32; 3], u32> = grid(2).map(|pos| (pos, solid.color)).collect();
+    expected.insert([1, 0, 1], 0xff00ff00);
+    assert_eq!(vb.voxels(2), expected);
+    // the root and the child holding the voxel split, and nothing else
+    assert_eq!(reachable(&vb), 1 + 8 + 8);
+
+    // painting empty space leaves it empty
+    vb.clear_voxel(UVec3::new(3, 3, 3), 2);
+    let nodes = vb.view().nodes().to_vec();
+    vb.paint_voxel(UVec3::new(3, 3, 3), 2, 0xffffff00);
+    assert_eq!(vb.view().nodes(), &nodes[..]);
+
+    // painting over everything merges the painted leaves back together,
+    // but keeps the hole
+    vb.paint_voxel(UVec3::ZERO, 0, solid.color);
+    expected.insert([1, 0, 1], solid.color);
+    expected.remove(&[3, 3, 3]);
+    assert_eq!(vb.voxels(2), expected);
+    assert_eq!(reachable(&vb), 1 + 8 + 7);
+
+    vb.set_voxel(UVec3::new(3, 3, 3), 2, solid);
+    assert_eq!(leaves(&vb), vec![(0, [0; 3], solid)]);
+}
@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use svo_cpu::procgen::heightmap::{Heightmap, HeightmapError, MAX_PIXELS};
+
+fn pnm(header: &str, data: &[u8]) -> Vec<u8> {
+    let mut bytes = header.as_bytes().to_vec();
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+#[test]
+fn reads_8_bit_pgm() {
+    let binary = pnm("P5\n# a comment\n3 2\n255\n", &[0, 51, 255, 1, 2, 3]);
+    let ascii = pnm("P2 3 2 255\n0 51 255\n1 2 3\n", &[]);
+    for pgm in [binary, ascii].iter() {
+        let map = Heightmap::from_pgm(&pgm[..]).unwrap();
+        assert_eq!((map.width, map.height), (3, 2));
+        assert_eq!(map.samples, vec![0, 0x3333, 0xffff, 0x101, 0x202, 0x303]);
+        assert!(map.colors.is_none());
+    }
+}
+
+#[test]
+fn reads_16_bit_pgm() {
+    // 16-bit samples are big-endian, and scaled up from maxval
+    let pgm = pnm("P5 2 1 65535\n", &[0x12, 0x34, 0xff, 0xfe]);
+    let map = Heightmap::from_pgm(&pgm[..]).unwrap();
+    assert_eq!(map.samples, vec![0x1234, 0xfffe]);
+
+    let pgm = pnm("P5 2 1 1000\n", &[0x01, 0xf4, 0x03, 0xe8]);
+    let map = Heightmap::from_pgm(&pgm[..]).unwrap();
+    assert_eq!(map.samples, vec![32767, 0xffff]);
+
+    // samples above maxval are clamped to it
+    let pgm = pnm("P2 2 1 1000\n", b"1000 4000\n");
+    let map = Heightmap::from_pgm(&pgm[..]).unwrap();
+    assert_eq!(map.samples, vec![0xffff, 0xffff]);
+}
+
+#[test]
+fn reads_color_maps() {
+    let pgm = pnm("P5 2 1 255\n", &[0, 0]);
+    let ppms = [
+        pnm("P6 2 1 255\n", &[255, 128, 0, 1, 2, 3]),
+        pnm("P3 2 1 255\n255 128 0 1 2 3\n", &[]),
+        pnm(
+            "P6 2 1 65535\n",
+            &[0xff, 0xff, 0x80, 0x80, 0, 0, 1, 1, 2, 2, 3, 3],
+        ),
+    ];
+    for ppm in ppms.iter() {
+        let map = Heightmap::from_pgm(&pgm[..])
+            .unwrap()
+            .with_color_map(&ppm[..])
+            .unwrap();
+        assert_eq!(map.colors, Some(vec![0xffff8000, 0xff010203]));
+    }
+
+    // a PGM color map is grayscale
+    let gray = pnm("P5 2 1 255\n", &[0x40, 0xff]);
+    let map = Heightmap::from_pgm(&pgm[..])
+        .unwrap()
+        .with_color_map(&gray[..])
+        .unwrap();
+    assert_eq!(map.colors, Some(vec![0xff404040, 0xffffffff]));
+
+    let small = pnm("P6 1 1 255\n", &[0, 0, 0]);
+    assert!(matches!(
+        Heightmap::from_pgm(&pgm[..])
+            .unwrap()
+            .with_color_map(&small[..]),
+        Err(HeightmapError::SizeMismatch)
+    ));
+}
+
+#[test]
+fn rejects_bad_pnm_headers() {
+    let headers = [
+        "P4 1 1\n",
+        "P5 one 1 255\n",
+        "P5 0 1 255\n",
+        "P5 1 1 0\n",
+        "P5 1 1 65536\n",
+        "P5 -1 1 255\n",
+        // a heightmap has to be grayscale
+        "P6 1 1 255\n",
+    ];
+    for header in headers.iter() {
+        let pgm = pnm(header, &[0; 6]);
+        assert!(
+            matches!(
+                Heightmap::from_pgm(&pgm[..]),
+                Err(HeightmapError::BadHeader)
+            ),
+            "{:?}",
+            header
+        );
+    }
+}
+
+#[test]
+fn rejects_truncated_pnm() {
+    let truncated = [
+        pnm("P5 3 2 255\n", &[0; 5]),
+        pnm("P5 3 2 65535\n", &[0; 11]),
+        pnm("P2 3 2 255\n0 1 2 3 4", &[]),
+        pnm("P5 3 2", &[]),
+        pnm("", &[]),
+    ];
+    for pgm in truncated.iter() {
+        assert!(
+            matches!(
+                Heightmap::from_pgm(&pgm[..]),
+                Err(HeightmapError::Truncated)
+            ),
+            "{:?}",
+            String::from_utf8_lossy(pgm)
+        );
+    }
+
+    let pgm = pnm("P5 2 1 255\n", &[0, 0]);
+    let ppm = pnm("P6 2 1 255\n", &[0; 5]);
+    assert!(matches!(
+        Heightmap::from_pgm(&pgm[..])
+            .unwrap()
+            .with_color_map(&ppm[..]),
+        Err(HeightmapError::Truncated)
+    ));
+}
+
+#[test]
+fn rejects_oversized_pnm() {
+    // none of these may try to allocate their claimed size
+    let side = (MAX_PIXELS as f64).sqrt() as usize + 1;
+    let headers = [
+        format!("P5 {} {} 255\n", side, side),
+        format!("P2 {} {} 255\n", MAX_PIXELS + 1, 1),
+        format!("P5 {} 2 65535\n", usize::MAX),
+        format!("P5 {} {} 255\n", usize::MAX / 2, usize::MAX / 2),
+    ];
+    for header in headers.iter() {
+        let pgm = pnm(header, &[0; 16]);
+        assert!(
+            matches!(Heightmap::from_pgm(&pgm[..]), Err(HeightmapError::TooLarge)),
+            "{:?}",
+            header
+        );
+    }
+
+    // a color map within the limit but with a huge claimed size is
+    // truncated, not allocated up front
+    let pgm = pnm("P5 2 1 255\n", &[0, 0]);
+    let ppm = pnm(&format!("P6 {} 1 65535\n", MAX_PIXELS), &[0; 16]);
+    assert!(matches!(
+        Heightmap::from_pgm(&pgm[..])
+            .unwrap()
+            .with_color_map(&ppm[..]),
+        Err(HeightmapError::Truncated)
+    ));
+}
+
+#[test]
+fn reads_raw16() {
+    let bytes = [0x34, 0x12, 0xff, 0xff, 0, 0, 1, 0];
+    let map = Heightmap::from_raw16(&bytes[..], 2, 2).unwrap();
+    assert_eq!(map.samples, vec![0x1234, 0xffff, 0, 1]);
+
+    assert!(matches!(
+        Heightmap::from_raw16(&bytes[..7], 2, 2),
+        Err(HeightmapError::Truncated)
+    ));
+    assert!(matches!(
+        Heightmap::from_raw16(&bytes[..], MAX_PIXELS, 2),
+        Err(HeightmapError::TooLarge)
+    ));
+    assert!(matches!(
+        Heightmap::from_raw16(&bytes[..], usize::MAX, usize::MAX),
+        Err(HeightmapError::TooLarge)
+    ));
+    // in range, but far longer than the input
+    assert!(matches!(
+        Heightmap::from_raw16(&bytes[..], MAX_PIXELS, 1),
+        Err(HeightmapError::Truncated)
+    ));
+}
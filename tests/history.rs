@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+mod common;
+
+use common::{empty, RED};
+use glam::{UVec3, Vec3A};
+use svo_cpu::brush::Sphere;
+use svo_cpu::edit::VoxelEdit;
+use svo_cpu::history::EditHistory;
+use svo_cpu::voxbuf::{Node, NodeRef};
+
+/// everything undo and redo have to put back, slot for slot
+fn snapshot(history: &EditHistory) -> (Vec<Node>, Vec<NodeRef>) {
+    let vb = history.voxbuf();
+    (vb.view().nodes().to_vec(), vb.free_nodes().to_vec())
+}
+
+#[test]
+fn replays_edits_exactly() {
+    let mut history = EditHistory::new(empty(), usize::MAX);
+    let mut snapshots = vec![snapshot(&history)];
+
+    let steps: Vec<Box<dyn Fn(&mut EditHistory)>> = vec![
+        Box::new(|h| h.set_voxel(UVec3::new(1, 2, 3), 4, RED)),
+        // frees the whole path
+        Box::new(|h| h.clear_voxel(UVec3::new(1, 2, 3), 4)),
+        // pops part of the free-list back off
+        Box::new(|h| h.set_voxel(UVec3::new(7, 7, 0), 3, RED)),
+        // pops the rest of it and appends past it
+        Box::new(|h| h.set_voxel(UVec3::new(30, 1, 17), 5, RED)),
+        Box::new(|h| h.paint_voxel(UVec3::ZERO, 0, 0xff00ff00)),
+        Box::new(|h| h.clear_voxel(UVec3::new(30, 1, 17), 5)),
+        // empties the free-list that step left, over many edits in one step
+        Box::new(|h| {
+            let sphere = Sphere {
+                center: Vec3A::new(0.2, -0.1, 0.3),
+                radius: 0.6,
+            };
+            h.apply_brush(&sphere, VoxelEdit::Set(RED), 4)
+        }),
+        Box::new(|h| h.clear_voxel(UVec3::new(2, 1, 2), 2)),
+        Box::new(|h| h.set_voxel(UVec3::new(3, 1, 2), 2, RED)),
+    ];
+
+    for (index, step) in steps.iter().enumerate() {
+        let len = history.voxbuf().view().nodes().len();
+        step(&mut history);
+        snapshots.push(snapshot(&history));
+
+        if index == 2 {
+            assert_eq!(history.voxbuf().view().nodes().len(), len);
+        }
+    }
+    assert!(snapshots.windows(2).all(|pair| pair[0] != pair[1]));
+
+    // an edit that changes nothing is not a step
+    history.set_voxel(UVec3::new(3, 1, 2), 2, RED);
+    assert_eq!(snapshot(&history), snapshots[steps.len()]);
+
+    for expected in snapshots.iter().rev().skip(1) {
+        assert!(history.undo());
+        assert!(snapshot(&history) == *expected);
+    }
+    assert!(!history.can_undo());
+    assert!(!history.undo());
+
+    for expected in snapshots.iter().skip(1) {
+        assert!(history.redo());
+        assert!(snapshot(&history) == *expected);
+    }
+    assert!(!history.can_redo());
+    assert!(!history.redo());
+
+    // and again from halfway through
+    for _ in 0..4 {
+        history.undo();
+    }
+    assert!(snapshot(&history) == snapshots[steps.len() - 4]);
+    while history.redo() {}
+    assert!(snapshot(&history) == snapshots[steps.len()]);
+}
+
+#[test]
+fn new_edit_clears_redo() {
+    let mut history = EditHistory::new(empty(), usize::MAX);
+    let start = snapshot(&history);
+    history.set_voxel(UVec3::new(0, 0, 0), 2, RED);
+    let first = snapshot(&history);
+    history.set_voxel(UVec3::new(3, 0, 0), 2, RED);
+    history.set_voxel(UVec3::new(3, 3, 0), 2, RED);
+
+    assert!(history.undo());
+    assert!(history.undo());
+    assert!(history.can_redo());
+    let used = history.memory_used();
+
+    history.set_voxel(UVec3::new(0, 3, 3), 2, RED);
+    assert!(!history.can_redo());
+    assert!(!history.redo());
+    assert!(history.memory_used() < used);
+
+    let voxels = history.voxbuf().voxels(2);
+    assert_eq!(voxels.len(), 2);
+    assert!(voxels.contains_key(&[0, 3, 3]));
+
+    assert!(history.undo());
+    assert!(snapshot(&history) == first);
+    assert!(history.undo());
+    assert!(snapshot(&history) == start);
+    assert!(!history.undo());
+}
+
+#[test]
+fn drops_checkpoints_with_their_steps() {
+    // each edit fills one octant's corner voxel, so every step is the same
+    // size: four appended nodes and the root changed
+    let corners = [[0, 0, 0], [15, 0, 0], [0, 15, 0], [0, 0, 15]];
+    let step_size = {
+        let mut history = EditHistory::new(empty(), usize::MAX);
+        history.set_voxel(UVec3::from(corners[0]), 4, RED);
+        history.memory_used()
+    };
+
+    let mut history = EditHistory::new(empty(), step_size * 2);
+    history.checkpoint("start");
+    history.set_voxel(UVec3::from(corners[0]), 4, RED);
+    history.checkpoint("one");
+    let one = snapshot(&history);
+    history.set_voxel(UVec3::from(corners[1]), 4, RED);
+    history.checkpoint("two");
+    assert_eq!(history.memory_used(), step_size * 2);
+    assert_eq!(history.checkpoints().count(), 3);
+
+    // the third step pushes the first out, and the start along with it
+    history.set_voxel(UVec3::from(corners[2]), 4, RED);
+    history.checkpoint("three");
+    assert!(history.memory_used() <= step_size * 2);
+    let names: Vec<_> = history.checkpoints().collect();
+    assert_eq!(names, vec!["one", "two", "three"]);
+    assert!(!history.restore("start"));
+
+    // the oldest step left can still be undone back to its checkpoint
+    assert!(history.restore("one"));
+    assert!(snapshot(&history) == one);
+    assert!(!history.can_undo());
+
+    // redoing it all comes back to the last checkpoint
+    assert!(history.restore("three"));
+    assert_eq!(history.voxbuf().voxels(4).len(), 3);
+
+    // and a fourth step drops the first checkpoint too
+    history.set_voxel(UVec3::from(corners[3]), 4, RED);
+    let names: Vec<_> = history.checkpoints().collect();
+    assert_eq!(names, vec!["two", "three"]);
+    assert!(!history.restore("one"));
+}
+
+#[test]
+fn restores_checkpoints() {
+    let mut history = EditHistory::new(empty(), usize::MAX);
+    history.checkpoint("empty");
+    let empty = snapshot(&history);
+
+    history.set_voxel(UVec3::new(1, 1, 1), 3, RED);
+    history.set_voxel(UVec3::new(6, 1, 1), 3, RED);
+    history.checkpoint("two");
+    let two = snapshot(&history);
+
+    history.clear_voxel(UVec3::new(1, 1, 1), 3);
+    history.paint_voxel(UVec3::new(6, 1, 1), 3, 0xff0000ff);
+    history.checkpoint("four");
+    let four = snapshot(&history);
+
+    // back through the undo stack
+    assert!(history.restore("two"));
+    assert!(snapshot(&history) == two);
+    assert!(history.restore("empty"));
+    assert!(snapshot(&history) == empty);
+
+    // and forward through the redo stack
+    assert!(history.restore("four"));
+    assert!(snapshot(&history) == four);
+    assert!(history.restore("two"));
+    assert!(history.restore("four"));
+    assert!(snapshot(&history) == four);
+
+    // restoring where it already is changes nothing
+    assert!(history.restore("four"));
+    assert!(snapshot(&history) == four);
+    assert!(!history.restore("missing"));
+
+    // checkpoints can be renamed in place
+    history.checkpoint("two");
+    assert!(history.restore("empty"));
+    assert!(history.restore("two"));
+    assert!(snapshot(&history) == four);
+
+    // a new edit after going back drops the checkpoints ahead of it
+    assert!(history.restore("empty"));
+    history.set_voxel(UVec3::new(2, 2, 2), 3, RED);
+    let names: Vec<_> = history.checkpoints().collect();
+    assert_eq!(names, vec!["empty"]);
+    assert!(!history.restore("four"));
+    assert!(history.restore("empty"));
+    assert!(snapshot(&history) == empty);
+}
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+mod common;
+
+use common::{build, payload_at, RED};
+use glam::{Mat4, Vec3, Vec3A, Vec4};
+use svo_cpu::camera::Camera;
+use svo_cpu::fb::ColorBuffer;
+use svo_cpu::lighting::{shade_pixel, DirectionalLight, Lighting};
+use svo_cpu::pick::IdBuffer;
+use svo_cpu::voxbuf::{Node, Payload, VoxBuf};
+
+/// a 16^3 grid filled where `filled` says so, one leaf per voxel
+fn grid(filled: impl Fn(u32, u32, u32) -> bool) -> VoxBuf {
+    let mut cells = Vec::new();
+    for z in 0..16 {
+        for y in 0..16 {
+            for x in 0..16 {
+                if filled(x, y, z) {
+                    cells.push((4, [x, y, z], RED));
+                }
+            }
+        }
+    }
+    build(&cells)
+}
+
+fn assert_near(actual: Vec3A, expected: Vec3A) {
+    assert!(
+        (actual - expected).abs().max_element() < 1e-5,
+        "{:?} != {:?}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn finds_slab_normals() {
+    // four layers, from y = 6 to y = 9
+    let vb = grid(|_x, y, _z| (6..10).contains(&y));
+    let normals = vb.node_normals();
+    assert_eq!(normals.len(), vb.view().nodes().len());
+
+    let mut checked = 0;
+    vb.walk_cells(|node_ref, node, cell| {
+        let normal = normals[node_ref as usize];
+        let [x, y, z] = cell.pos.to_array();
+        let inside = |p: u32| (2..14).contains(&p);
+        if cell.depth == 4 && inside(x) && inside(z) {
+            assert!(node.is_leaf());
+            match y {
+                9 => assert_near(normal, Vec3A::Y),
+                6 => assert_near(normal, -Vec3A::Y),
+                _ => assert_near(normal.abs(), Vec3A::Y),
+            }
+            checked += 1;
+        }
+        true
+    });
+    assert_eq!(checked, 12 * 4 * 12);
+}
+
+#[test]
+fn lights_by_normal() {
+    let lighting = Lighting {
+        ambient: Vec3A::new(0.1, 0.2, 0.3),
+        lights: vec![
+            DirectionalLight {
+                direction: Vec3A::Y,
+                color: Vec3A::new(0.5, 0.5, 0.5),
+            },
+            DirectionalLight {
+                direction: Vec3A::X,
+                color: Vec3A::new(0.0, 0.0, 1.0),
+            },
+        ],
+    };
+
+    assert_near(lighting.light(Vec3A::Y), Vec3A::new(0.6, 0.7, 0.8));
+    assert_near(lighting.light(Vec3A::X), Vec3A::new(0.1, 0.2, 1.3));
+
+    // lights only reach the side they shine on, at the cosine of the angle
+    let diagonal = Vec3A::new(1.0, 1.0, 0.0).normalize();
+    let cosine = 0.5f32.sqrt();
+    let expected = Vec3A::new(0.1, 0.2, 0.3) + Vec3A::new(0.5, 0.5, 1.5) * cosine;
+    assert_near(lighting.light(diagonal), expected);
+    assert_near(lighting.light(-diagonal), lighting.ambient);
+    assert_near(lighting.light(Vec3A::ZERO), lighting.ambient);
+}
+
+#[test]
+fn shades_pixels() {
+    let light = Vec3A::new(0.5, 2.0, 1.0);
+    assert_eq!(shade_pixel(0x80402010, light), 0x80204010);
+    assert_eq!(shade_pixel(0x00402010, Vec3A::ZERO), 0x00000000);
+    assert_eq!(shade_pixel(0xff123456, Vec3A::ONE), 0xff123456);
+
+    // channels saturate instead of spilling into their neighbors
+    assert_eq!(shade_pixel(0xffc0c0c0, Vec3A::splat(2.0)), 0xffffffff);
+    assert_eq!(shade_pixel(0xffc08040, light), 0xff60ff40);
+}
+
+#[test]
+fn shades_side_facing_camera() {
+    // a camera between two voxels, looking up at one and away from the
+    // other and from the middle of the grid
+    let eye = Vec3::new(0.0, 0.2, 0.0);
+    let view = Mat4::look_at_lh(eye, Vec3::new(0.0, 1.0, 0.0), Vec3::Z);
+    let projection = Mat4::perspective_rh(60f32.to_radians(), 1.0, 0.01, 100.0);
+    let camera = Camera {
+        eye: Vec3A::from(eye),
+        vp: projection * view,
+    };
+
+    let mut fb = ColorBuffer {
+        width: 2,
+        height: 1,
+        px: 1.0,
+        data: vec![0xff404040; 2],
+    };
+    let mut ids = IdBuffer::new(&fb);
+    ids.data = vec![0, 0];
+    ids.voxels = vec![
+        Vec4::new(0.0, 0.5, 0.0, 0.03125),
+        Vec4::new(0.0, -0.5, 0.0, 0.03125),
+    ];
+
+    let lighting = Lighting {
+        ambient: Vec3A::splat(0.2),
+        lights: vec![DirectionalLight {
+            direction: -Vec3A::Y,
+            color: Vec3A::ONE,
+        }],
+    };
+    lighting.shade(&camera, &mut fb, &ids, &[Vec3A::Y]);
+
+    // the voxel above faces down to the camera and into the light, for
+    // 0x40 * 1.2 = 0x4c, and the one below faces up, away from the light,
+    // for 0x40 * 0.2 = 0x0c
+    assert_eq!(fb.data, vec![0xff4c4c4c, 0xff0c0c0c]);
+}
+
+#[test]
+fn occludes_corners_more_than_faces() {
+    // a floor four voxels thick, with a wall as thick along one side of it
+    let mut vb = grid(|x, y, _z| y < 4 || x < 4);
+    vb.bake_occlusion();
+
+    let corner = payload_at(&vb, 4, [4, 3, 8]).occlusion;
+    let face = payload_at(&vb, 4, [12, 3, 8]).occlusion;
+    let wall = payload_at(&vb, 4, [3, 12, 8]).occlusion;
+    assert!(corner > face, "{} <= {}", corner, face);
+    assert!(corner > wall, "{} <= {}", corner, wall);
+
+    // building lod colors afterwards averages branches the same way
+    let baked = vb.view().nodes().to_vec();
+    vb.build_lod_colors();
+    let occlusion = |nodes: &[Node]| {
+        nodes
+            .iter()
+            .map(|node| node.data.occlusion)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(occlusion(vb.view().nodes()), occlusion(&baked));
+    assert!(baked
+        .iter()
+        .any(|node| !node.is_leaf() && node.data.occlusion > 0));
+}
+
+#[test]
+fn darkens_occluded_colors() {
+    let data = |occlusion| Payload {
+        color: 0x80c08040,
+        occlusion,
+    };
+    assert_eq!(data(0).occluded_color(), 0x80c08040);
+    assert_eq!(data(128).occluded_color(), 0x805f3f1f);
+    assert_eq!(data(255).occluded_color(), 0x80000000);
+}
@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+mod common;
+
+use common::{build, payload_at};
+use glam::{UVec3, Vec3A};
+use svo_cpu::voxbuf::Payload;
+
+#[test]
+fn averages_by_coverage() {
+    // a full red octant, and a quarter filled one in green and blue
+    let vb = build(&[
+        (1, [0, 0, 0], (0xffff0000, 0)),
+        (2, [2, 0, 0], (0xff00ff00, 40)),
+        (2, [3, 0, 0], (0xff0000ff, 80)),
+    ]);
+
+    // the two halves of a channel round up
+    let partial = Payload {
+        color: 0xff008080,
+        occlusion: 60,
+    };
+    assert_eq!(payload_at(&vb, 1, [1, 0, 0]), partial);
+
+    // the partial octant weighs a quarter as much as the full one:
+    // 0xff / 1.25 = 0xcc and 0x80 * 0.25 / 1.25 = 25.6, or 0x1a
+    let root = Payload {
+        color: 0xffcc1a1a,
+        occlusion: 12,
+    };
+    assert_eq!(payload_at(&vb, 0, [0, 0, 0]), root);
+
+    // and averaging again changes nothing
+    let mut again = vb.clone();
+    again.build_lod_colors();
+    assert_eq!(again.view().nodes(), vb.view().nodes());
+}
+
+#[test]
+fn averages_shared_subtrees() {
+    // the same partial octant twice, beside a full one
+    let mut vb = build(&[
+        (1, [0, 0, 0], (0xffff0000, 0)),
+        (2, [2, 0, 0], (0xff00ff00, 40)),
+        (2, [3, 0, 0], (0xff0000ff, 80)),
+        (2, [2, 2, 0], (0xff00ff00, 40)),
+        (2, [3, 2, 0], (0xff0000ff, 80)),
+    ]);
+
+    // painting the full octant leaves the root's color out of date
+    vb.paint_voxel(UVec3::ZERO, 1, 0xff00ff00);
+    let mut dag = vb.clone();
+    dag.compress_dag();
+    assert!(dag.view().reachable_nodes() < vb.view().reachable_nodes());
+    vb.build_lod_colors();
+    dag.build_lod_colors();
+
+    // 0xff / 1.5 + 0x80 * 0.5 / 1.5 = 212.7, or 0xd5, and 0x80 * 0.5 / 1.5
+    // = 42.7, or 0x2b
+    let root = Payload {
+        color: 0xff00d52b,
+        occlusion: 20,
+    };
+    assert_eq!(payload_at(&vb, 0, [0, 0, 0]), root);
+    let eye = Vec3A::new(2.0, 3.0, -1.0);
+    assert_eq!(dag.walk_all(&eye), vb.walk_all(&eye));
+}
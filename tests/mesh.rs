@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::{Vec2, Vec3A};
+use svo_cpu::mesh::{load_obj, ObjError};
+
+#[test]
+fn loads_obj_polygons() {
+    let obj = "# a quad and a triangle\n\
+               v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0 1.0\n\
+               vn 0 0 1\nusemtl ignored\n\
+               f 1 2 3 4\nf -4 -2 -1\n";
+    let mesh = load_obj(obj.as_bytes()).unwrap();
+
+    // the quad is split into a fan, and corners share their vertices
+    assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3], [0, 2, 3]]);
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.positions[2], Vec3A::new(1.0, 1.0, 0.0));
+    assert!(mesh.colors.is_empty());
+    assert!(mesh.uvs.is_empty());
+}
+
+#[test]
+fn loads_obj_colors_and_uvs() {
+    let obj = "v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 0 1 0 0 0 2\n\
+               vt 0 0\nvt 1 0\nvt 0 1 0\n\
+               f 1/1 2/2 3/3\nf 1/3/1 2//1 3/1\n";
+    let mesh = load_obj(obj.as_bytes()).unwrap();
+
+    // every distinct position and uv pair is its own vertex
+    assert_eq!(mesh.positions.len(), 6);
+    assert_eq!(mesh.triangles, vec![[0, 1, 2], [3, 4, 5]]);
+    assert_eq!(
+        mesh.colors,
+        vec![0xffff0000, 0xff00ff00, 0xff0000ff, 0xffff0000, 0xff00ff00, 0xff0000ff]
+    );
+    assert_eq!(mesh.uvs[1], Vec2::new(1.0, 0.0));
+    assert_eq!(mesh.uvs[3], Vec2::new(0.0, 1.0));
+    // a corner without a uv gets zero
+    assert_eq!(mesh.uvs[4], Vec2::ZERO);
+}
+
+#[test]
+fn rejects_malformed_obj() {
+    let malformed = [
+        ("v 0 0\n", 1),
+        ("v 0 0 0 1 1\n", 1),
+        ("v 0 zero 0\n", 1),
+        ("v 0 0 0\nv nan 0 0\n", 2),
+        ("v 0 0 0\nv inf 0 0\n", 2),
+        ("v 0 0 0 1 NaN 1\n", 1),
+        ("vt\n", 1),
+        ("vt -inf 0\n", 1),
+        ("v 0 0 0\nv 1 0 0\nf 1 2\n", 3),
+    ];
+
+    for (obj, line) in malformed.iter() {
+        match load_obj(obj.as_bytes()) {
+            Err(ObjError::Malformed { line: found }) => assert_eq!(found, *line, "{:?}", obj),
+            other => panic!("{:?} gave {:?}", obj, other.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn rejects_invalid_obj_indices() {
+    let invalid = [
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n",
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n",
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -4 1 2\n",
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2 3\n",
+    ];
+
+    for obj in invalid.iter() {
+        assert!(
+            matches!(
+                load_obj(obj.as_bytes()),
+                Err(ObjError::InvalidIndex { line: 4 })
+            ),
+            "{:?}",
+            obj
+        );
+    }
+}
@@ -0,0 +1,241 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+mod common;
+
+use common::build;
+use std::collections::HashMap;
+use svo_cpu::mesh::{load_obj, write_obj, write_ply, Mesh};
+use svo_cpu::meshing::{greedy_mesh, isosurface};
+use svo_cpu::voxbuf::VoxBuf;
+
+/// the voxels of a grid of 2^depth voxels per axis within `radius` voxels of
+/// its center
+fn sphere(depth: u32, radius: f32) -> VoxBuf {
+    let size = 1 << depth;
+    let center = size as f32 / 2.0;
+    let voxels: Vec<_> = (0..size * size * size)
+        .filter_map(|index| {
+            let pos = [index % size, (index / size) % size, index / (size * size)];
+            let distance = pos
+                .iter()
+                .map(|axis| (*axis as f32 + 0.5 - center).powi(2))
+                .sum::<f32>()
+                .sqrt();
+            if distance < radius {
+                Some((depth, pos, 0xff000000 | (pos[1] * 16) << 8))
+            } else {
+                None
+            }
+        })
+        .collect();
+    build(&voxels)
+}
+
+/// the volume enclosed by a mesh, by the divergence theorem
+fn signed_volume(mesh: &Mesh) -> f32 {
+    mesh.triangles
+        .iter()
+        .map(|triangle| {
+            let [a, b, c] = triangle.map(|index| mesh.positions[index as usize]);
+            a.dot(b.cross(c)) / 6.0
+        })
+        .sum()
+}
+
+/// checks that every edge is shared by exactly two triangles that run along
+/// it in opposite directions, so the mesh is closed, manifold and
+/// consistently oriented
+fn assert_watertight(mesh: &Mesh) {
+    let mut edges = HashMap::<(u32, u32), usize>::new();
+    for [a, b, c] in mesh.triangles.iter() {
+        for edge in [(*a, *b), (*b, *c), (*c, *a)].iter() {
+            *edges.entry(*edge).or_default() += 1;
+        }
+    }
+
+    for ((from, to), count) in edges.iter() {
+        assert_eq!(*count, 1, "edge {} -> {} is used {} times", from, to, count);
+        assert_eq!(
+            edges.get(&(*to, *from)),
+            Some(&1),
+            "edge {} -> {} is open",
+            from,
+            to
+        );
+    }
+}
+
+#[test]
+fn merges_solid_block_into_six_quads() {
+    // a 2x2x2 block of one color away from the grid's boundary
+    let block = (0..8).map(|index| {
+        let pos = [1 + (index & 1), 1 + ((index >> 1) & 1), 1 + (index >> 2)];
+        (2, pos, 0xff336699)
+    });
+    let vb = build(&block.collect::<Vec<_>>());
+    let mesh = greedy_mesh(&vb, 2);
+
+    assert_eq!(mesh.triangles.len(), 6 * 2);
+    assert_eq!(mesh.positions.len(), 6 * 4);
+    assert!(mesh.colors.iter().all(|color| *color == 0xff336699));
+
+    // the grid spans [-1, 1], so the block spans [-0.5, 0.5]
+    let (min, max) = mesh.aabb().unwrap();
+    assert_eq!(min.to_array(), [-0.5; 3]);
+    assert_eq!(max.to_array(), [0.5; 3]);
+    assert!((signed_volume(&mesh) - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn keeps_colors_apart() {
+    // two voxels of different colors share no quads
+    let vb = build(&[(1, [0, 0, 0], 0xffff0000), (1, [1, 0, 0], 0xff0000ff)]);
+    let mesh = greedy_mesh(&vb, 1);
+    assert_eq!(mesh.triangles.len(), 10 * 2);
+
+    // the same voxels in one color merge along x
+    let vb = build(&[(1, [0, 0, 0], 0xffff0000), (1, [1, 0, 0], 0xffff0000)]);
+    let mesh = greedy_mesh(&vb, 1);
+    assert_eq!(mesh.triangles.len(), 6 * 2);
+}
+
+#[test]
+fn encloses_sphere_volume() {
+    let depth = 4;
+    let vb = sphere(depth, 6.0);
+    let mesh = greedy_mesh(&vb, depth);
+
+    // every exposed face is covered once, facing out, so the mesh encloses
+    // exactly the voxels' volume
+    let voxel = 2.0 / (1 << depth) as f32;
+    let volume = vb.voxels(depth).len() as f32 * voxel.powi(3);
+    assert!((signed_volume(&mesh) - volume).abs() < 1e-4);
+
+    // and the quads cover exactly the exposed faces
+    let voxels = vb.voxels(depth);
+    let exposed: usize = voxels
+        .keys()
+        .map(|[x, y, z]| {
+            let (x, y, z) = (*x as i64, *y as i64, *z as i64);
+            let neighbors = [
+                [x - 1, y, z],
+                [x + 1, y, z],
+                [x, y - 1, z],
+                [x, y + 1, z],
+                [x, y, z - 1],
+                [x, y, z + 1],
+            ];
+            neighbors
+                .iter()
+                .filter(|[x, y, z]| {
+                    let key = [*x as u32, *y as u32, *z as u32];
+                    [*x, *y, *z].iter().any(|axis| *axis < 0) || !voxels.contains_key(&key)
+                })
+                .count()
+        })
+        .sum();
+    let area: f32 = mesh
+        .triangles
+        .iter()
+        .map(|triangle| {
+            let [a, b, c] = triangle.map(|index| mesh.positions[index as usize]);
+            (b - a).cross(c - a).length() / 2.0
+        })
+        .sum();
+    assert!((area - exposed as f32 * voxel * voxel).abs() < 1e-3);
+}
+
+#[test]
+fn exports_obj_and_ply() {
+    let vb = sphere(3, 3.0);
+    let mesh = greedy_mesh(&vb, 3);
+
+    let mut obj = Vec::new();
+    write_obj(&mesh, &mut obj).unwrap();
+    // load_obj() numbers the vertices in the order the faces use them
+    let corners = |mesh: &Mesh| {
+        mesh.triangles
+            .iter()
+            .map(|triangle| {
+                triangle.map(|index| {
+                    let index = index as usize;
+                    (mesh.positions[index].to_array(), mesh.colors[index])
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let loaded = load_obj(&obj[..]).unwrap();
+    assert_eq!(corners(&loaded), corners(&mesh));
+
+    let mut ply = Vec::new();
+    write_ply(&mesh, &mut ply).unwrap();
+    let ply = String::from_utf8(ply).unwrap();
+    let (header, body) = ply.split_at(ply.find("end_header\n").unwrap() + 11);
+    assert!(header.contains(&format!("element vertex {}", mesh.positions.len())));
+    assert!(header.contains(&format!("element face {}", mesh.triangles.len())));
+    assert!(header.contains("property uchar red"));
+
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), mesh.positions.len() + mesh.triangles.len());
+    let faces: Vec<[u32; 3]> = lines[mesh.positions.len()..]
+        .iter()
+        .map(|line| {
+            let words: Vec<u32> = line.split(' ').map(|word| word.parse().unwrap()).collect();
+            assert_eq!(words[0], 3);
+            [words[1], words[2], words[3]]
+        })
+        .collect();
+    assert_eq!(faces, mesh.triangles);
+}
+
+#[test]
+fn surrounds_single_voxel() {
+    let vb = build(&[(1, [0, 0, 0], 0xff00ff00)]);
+    let mesh = isosurface(&vb, 1);
+
+    // the dual grid's tetrahedra join the voxel to 14 of its neighbors: 6
+    // across faces, 6 across the edges its diagonal split runs along and 2
+    // across corners, and each of its 24 tetrahedra gives one triangle
+    assert_eq!(mesh.positions.len(), 14);
+    assert_eq!(mesh.triangles.len(), 24);
+    assert!(mesh.colors.iter().all(|color| *color == 0xff00ff00));
+    assert_watertight(&mesh);
+    assert!(signed_volume(&mesh) > 0.0);
+
+    // the surface stays around the voxel's [-1, 0] cube
+    let (min, max) = mesh.aabb().unwrap();
+    assert!(min.cmpgt(glam::Vec3A::splat(-1.5)).all());
+    assert!(max.cmplt(glam::Vec3A::splat(0.5)).all());
+}
+
+#[test]
+fn closes_sphere() {
+    let depth = 5;
+    let radius = 12.0;
+    let vb = sphere(depth, radius);
+    let mesh = isosurface(&vb, depth);
+    assert_watertight(&mesh);
+
+    // the smoothed surface stays close to the sphere it was sampled from
+    let voxel = 2.0 / (1 << depth) as f32;
+    let sphere = 4.0 / 3.0 * std::f32::consts::PI * (radius * voxel).powi(3);
+    let volume = signed_volume(&mesh);
+    assert!(
+        (volume - sphere).abs() / sphere < 0.1,
+        "{} vs {}",
+        volume,
+        sphere
+    );
+    // vertices next to larger empty leaves sit halfway to their centers,
+    // which are a few voxels out
+    for position in mesh.positions.iter() {
+        let distance = position.length() / voxel;
+        assert!((distance - radius).abs() < 3.0, "{}", distance);
+    }
+
+    // cutting the tree off higher up gives a coarser surface that still closes
+    let coarse = isosurface(&vb, depth - 2);
+    assert_watertight(&coarse);
+    assert!(coarse.triangles.len() < mesh.triangles.len());
+}
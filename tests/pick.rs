@@ -0,0 +1,160 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::{Mat4, UVec3, Vec3, Vec3A, Vec4};
+use svo_cpu::camera::{Camera, DrawConfig};
+use svo_cpu::fb::ColorBuffer;
+use svo_cpu::pick::{IdBuffer, VoxelHit};
+use svo_cpu::voxbuf::{Cell, NodeRef, Payload, TreeBuilder, VoxBuf};
+
+const RED: Payload = Payload {
+    color: 0xffff0000,
+    occlusion: 3,
+};
+
+const GREEN: Payload = Payload {
+    color: 0xff00ff00,
+    occlusion: 7,
+};
+
+/// a coarse red voxel, and the same green voxel in two neighboring octants
+fn tree() -> VoxBuf {
+    let mut builder = TreeBuilder::default();
+    let cells = [
+        (1, [0, 1, 0], RED),
+        (2, [0, 0, 0], GREEN),
+        (2, [2, 0, 0], GREEN),
+    ];
+    for (depth, pos, data) in cells.iter() {
+        let cell = Cell {
+            pos: UVec3::from(*pos),
+            depth: *depth,
+        };
+        builder.insert(cell, *data);
+    }
+    builder.build()
+}
+
+/// the node at a cell
+fn node_at(vb: &VoxBuf, cell: Cell) -> NodeRef {
+    let mut found = None;
+    vb.walk_cells(|node_ref, _node, at| {
+        if at == cell {
+            found = Some(node_ref);
+        }
+        true
+    });
+    found.unwrap()
+}
+
+/// draws the tree head-on from in front of it, and picks the pixel under
+/// each voxel's center
+fn pick(vb: &VoxBuf, centers: &[[f32; 3]]) -> Vec<Option<VoxelHit>> {
+    let (width, height) = (320, 240);
+    let mut fb = ColorBuffer {
+        width,
+        height,
+        px: height as f32,
+        data: vec![0; width * height],
+    };
+
+    let eye = Vec3::new(0.0, 0.0, -4.0);
+    let view = Mat4::look_at_lh(eye, Vec3::ZERO, Vec3::Y);
+    let projection = Mat4::perspective_rh(60f32.to_radians(), 4.0 / 3.0, 0.1, 100.0);
+    let camera = Camera {
+        eye: Vec3A::from(eye),
+        vp: projection * view,
+    };
+    let config = DrawConfig {
+        min_rect: 0.5 / fb.px,
+        max_rect: 6.0 / fb.px,
+        max_test: 1024,
+        occlusion: false,
+    };
+
+    let mut ids = IdBuffer::new(&fb);
+    vb.draw_with_ids(&camera, &config, &mut fb, &mut ids);
+    assert!(vb.pick(&ids, 0, 0).is_none());
+    assert!(vb.pick(&ids, width, 0).is_none());
+
+    centers
+        .iter()
+        .map(|center| {
+            let projected = camera.project_voxel(&Vec3A::from(*center).extend(0.0));
+            let (x, y) = fb.frag_xy(&projected);
+            vb.pick(&ids, x, y)
+        })
+        .collect()
+}
+
+#[test]
+fn picks_voxel_under_pixel() {
+    let vb = tree();
+    let centers = [
+        [-0.5, 0.5, -0.5],
+        [-0.75, -0.75, -0.75],
+        [0.25, -0.75, -0.75],
+    ];
+    let hits = pick(&vb, &centers);
+
+    let cells = [([0, 1, 0], 1), ([0, 0, 0], 2), ([2, 0, 0], 2)];
+    let expected = [RED, GREEN, GREEN];
+    for index in 0..3 {
+        let (pos, depth) = cells[index];
+        let cell = Cell {
+            pos: UVec3::from(pos),
+            depth,
+        };
+        let hit = VoxelHit {
+            node: node_at(&vb, cell),
+            voxel: Vec3A::from(centers[index]).extend(VoxBuf::depth_to_offset(depth)),
+            data: expected[index],
+        };
+        assert_eq!(hits[index], Some(hit));
+    }
+}
+
+#[test]
+fn picks_shared_nodes_where_drawn() {
+    let mut dag = tree();
+    dag.compress_dag();
+
+    // both green voxels are the same node, but each keeps its own place
+    let green = node_at(
+        &dag,
+        Cell {
+            pos: UVec3::new(2, 0, 0),
+            depth: 2,
+        },
+    );
+    let centers = [[-0.75, -0.75, -0.75], [0.25, -0.75, -0.75]];
+    let hits = pick(&dag, &centers);
+    for (hit, center) in hits.iter().zip(centers.iter()) {
+        let expected = VoxelHit {
+            node: green,
+            voxel: Vec4::new(center[0], center[1], center[2], 0.125),
+            data: GREEN,
+        };
+        assert_eq!(*hit, Some(expected));
+    }
+}
+
+#[test]
+fn ignores_ids_of_missing_nodes() {
+    let vb = tree();
+    let fb = ColorBuffer {
+        width: 4,
+        height: 4,
+        px: 4.0,
+        data: vec![0; 16],
+    };
+    let mut ids = IdBuffer::new(&fb);
+    let last = vb.view().nodes().len() as NodeRef - 1;
+    ids.data[5] = last;
+    assert!(vb.pick(&ids, 1, 1).is_some());
+
+    // ids kept from a bigger tree, or from before an edit shrank it
+    ids.data[5] = last + 1;
+    assert_eq!(vb.pick(&ids, 1, 1), None);
+    assert_eq!(VoxBuf::new().pick(&ids, 1, 1), None);
+}
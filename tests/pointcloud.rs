@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::Vec3A;
+use svo_cpu::pointcloud::{import_ply_points, import_xyz_points, PointCloudError};
+use svo_cpu::voxbuf::VoxBuf;
+
+/// four points in three unit leaves, with the first two sharing a leaf
+const POINTS: [([f32; 3], [u8; 3]); 4] = [
+    ([0.25, 0.25, 0.25], [255, 0, 0]),
+    ([0.75, 0.5, 0.5], [0, 0, 255]),
+    ([2.5, 0.5, 0.5], [0, 255, 0]),
+    ([-0.5, 3.5, 0.5], [255, 255, 255]),
+];
+
+/// the leaves of POINTS relative to the leaf at (-1, 0, 0), with the first
+/// two points' colors averaged
+const LEAVES: [([u32; 3], u32); 3] = [
+    ([0, 3, 0], 0xffffffff),
+    ([1, 0, 0], 0xff800080),
+    ([3, 0, 0], 0xff00ff00),
+];
+
+fn leaves(vb: &VoxBuf) -> Vec<([u32; 3], u32)> {
+    let mut leaves: Vec<_> = vb.voxels(vb.depth()).into_iter().collect();
+    leaves.sort_unstable();
+    leaves
+}
+
+fn assert_points(vb: &VoxBuf) {
+    assert_eq!(leaves(vb), LEAVES.to_vec());
+    assert_eq!(vb.bounds().min, Vec3A::new(-1.0, 0.0, 0.0));
+    assert_eq!(vb.bounds().size, 4.0);
+}
+
+/// a PLY of POINTS with a face element before the vertices, which has to be
+/// skipped over
+fn ply_header(format: &str) -> String {
+    format!(
+        "ply\nformat {} 1.0\ncomment made by hand\n\
+         element face 2\nproperty list uchar int vertex_indices\n\
+         element vertex 4\nproperty double x\nproperty float y\nproperty float z\n\
+         property uchar red\nproperty uchar green\nproperty uchar blue\nend_header\n",
+        format
+    )
+}
+
+fn ascii_ply() -> Vec<u8> {
+    let mut ply = ply_header("ascii");
+    ply += "3 0 1 2\n3 1 2 3\n";
+    for ([x, y, z], [r, g, b]) in POINTS.iter() {
+        ply += &format!("{} {} {} {} {} {}\n", x, y, z, r, g, b);
+    }
+    ply.into_bytes()
+}
+
+fn binary_ply(big_endian: bool) -> Vec<u8> {
+    let format = if big_endian {
+        "binary_big_endian"
+    } else {
+        "binary_little_endian"
+    };
+    let mut ply = ply_header(format).into_bytes();
+
+    let mut push = |bytes: &mut [u8]| {
+        if big_endian {
+            bytes.reverse();
+        }
+        ply.extend_from_slice(bytes);
+    };
+
+    for face in [[0, 1, 2], [1, 2, 3]].iter() {
+        push(&mut [3]);
+        for index in face.iter() {
+            push(&mut i32::to_le_bytes(*index));
+        }
+    }
+
+    for ([x, y, z], rgb) in POINTS.iter() {
+        push(&mut (*x as f64).to_le_bytes());
+        push(&mut y.to_le_bytes());
+        push(&mut z.to_le_bytes());
+        for channel in rgb.iter() {
+            push(&mut [*channel]);
+        }
+    }
+
+    ply
+}
+
+#[test]
+fn imports_ascii_ply() {
+    let vb = import_ply_points(&ascii_ply()[..], 1.0).unwrap();
+    assert_points(&vb);
+}
+
+#[test]
+fn imports_binary_ply() {
+    for big_endian in [false, true] {
+        let vb = import_ply_points(&binary_ply(big_endian)[..], 1.0).unwrap();
+        assert_points(&vb);
+    }
+}
+
+#[test]
+fn imports_float_colored_ply() {
+    let ply = "ply\nformat ascii 1.0\nelement vertex 2\n\
+               property float x\nproperty float y\nproperty float z\n\
+               property float r\nproperty float g\nproperty float b\nend_header\n\
+               0 0 0 1.0 0.5 0\n1 0 0 0 0 2.0\n";
+    let vb = import_ply_points(ply.as_bytes(), 1.0).unwrap();
+    assert_eq!(
+        leaves(&vb),
+        vec![([0, 0, 0], 0xffff8000), ([1, 0, 0], 0xff0000ff)]
+    );
+}
+
+#[test]
+fn imports_xyz() {
+    let xyz = "# x y z [intensity] [r g b]\n\
+               // another comment\n\
+               0.25 0.25 0.25 255 0 0\n\
+               0.75,0.5,0.5,0.3,0,0,255\n\
+               \n\
+               2.5 0.5 0.5 0.0 1.0 0.0\n\
+               -0.5 3.5 0.5 1 1.0 1.0 1.0\n";
+    let vb = import_xyz_points(xyz.as_bytes(), 1.0).unwrap();
+    assert_points(&vb);
+
+    // uncolored points take the default color
+    let vb = import_xyz_points("0 0 0\n1 1 1 0.5\n".as_bytes(), 0.5).unwrap();
+    assert_eq!(vb.depth(), 2);
+    assert_eq!(vb.voxels(2).len(), 2);
+    assert!(vb.voxels(2).values().all(|color| *color == 0xff0000ff));
+
+    // as do non-finite ones, which are skipped
+    let vb = import_xyz_points("0 0 0\nnan 0 0\n0 inf 0\n".as_bytes(), 1.0).unwrap();
+    assert_eq!(vb.voxels(0).len(), 1);
+}
+
+#[test]
+fn rejects_bad_ply_headers() {
+    let vertex = "element vertex 1\nproperty float x\nproperty float y\nproperty float z\n";
+    let headers = [
+        String::new(),
+        format!("PLY\nformat ascii 1.0\n{}end_header\n", vertex),
+        format!("ply\n{}end_header\n", vertex),
+        format!(
+            "ply\nformat binary_middle_endian 1.0\n{}end_header\n",
+            vertex
+        ),
+        format!("ply\nformat ascii 1.0\n{}", vertex),
+        format!("ply\nformat ascii 1.0\n{}bogus\nend_header\n", vertex),
+        "ply\nformat ascii 1.0\nproperty float x\nend_header\n".to_string(),
+        "ply\nformat ascii 1.0\nelement vertex many\nend_header\n".to_string(),
+        format!(
+            "ply\nformat ascii 1.0\n{}property half w\nend_header\n",
+            vertex
+        ),
+        format!(
+            "ply\nformat ascii 1.0\n{}property list uchar w\nend_header\n",
+            vertex
+        ),
+    ];
+
+    for header in headers.iter() {
+        let result = import_ply_points(format!("{}0 0 0\n", header).as_bytes(), 1.0);
+        assert!(
+            matches!(result, Err(PointCloudError::BadHeader)),
+            "{:?} gave {:?}",
+            header,
+            result.map(|_| ())
+        );
+    }
+
+    let no_z = "ply\nformat ascii 1.0\nelement vertex 1\n\
+                property float x\nproperty float y\nend_header\n0 0\n";
+    assert!(matches!(
+        import_ply_points(no_z.as_bytes(), 1.0),
+        Err(PointCloudError::Unsupported)
+    ));
+}
+
+#[test]
+fn rejects_truncated_and_malformed_points() {
+    let ascii = ascii_ply();
+    let text = String::from_utf8(ascii.clone()).unwrap();
+    let last_line = text.trim_end().rfind('\n').unwrap() + 1;
+    assert!(matches!(
+        import_ply_points(&ascii[..last_line], 1.0),
+        Err(PointCloudError::Truncated)
+    ));
+
+    // the header is 13 lines and the faces 2 more
+    let mut malformed = text[..last_line].to_string();
+    malformed += "1 2 three 0 0 0\n";
+    assert!(matches!(
+        import_ply_points(malformed.as_bytes(), 1.0),
+        Err(PointCloudError::Malformed { line: 19 })
+    ));
+
+    for big_endian in [false, true] {
+        let binary = binary_ply(big_endian);
+        let end = b"end_header\n";
+        let faces = binary.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        for len in [binary.len() - 1, binary.len() - 20, faces + 5] {
+            assert!(matches!(
+                import_ply_points(&binary[..len], 1.0),
+                Err(PointCloudError::Truncated)
+            ));
+        }
+    }
+
+    for xyz in ["0 0\n", "0 0 0 0 0\n", "0 0 zero\n", "0 0 0 red 0 0\n"].iter() {
+        let xyz = format!("# comment\n1 1 1\n{}", xyz);
+        assert!(matches!(
+            import_xyz_points(xyz.as_bytes(), 1.0),
+            Err(PointCloudError::Malformed { line: 3 })
+        ));
+    }
+}
+
+#[test]
+fn rejects_out_of_range_points() {
+    let clouds = [
+        // leaves too far apart for an octree
+        "0 0 0\n3000000000 0 0\n",
+        // leaves whose coordinates would overflow an i64
+        "1e30 0 0\n",
+        "1e30 0 0\n-1e30 0 0\n",
+        "0 0 -3e38\n0 0 3e38\n",
+    ];
+
+    for xyz in clouds.iter() {
+        assert!(
+            matches!(
+                import_xyz_points(xyz.as_bytes(), 1.0),
+                Err(PointCloudError::TooLarge)
+            ),
+            "{:?}",
+            xyz
+        );
+    }
+
+    // a finite point can still be out of range once divided into leaves
+    assert!(matches!(
+        import_xyz_points("1 0 0\n".as_bytes(), 1e-30),
+        Err(PointCloudError::TooLarge)
+    ));
+}
@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::{Mat4, UVec3, Vec3, Vec3A, Vec4};
+use svo_cpu::binvox::import_binvox_svo;
+use svo_cpu::camera::{Camera, DrawConfig};
+use svo_cpu::fb::{ColorBuffer, DepthBuffer};
+use svo_cpu::voxbuf::{Cell, Payload, TreeBuilder, VoxBuf};
+
+fn bunny() -> VoxBuf {
+    import_binvox_svo(include_bytes!("../examples/models/stanford_bunny.binvox"))
+}
+
+fn framebuffer() -> ColorBuffer {
+    let (width, height) = (320, 240);
+    ColorBuffer {
+        width,
+        height,
+        px: height as f32,
+        data: vec![0; width * height],
+    }
+}
+
+/// a camera like SpinnyCamera's, looking at the model from `eye`
+fn camera(eye: [f32; 3], fb: &ColorBuffer) -> (Camera, DrawConfig) {
+    camera_at(eye, [0.0, -0.15, 0.0], fb)
+}
+
+/// a camera like SpinnyCamera's, looking at `target` from `eye`
+fn camera_at(eye: [f32; 3], target: [f32; 3], fb: &ColorBuffer) -> (Camera, DrawConfig) {
+    let eye = Vec3::from(eye);
+    let view = Mat4::look_at_lh(eye, Vec3::from(target), Vec3::Y);
+    let aspect = fb.width as f32 / fb.height as f32;
+    let projection = Mat4::perspective_rh(60f32.to_radians(), aspect, 0.1, 100.0);
+    let camera = Camera {
+        eye: Vec3A::from(eye),
+        vp: projection * view,
+    };
+
+    let config = DrawConfig {
+        min_rect: 0.5 / fb.px,
+        max_rect: 6.0 / fb.px,
+        max_test: 1024,
+        occlusion: false,
+    };
+
+    (camera, config)
+}
+
+/// everything a walk hands to its callback, in order
+fn walked<W>(walk: W) -> Vec<(bool, Payload, [f32; 4])>
+where
+    W: FnOnce(&mut dyn FnMut(bool, &Payload, Vec4) -> bool),
+{
+    let mut walked = Vec::new();
+    walk(&mut |is_leaf, data, voxel| {
+        walked.push((is_leaf, *data, voxel.to_array()));
+        true
+    });
+    walked
+}
+
+/// views from far and near, above and below, and from inside of the
+/// bounding cube
+const EYES: [[f32; 3]; 5] = [
+    [3.0, 2.0, 0.0],
+    [-2.0, 0.5, -2.5],
+    [0.3, -2.5, 1.0],
+    [1.2, 0.4, 1.1],
+    [0.6, 0.2, -0.9],
+];
+
+#[test]
+fn draws_packed_like_tree() {
+    let vb = bunny();
+    let packed = vb.to_packed();
+    for eye in EYES.iter() {
+        let mut expected = framebuffer();
+        let (camera, config) = camera(*eye, &expected);
+        vb.draw(&camera, &config, &mut expected);
+        assert!(expected.data.iter().any(|pixel| *pixel != 0));
+
+        let mut fb = framebuffer();
+        packed.draw(&camera, &config, &mut fb);
+        assert!(fb.data == expected.data, "{:?}", eye);
+    }
+}
+
+#[test]
+fn draws_parallel_on_any_number_of_threads() {
+    let vb = bunny();
+    for eye in EYES.iter() {
+        let mut expected = framebuffer();
+        let (camera, config) = camera(*eye, &expected);
+        vb.draw_parallel(&camera, &config, &mut expected, 1);
+        let drawn = expected.data.iter().filter(|pixel| **pixel != 0).count();
+        assert!(drawn > 0);
+
+        for threads in [3, 16].iter() {
+            let mut fb = framebuffer();
+            vb.draw_parallel(&camera, &config, &mut fb, *threads);
+            assert!(fb.data == expected.data, "{:?} on {}", eye, threads);
+        }
+
+        // draw() culls branches by their own splats, which misses a few
+        // pixels, and from inside of the bounding cube, many more
+        if eye.iter().any(|axis| axis.abs() > 1.0) {
+            let mut fb = framebuffer();
+            vb.draw(&camera, &config, &mut fb);
+            let differing = fb
+                .data
+                .iter()
+                .zip(expected.data.iter())
+                .filter(|(pixel, expected)| pixel != expected)
+                .count();
+            assert!(differing * 100 < drawn, "{:?}: {}", eye, differing);
+        }
+    }
+}
+
+#[test]
+fn draws_depth_of_voxel_center() {
+    // one voxel of the 2^1 grid, centered at (-0.5, -0.5, -0.5), seen
+    // head-on from 3.5 away
+    let mut builder = TreeBuilder::default();
+    let cell = Cell {
+        pos: UVec3::ZERO,
+        depth: 1,
+    };
+    let color = 0xff00ff00;
+    builder.insert(
+        cell,
+        Payload {
+            color,
+            occlusion: 0,
+        },
+    );
+    let vb = builder.build();
+
+    let mut fb = framebuffer();
+    let (camera, config) = camera_at([-0.5, -0.5, -4.0], [-0.5, -0.5, 0.0], &fb);
+
+    let mut depth = DepthBuffer {
+        width: fb.width,
+        height: fb.height,
+        px: fb.px,
+        data: vec![f32::INFINITY; fb.width * fb.height],
+    };
+    vb.draw_with_depth(&camera, &config, &mut fb, &mut depth);
+
+    // the whole splat takes the depth of the center, not of the front face
+    // half a voxel nearer
+    let (x, y) = (fb.width / 2, fb.height / 2);
+    assert_eq!(fb.data[y * fb.width + x], color);
+    let center = depth.depth_at(x, y).unwrap();
+    assert!((center - 3.5).abs() < 1e-4, "{}", center);
+    assert_eq!(depth.depth_at(x + 10, y - 10), Some(center));
+
+    assert_eq!(depth.depth_at(5, 5), None);
+    assert_eq!(depth.depth_at(fb.width - 5, y), None);
+    assert_eq!(depth.depth_at(fb.width, y), None);
+    assert_eq!(fb.data[5 * fb.width + 5], 0);
+}
+
+#[test]
+fn unpacks_to_same_tree() {
+    let vb = bunny();
+    let packed = vb.to_packed();
+    assert_eq!(packed.nodes().len(), vb.view().reachable_nodes());
+    let unpacked = packed.to_voxbuf();
+    assert_eq!(unpacked.bounds(), vb.bounds());
+
+    for eye in EYES.iter() {
+        let eye = Vec3A::from(*eye);
+        let expected = walked(|on_node| vb.walk(&eye, on_node));
+        assert_eq!(walked(|on_node| unpacked.walk(&eye, on_node)), expected);
+        assert_eq!(walked(|on_node| packed.walk(&eye, on_node)), expected);
+    }
+}
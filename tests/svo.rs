@@ -0,0 +1,230 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::Vec3A;
+use svo_cpu::binvox::import_binvox_svo;
+use svo_cpu::svo::{SvoError, HEADER_SIZE, NODE_SIZE};
+use svo_cpu::voxbuf::{VoxBuf, VoxBufView};
+
+fn save(vb: &VoxBuf) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    vb.save(&mut bytes).unwrap();
+    bytes
+}
+
+/// CRC-32 (IEEE 802.3), to forge valid checksums over corrupt payloads
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn forge_checksum(bytes: &mut [u8]) {
+    let mut checked = bytes[..44].to_vec();
+    checked.extend_from_slice(&bytes[HEADER_SIZE..]);
+    let crc = crc32(&checked);
+    bytes[44..48].copy_from_slice(&crc.to_le_bytes());
+}
+
+fn bunny() -> VoxBuf {
+    import_binvox_svo(include_bytes!("../examples/models/stanford_bunny.binvox"))
+}
+
+#[test]
+fn round_trips_bunny() {
+    let vb = bunny();
+    let bytes = save(&vb);
+    assert_eq!(&bytes[..8], b"svo-cpu\0");
+
+    let loaded = VoxBuf::load(&bytes[..]).unwrap();
+    assert_eq!(loaded.bounds(), vb.bounds());
+    assert_eq!(loaded.depth(), 9);
+
+    let eye = Vec3A::new(3.0, 2.0, 1.0);
+    let leaves = |vb: &VoxBuf| {
+        vb.walk_all(&eye)
+            .iter()
+            .map(|(data, voxel)| (data.color, voxel.to_array()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(leaves(&loaded), leaves(&vb));
+
+    // the nodes are already depth-sorted, so the bytes come back unchanged
+    assert!(save(&loaded) == bytes);
+}
+
+#[test]
+fn round_trips_dummy() {
+    let vb = VoxBuf::new_dummy();
+    let bytes = save(&vb);
+    assert_eq!(bytes.len(), HEADER_SIZE + 2 * NODE_SIZE);
+
+    let loaded = VoxBuf::load(&bytes[..]).unwrap();
+    assert_eq!(loaded.depth(), 1);
+    assert!(save(&loaded) == bytes);
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let mut bytes = save(&VoxBuf::new_dummy());
+    bytes[0] = b'S';
+    assert!(matches!(VoxBuf::load(&bytes[..]), Err(SvoError::BadMagic)));
+    assert!(matches!(
+        VoxBuf::load(&include_bytes!("../examples/models/stanford_bunny.binvox")[..]),
+        Err(SvoError::BadMagic)
+    ));
+}
+
+#[test]
+fn rejects_other_versions() {
+    let bytes = save(&VoxBuf::new_dummy());
+    for version in [0u32, 1, 3, u32::MAX].iter() {
+        let mut bytes = bytes.clone();
+        bytes[8..12].copy_from_slice(&version.to_le_bytes());
+        forge_checksum(&mut bytes);
+        match VoxBuf::load(&bytes[..]) {
+            Err(SvoError::UnsupportedVersion(v)) => assert_eq!(v, *version),
+            other => panic!("version {} gave {:?}", version, other.err()),
+        }
+    }
+}
+
+#[test]
+fn rejects_truncated() {
+    let bytes = save(&bunny());
+    let step = bytes.len() / 16;
+    for len in (0..HEADER_SIZE).chain((HEADER_SIZE..bytes.len()).step_by(step)) {
+        assert!(matches!(
+            VoxBuf::load(&bytes[..len]),
+            Err(SvoError::Truncated)
+        ));
+    }
+}
+
+#[test]
+fn rejects_corrupt_bytes() {
+    let bytes = save(&bunny());
+    let step = bytes.len() / 16;
+    for offset in (0..HEADER_SIZE).chain((HEADER_SIZE..bytes.len()).step_by(step)) {
+        let mut corrupt = bytes.clone();
+        corrupt[offset] ^= 0x10;
+        assert!(VoxBuf::load(&corrupt[..]).is_err());
+    }
+
+    // past the header checks, only the checksum can catch a flipped bit
+    let mut corrupt = bytes.clone();
+    corrupt[bytes.len() - 1] ^= 0x01;
+    assert!(matches!(
+        VoxBuf::load(&corrupt[..]),
+        Err(SvoError::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn rejects_invalid_structure() {
+    let bytes = save(&VoxBuf::new_dummy());
+    let child = HEADER_SIZE + 4;
+
+    // a child pointing back at its parent would loop forever
+    for target in [0u32, 2, u32::MAX].iter() {
+        let mut corrupt = bytes.clone();
+        corrupt[child..child + 4].copy_from_slice(&target.to_le_bytes());
+        forge_checksum(&mut corrupt);
+        assert!(matches!(
+            VoxBuf::load(&corrupt[..]),
+            Err(SvoError::InvalidNode(0))
+        ));
+    }
+
+    let mut corrupt = bytes.clone();
+    corrupt[24..28].copy_from_slice(&5u32.to_le_bytes());
+    forge_checksum(&mut corrupt);
+    assert!(matches!(
+        VoxBuf::load(&corrupt[..]),
+        Err(SvoError::InvalidDepth(5))
+    ));
+
+    let mut corrupt = bytes;
+    corrupt[16..24].copy_from_slice(&0u64.to_le_bytes());
+    forge_checksum(&mut corrupt);
+    assert!(matches!(
+        VoxBuf::load(&corrupt[..]),
+        Err(SvoError::InvalidNodeCount(0))
+    ));
+}
+
+/// copies bytes into storage aligned for Node, like a page-aligned mapping
+fn aligned(bytes: &[u8]) -> Vec<u32> {
+    let mut storage = vec![0u32; bytes.len().div_ceil(4) + 1];
+    unsafe {
+        let dst = storage.as_mut_ptr() as *mut u8;
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst, bytes.len());
+    }
+    storage
+}
+
+fn as_bytes(storage: &[u32], len: usize, offset: usize) -> &[u8] {
+    unsafe { std::slice::from_raw_parts((storage.as_ptr() as *const u8).add(offset), len) }
+}
+
+#[test]
+fn views_bunny_in_place() {
+    let vb = bunny();
+    let bytes = save(&vb);
+    let storage = aligned(&bytes);
+    let view = VoxBufView::from_bytes(as_bytes(&storage, bytes.len(), 0)).unwrap();
+
+    assert_eq!(view.bounds(), vb.bounds());
+    assert_eq!(view.depth(), 9);
+    assert_eq!(view.nodes().len(), vb.view().nodes().len());
+
+    let eye = Vec3A::new(3.0, 2.0, 1.0);
+    let leaves = |view: VoxBufView| {
+        view.walk_all(&eye)
+            .iter()
+            .map(|(data, voxel)| (data.color, voxel.to_array()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(leaves(view), leaves(vb.view()));
+
+    let unchecked = unsafe { VoxBufView::from_bytes_unchecked(as_bytes(&storage, bytes.len(), 0)) };
+    assert_eq!(leaves(unchecked.unwrap()), leaves(vb.view()));
+}
+
+#[test]
+fn rejects_unviewable() {
+    let bytes = save(&VoxBuf::new_dummy());
+    let mut shifted = vec![0];
+    shifted.extend_from_slice(&bytes);
+    let storage = aligned(&shifted);
+    assert!(matches!(
+        VoxBufView::from_bytes(as_bytes(&storage, bytes.len(), 1)),
+        Err(SvoError::NotMappable)
+    ));
+
+    let storage = aligned(&bytes);
+    for len in 0..bytes.len() {
+        assert!(matches!(
+            VoxBufView::from_bytes(as_bytes(&storage, len, 0)),
+            Err(SvoError::Truncated)
+        ));
+    }
+
+    let mut corrupt = bytes;
+    corrupt[HEADER_SIZE + 4] = 0;
+    forge_checksum(&mut corrupt);
+    let storage = aligned(&corrupt);
+    assert!(matches!(
+        VoxBufView::from_bytes(as_bytes(&storage, corrupt.len(), 0)),
+        Err(SvoError::InvalidNode(0))
+    ));
+}
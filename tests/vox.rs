@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::{UVec3, Vec3A};
+use std::collections::{HashMap, HashSet};
+use svo_cpu::vox::{export_vox, try_import_vox_svo, VoxError, MAX_SCENE_NODES};
+use svo_cpu::voxbuf::{Cell, Payload, TreeBuilder, VoxBuf};
+
+fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+    let mut bytes = id.to_vec();
+    bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(content);
+    bytes.extend_from_slice(children);
+    bytes
+}
+
+fn vox_file(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = b"VOX ".to_vec();
+    bytes.extend_from_slice(&150u32.to_le_bytes());
+    bytes.extend(chunk(b"MAIN", &[], &chunks.concat()));
+    bytes
+}
+
+fn words(words: &[i32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+fn string(s: &str) -> Vec<u8> {
+    let mut bytes = words(&[s.len() as i32]);
+    bytes.extend_from_slice(s.as_bytes());
+    bytes
+}
+
+/// a 1x1x1 model with its one voxel at (0, 0, 0)
+fn unit_model() -> Vec<Vec<u8>> {
+    vec![
+        chunk(b"SIZE", &words(&[1, 1, 1]), &[]),
+        chunk(b"XYZI", &[words(&[1]), vec![0, 0, 0, 1]].concat(), &[]),
+    ]
+}
+
+/// a transform node with one frame, translating by `t`
+fn transform(id: i32, child: i32, t: &str) -> Vec<u8> {
+    let frame = [words(&[1]), string("_t"), string(t)].concat();
+    chunk(
+        b"nTRN",
+        &[words(&[id, 0, child, -1, 0, 1]), frame].concat(),
+        &[],
+    )
+}
+
+fn group(id: i32, children: &[i32]) -> Vec<u8> {
+    let content = [words(&[id, 0, children.len() as i32]), words(children)].concat();
+    chunk(b"nGRP", &content, &[])
+}
+
+fn shape(id: i32, model: i32) -> Vec<u8> {
+    chunk(b"nSHP", &words(&[id, 0, 1, model, 0]), &[])
+}
+
+#[test]
+fn places_translated_models() {
+    let mut chunks = unit_model();
+    chunks.push(group(0, &[1, 2]));
+    chunks.push(transform(1, 3, "0 0 0"));
+    chunks.push(transform(2, 3, "3 0 0"));
+    chunks.push(shape(3, 0));
+
+    let vb = try_import_vox_svo(&vox_file(&chunks)).unwrap();
+    assert_eq!(vb.depth(), 2);
+    assert_eq!(vb.walk_all(&Vec3A::new(3.0, 2.0, 1.0)).len(), 2);
+}
+
+#[test]
+fn rejects_overflowing_translations() {
+    let cases = [
+        ("2147483647 0 0", "1 0 0"),
+        ("-2147483648 0 0", "-1 0 0"),
+        // in range, but flipping y for the octree overflows
+        ("0 -2147483648 0", "0 0 0"),
+    ];
+
+    for (outer, inner) in cases.iter() {
+        let mut chunks = unit_model();
+        chunks.push(transform(0, 1, outer));
+        chunks.push(transform(1, 2, inner));
+        chunks.push(shape(2, 0));
+        assert!(
+            matches!(
+                try_import_vox_svo(&vox_file(&chunks)),
+                Err(VoxError::TooLarge)
+            ),
+            "{} then {}",
+            outer,
+            inner
+        );
+    }
+}
+
+#[test]
+fn rejects_overflowing_extents() {
+    let mut chunks = unit_model();
+    chunks.push(group(0, &[1, 2]));
+    chunks.push(transform(1, 3, "-2147483000 0 0"));
+    chunks.push(transform(2, 3, "2147483000 0 0"));
+    chunks.push(shape(3, 0));
+    assert!(matches!(
+        try_import_vox_svo(&vox_file(&chunks)),
+        Err(VoxError::TooLarge)
+    ));
+}
+
+#[test]
+fn rejects_exponential_scene_graphs() {
+    // every group places the next one twice, for 2^levels placements
+    let levels = 24;
+    assert!(1 << levels > MAX_SCENE_NODES);
+
+    let mut chunks = unit_model();
+    for id in 0..levels {
+        chunks.push(group(id, &[id + 1, id + 1]));
+    }
+    chunks.push(shape(levels, 0));
+    assert!(matches!(
+        try_import_vox_svo(&vox_file(&chunks)),
+        Err(VoxError::InvalidSceneGraph)
+    ));
+
+    // the same sharing, kept under the limit, places every copy
+    let mut chunks = unit_model();
+    for id in 0..4 {
+        chunks.push(group(id, &[id + 1, id + 1]));
+    }
+    chunks.push(shape(4, 0));
+    assert!(try_import_vox_svo(&vox_file(&chunks)).is_ok());
+}
+
+/// a sparse 512^3 tree with voxels in many of its 256^3 tiles, colored from
+/// a 25x24 grid of reds and greens spaced 10 apart
+fn gradient_tree() -> (VoxBuf, HashMap<[u32; 3], u32>) {
+    let depth = 9;
+    let mut voxels = HashMap::new();
+    // the corners pin the imported bounds to the same grid
+    voxels.insert([0, 0, 0], 0xff000080);
+    voxels.insert([511, 511, 511], 0xfff0e680);
+    for i in 0..600u32 {
+        let pos = [(i * 97) % 512, (i * 389 + 7) % 512, (i * 173 + 300) % 512];
+        let color = 0xff000080 | ((i % 25) * 10) << 16 | ((i / 25) * 10) << 8;
+        voxels.insert(pos, color);
+    }
+
+    let mut builder = TreeBuilder::default();
+    for (pos, color) in voxels.iter() {
+        let cell = Cell {
+            pos: UVec3::from(*pos),
+            depth,
+        };
+        let data = Payload {
+            color: *color,
+            occlusion: 0,
+        };
+        builder.insert(cell, data);
+    }
+
+    (builder.build(), voxels)
+}
+
+#[test]
+fn round_trips_many_tiles_and_colors() {
+    let (vb, voxels) = gradient_tree();
+    let colors: HashSet<u32> = voxels.values().copied().collect();
+    assert!(colors.len() > 255);
+
+    let mut bytes = Vec::new();
+    export_vox(&vb, 9, &mut bytes).unwrap();
+    let loaded = try_import_vox_svo(&bytes).unwrap();
+    assert_eq!(loaded.depth(), 9);
+
+    let loaded = loaded.voxels(9);
+    let occupied = |voxels: &HashMap<[u32; 3], u32>| voxels.keys().copied().collect::<HashSet<_>>();
+    assert_eq!(occupied(&loaded), occupied(&voxels));
+
+    // median cut leaves each color within a step of the grid of its entry
+    for (pos, color) in voxels.iter() {
+        let found = loaded[pos];
+        assert_eq!(found >> 24, 0xff);
+        for shift in [16, 8, 0] {
+            let channel = |color: u32| ((color >> shift) & 0xff) as i32;
+            assert!(
+                (channel(found) - channel(*color)).abs() <= 10,
+                "{:08x} became {:08x}",
+                color,
+                found
+            );
+        }
+    }
+}
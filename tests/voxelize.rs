@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2021 Marceline Cramer
+
+use glam::Vec3A;
+use std::f32::consts::PI;
+use svo_cpu::mesh::{load_obj, Mesh};
+use svo_cpu::voxbuf::VoxBuf;
+use svo_cpu::voxelize::{voxelize, voxelize_solid};
+
+/// the filled volume, in voxels at `depth`, and the number of filled leaves
+/// shallower than `depth`
+fn volume(vb: &VoxBuf, depth: u32) -> (u64, usize) {
+    let mut volume = 0;
+    let mut coarse = 0;
+    vb.walk_cells(|_node_ref, node, cell| {
+        if node.is_leaf() {
+            if !node.data.is_empty() {
+                volume += 1 << (3 * (depth - cell.depth));
+                coarse += (cell.depth < depth) as usize;
+            }
+            false
+        } else {
+            true
+        }
+    });
+    (volume, coarse)
+}
+
+fn is_filled(vb: &VoxBuf, depth: u32, voxel: [u32; 3]) -> bool {
+    let mut filled = false;
+    vb.walk_cells(|_node_ref, node, cell| {
+        let scale = 1 << (depth - cell.depth);
+        let contains = (0..3).all(|axis| voxel[axis] / scale == cell.pos.to_array()[axis]);
+        if contains && node.is_leaf() {
+            filled = !node.data.is_empty();
+        }
+        contains
+    });
+    filled
+}
+
+/// a UV sphere of unit radius, with `rings` rings of quads around the y axis
+fn sphere(rings: u32) -> Mesh {
+    let mut mesh = Mesh::default();
+    let segments = 2 * rings;
+    for ring in 0..=rings {
+        let theta = PI * ring as f32 / rings as f32;
+        for segment in 0..segments {
+            let phi = PI * segment as f32 / rings as f32;
+            mesh.positions.push(Vec3A::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ));
+        }
+    }
+
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let a = ring * segments + segment;
+            let b = ring * segments + (segment + 1) % segments;
+            let (c, d) = (a + segments, b + segments);
+            mesh.triangles.push([a, b, d]);
+            mesh.triangles.push([a, d, c]);
+        }
+    }
+
+    mesh
+}
+
+/// the faces of an axis-aligned box, inward-facing if `inverted`
+fn push_box(mesh: &mut Mesh, min: Vec3A, max: Vec3A, inverted: bool) {
+    let base = mesh.positions.len() as u32;
+    for index in 0..8 {
+        mesh.positions.push(Vec3A::select(
+            glam::BVec3A::new(index & 1 != 0, index & 2 != 0, index & 4 != 0),
+            max,
+            min,
+        ));
+    }
+
+    let quads = [
+        [0, 2, 3, 1],
+        [4, 5, 7, 6],
+        [0, 1, 5, 4],
+        [2, 6, 7, 3],
+        [0, 4, 6, 2],
+        [1, 3, 7, 5],
+    ];
+
+    for [a, b, c, d] in quads.iter() {
+        let (mut first, mut second) = ([*a, *b, *c], [*a, *c, *d]);
+        if inverted {
+            first.swap(1, 2);
+            second.swap(1, 2);
+        }
+        mesh.triangles.push(first.map(|index| base + index));
+        mesh.triangles.push(second.map(|index| base + index));
+    }
+}
+
+#[test]
+fn voxelizes_obj_surfaces() {
+    let obj = "v 0 0 0 1 0 0\nv 1 0 0 1 0 0\nv 1 1 0 0 0 1\nv 0 1 0 0 0 1\n\
+               v 0 0 1 1 0 0\nv 1 0 1 1 0 0\nv 1 1 1 0 0 1\nv 0 1 1 0 0 1\n\
+               f -8 -7 -6 -5\nf 5 6 7 8\nf 1 2 6 5\nf 4 3 7 8\nf 1 4 8 5\nf 2 3 7 6\n";
+    let mesh = load_obj(obj.as_bytes()).unwrap();
+    assert_eq!(mesh.triangles.len(), 12);
+    assert_eq!(mesh.colors[0], 0xffff0000);
+
+    let vb = voxelize(&mesh, 4);
+    assert_eq!(volume(&vb, 4), (16 * 16 * 16 - 14 * 14 * 14, 0));
+    assert!(!is_filled(&vb, 4, [8, 8, 8]));
+
+    assert!(load_obj("v 0 0 0\nf 1 2 3\n".as_bytes()).is_err());
+    assert!(load_obj("v 0 0\n".as_bytes()).is_err());
+}
+
+#[test]
+fn voxelizes_nearest_surface_colors() {
+    // a unit square in the z = 0 plane, split along its diagonal into a red
+    // triangle below it and a blue one above
+    let mut mesh = Mesh::default();
+    for (corners, color) in [
+        ([[0, 0], [1, 0], [1, 1]], 0xffff0000u32),
+        ([[0, 0], [1, 1], [0, 1]], 0xff0000ff),
+    ] {
+        let base = mesh.positions.len() as u32;
+        for [x, y] in corners.iter() {
+            mesh.positions.push(Vec3A::new(*x as f32, *y as f32, 0.0));
+            mesh.colors.push(color);
+        }
+        mesh.triangles.push([base, base + 1, base + 2]);
+    }
+
+    let depth = 3;
+    let vb = voxelize(&mesh, depth);
+    let voxels = vb.voxels(depth);
+
+    // the square lies on the cube's bottom face, so only that layer is hit
+    assert_eq!(voxels.len(), 64);
+    for ([x, y, z], color) in voxels.iter() {
+        assert_eq!(*z, 0);
+        if x > y {
+            assert_eq!(*color, 0xffff0000, "{} {}", x, y);
+        } else if x < y {
+            assert_eq!(*color, 0xff0000ff, "{} {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn skips_non_finite_triangles() {
+    let mut mesh = Mesh::default();
+    push_box(&mut mesh, Vec3A::ZERO, Vec3A::ONE, false);
+    let expected = voxelize(&mesh, 4).voxels(4);
+
+    for bad in [f32::NAN, f32::INFINITY] {
+        let mut mesh = mesh.clone();
+        let base = mesh.positions.len() as u32;
+        mesh.positions.push(Vec3A::new(bad, 0.5, 0.5));
+        mesh.positions.push(Vec3A::new(0.5, 0.5, 0.5));
+        mesh.positions.push(Vec3A::new(0.5, 0.6, 0.5));
+        mesh.triangles.push([base, base + 1, base + 2]);
+        assert_eq!(voxelize(&mesh, 4).voxels(4), expected);
+    }
+}
+
+#[test]
+fn fills_cube() {
+    let mut mesh = Mesh::default();
+    push_box(&mut mesh, Vec3A::ZERO, Vec3A::ONE, false);
+
+    for depth in 1..6 {
+        let vb = voxelize_solid(&mesh, depth);
+        let (volume, coarse) = volume(&vb, depth);
+        assert_eq!(volume, 1 << (3 * depth));
+        if depth > 2 {
+            // the interior is kept at coarse levels
+            assert!(coarse > 0);
+        }
+    }
+}
+
+#[test]
+fn fills_hollow_box() {
+    let mut mesh = Mesh::default();
+    push_box(&mut mesh, Vec3A::ZERO, Vec3A::splat(4.0), false);
+    push_box(&mut mesh, Vec3A::ONE, Vec3A::splat(3.0), true);
+
+    // voxels on either side of the cavity's faces touch its surface
+    let vb = voxelize_solid(&mesh, 4);
+    assert_eq!(volume(&vb, 4).0, 16 * 16 * 16 - 6 * 6 * 6);
+    assert!(!is_filled(&vb, 4, [8, 8, 8]));
+    assert!(is_filled(&vb, 4, [2, 8, 8]));
+}
+
+/// surface voxels are kept whole, so the volume is overestimated by about
+/// half a voxel over the sphere's surface; the error stays below 2.5 voxels
+/// over the radius, and below 2% from a radius of 128 voxels
+#[test]
+fn fills_sphere() {
+    let mesh = sphere(64);
+    for depth in 4..9 {
+        let radius = (1 << (depth - 1)) as f32;
+        let expected = 4.0 / 3.0 * PI * radius.powi(3);
+
+        let vb = voxelize_solid(&mesh, depth);
+        let (volume, coarse) = volume(&vb, depth);
+        let error = (volume as f32 - expected).abs() / expected;
+        assert!(
+            error < 2.5 / radius,
+            "depth {} volume error {}",
+            depth,
+            error
+        );
+        assert!(coarse > 0);
+
+        let center = 1 << (depth - 1);
+        assert!(is_filled(&vb, depth, [center; 3]));
+        assert!(!is_filled(&vb, depth, [0, 0, 0]));
+
+        if depth == 8 {
+            assert!(error < 0.02);
+        }
+    }
+}